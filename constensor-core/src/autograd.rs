@@ -0,0 +1,393 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    device::Dev,
+    graph::{BinaryOpType, GraphTensorId, IndexMode, Op, ReduceOpType, UnaryOpType},
+    tensor::{contiguous_strides, is_contiguous_strides},
+    DType, Graph, GraphTensor, Result, Shape,
+};
+
+/// The gradients produced by [`Graph::backward`], one node per leaf the output depends on.
+pub struct Gradients<T: DType> {
+    graph: Graph<T>,
+    grads: HashMap<usize, GraphTensorId>,
+}
+
+impl<T: DType> Gradients<T> {
+    /// The gradient of the output with respect to `leaf`, or `None` if the output does not
+    /// depend on it.
+    pub fn get<S: Shape, D: Dev>(
+        &self,
+        leaf: &GraphTensor<S, T, D>,
+    ) -> Option<GraphTensor<S, T, D>> {
+        let id = self.id(&leaf.id())?;
+        let strides = self.graph.get_ops()[id.get()].strides.clone();
+        Some(GraphTensor::from_parts(
+            id,
+            Arc::new(RwLock::new(self.graph.clone())),
+            strides,
+        ))
+    }
+
+    /// The id of the gradient node for the leaf `id`, e.g. to pass to [`Graph::compile_multi`].
+    pub fn id(&self, id: &GraphTensorId) -> Option<GraphTensorId> {
+        self.grads.get(&id.get()).cloned()
+    }
+}
+
+impl<T: DType> Graph<T> {
+    /// Append the reverse-mode gradient of `output` to the graph, seeded with ones.
+    ///
+    /// Every node without inputs that `output` depends on (fills, aranges, random tensors,
+    /// constants) gets a gradient node, which has the leaf's shape. Supported ops are add, sub,
+    /// mul, neg, sqrt, fused multiply-add, matmul, sum reductions, clamps, masked fills,
+    /// broadcasts, rolls, flips and transposing or reshaping views; backpropagating through
+    /// anything else is an error.
+    ///
+    /// The gradient nodes read forward values, so in-place flags from an earlier
+    /// [`Graph::optimize`] are cleared. The gradients are not the last node of the graph, so use
+    /// [`Graph::compile_multi`] to run them; [`Graph::optimize`] would remove all but one.
+    pub fn backward(&mut self, output: &GraphTensorId) -> Result<Gradients<T>> {
+        let out = output.get();
+        let n = self.get_ops().len();
+        if out >= n {
+            crate::bail!("backward output {out} is not a node of a graph with {n} nodes");
+        }
+        self.clear_inplace();
+        let nodes = self.get_ops()[..=out].to_vec();
+
+        let mut grads: Vec<Option<GraphTensorId>> = vec![None; out + 1];
+        grads[out] = Some(self.grad_node(Op::Fill { v: T::ONE }, nodes[out].shape.clone()));
+        for (i, node) in nodes.iter().enumerate().rev() {
+            let Some(g) = grads[i].clone() else {
+                continue;
+            };
+            let value = GraphTensorId::out_of_place(i);
+            let mut contributions = Vec::new();
+            match &node.op {
+                op if op.input_ids().is_empty() => {}
+                Op::BinaryOp {
+                    l_id,
+                    r_id,
+                    operator: BinaryOpType::Add,
+                } => {
+                    contributions.push((l_id.get(), g.clone()));
+                    contributions.push((r_id.get(), g));
+                }
+                Op::BinaryOp {
+                    l_id,
+                    r_id,
+                    operator: BinaryOpType::Sub,
+                } => {
+                    let neg = self.grad_unary(&g, UnaryOpType::Neg, &node.shape);
+                    contributions.push((l_id.get(), g));
+                    contributions.push((r_id.get(), neg));
+                }
+                Op::BinaryOp {
+                    l_id,
+                    r_id,
+                    operator: BinaryOpType::Mul,
+                } => {
+                    let dl = self.grad_binary(&g, r_id, BinaryOpType::Mul, &node.shape);
+                    let dr = self.grad_binary(&g, l_id, BinaryOpType::Mul, &node.shape);
+                    contributions.push((l_id.get(), dl));
+                    contributions.push((r_id.get(), dr));
+                }
+                Op::UnaryOp {
+                    v_id,
+                    operator: UnaryOpType::Neg,
+                } => {
+                    let neg = self.grad_unary(&g, UnaryOpType::Neg, &node.shape);
+                    contributions.push((v_id.get(), neg));
+                }
+                Op::UnaryOp {
+                    v_id,
+                    operator: UnaryOpType::Sqrt,
+                } => {
+                    // d sqrt(v) = 1 / (2 sqrt(v))
+                    let two = self.grad_node(Op::Fill { v: T::ONE + T::ONE }, node.shape.clone());
+                    let denom = self.grad_binary(&two, &value, BinaryOpType::Mul, &node.shape);
+                    let dv = self.grad_binary(&g, &denom, BinaryOpType::Div, &node.shape);
+                    contributions.push((v_id.get(), dv));
+                }
+                Op::FusedMulAdd { a_id, b_id, c_id } => {
+                    let da = self.grad_binary(&g, b_id, BinaryOpType::Mul, &node.shape);
+                    let db = self.grad_binary(&g, a_id, BinaryOpType::Mul, &node.shape);
+                    contributions.push((a_id.get(), da));
+                    contributions.push((b_id.get(), db));
+                    contributions.push((c_id.get(), g));
+                }
+                Op::MatMul {
+                    l_id,
+                    r_id,
+                    o_id,
+                    k,
+                    alpha,
+                    beta,
+                } => {
+                    let (b, m, n) = (node.shape[0], node.shape[1], node.shape[2]);
+                    if nodes[l_id.get()].shape[1] != m || nodes[r_id.get()].shape[2] != n {
+                        crate::bail!(
+                            "backward is not supported through a matmul into a larger output"
+                        )
+                    }
+                    let l_t = self.grad_transpose(l_id, &nodes[l_id.get()].strides, [b, *k, m]);
+                    let r_t = self.grad_transpose(r_id, &nodes[r_id.get()].strides, [b, n, *k]);
+                    let dl = self.grad_node(
+                        Op::MatMul {
+                            l_id: g.clone(),
+                            r_id: r_t,
+                            o_id: None,
+                            k: n,
+                            alpha: T::ZERO,
+                            beta: *beta,
+                        },
+                        vec![b, m, *k],
+                    );
+                    let dr = self.grad_node(
+                        Op::MatMul {
+                            l_id: l_t,
+                            r_id: g.clone(),
+                            o_id: None,
+                            k: m,
+                            alpha: T::ZERO,
+                            beta: *beta,
+                        },
+                        vec![b, *k, n],
+                    );
+                    contributions.push((l_id.get(), dl));
+                    contributions.push((r_id.get(), dr));
+                    if let Some(o_id) = o_id {
+                        let scale = self.grad_node(Op::Fill { v: *alpha }, node.shape.clone());
+                        let d_o = self.grad_binary(&g, &scale, BinaryOpType::Mul, &node.shape);
+                        contributions.push((o_id.get(), d_o));
+                    }
+                }
+                Op::Reduce {
+                    v_id,
+                    axis,
+                    operator: ReduceOpType::Sum,
+                } => {
+                    // Every entry along the axis receives the gradient of its sum.
+                    let shape = nodes[v_id.get()].shape.clone();
+                    let zeros = self.grad_node(Op::Fill { v: T::ZERO }, shape.clone());
+                    let dv = self.grad_node(
+                        Op::Gather {
+                            v_id: g,
+                            idx_id: zeros,
+                            axis: *axis,
+                            mode: IndexMode::Clamp,
+                        },
+                        shape,
+                    );
+                    contributions.push((v_id.get(), dv));
+                }
+                Op::Permute { v_id } => {
+                    let v = &nodes[v_id.get()];
+                    let Some(strides) = view_grad_strides(v, node) else {
+                        crate::bail!(
+                            "backward does not support a view from {:?} (strides {:?}) to {:?} (strides {:?})",
+                            v.shape,
+                            v.strides,
+                            node.shape,
+                            node.strides
+                        );
+                    };
+                    let dv = self.next_id();
+                    self.add_op_with_shape(Op::Permute { v_id: g }, v.shape.clone(), &strides, &dv);
+                    contributions.push((v_id.get(), dv));
+                }
+                Op::Contiguous { v_id } => contributions.push((v_id.get(), g)),
+                Op::Clamp { v_id, .. } => {
+                    // `v - clamp(v)` is nonzero exactly where `v` was outside `[min, max]`,
+                    // which is where the gradient is zeroed.
+                    let moved = self.grad_binary(v_id, &value, BinaryOpType::Sub, &node.shape);
+                    let dv = self.grad_node(
+                        Op::MaskedFill {
+                            v_id: g,
+                            mask_id: moved,
+                            value: T::ZERO,
+                        },
+                        node.shape.clone(),
+                    );
+                    contributions.push((v_id.get(), dv));
+                }
+                Op::MaskedFill { v_id, mask_id, .. } => {
+                    // Filled entries do not depend on `v`, and the mask gets no gradient.
+                    let dv = self.grad_node(
+                        Op::MaskedFill {
+                            v_id: g,
+                            mask_id: mask_id.clone(),
+                            value: T::ZERO,
+                        },
+                        node.shape.clone(),
+                    );
+                    contributions.push((v_id.get(), dv));
+                }
+                Op::Broadcast { v_id } => {
+                    // Sum the gradient over every repeated axis.
+                    let mut shape = node.shape.clone();
+                    let mut dv = g;
+                    for (axis, &dim) in nodes[v_id.get()].shape.iter().enumerate() {
+                        if dim != shape[axis] {
+                            shape[axis] = 1;
+                            dv = self.grad_node(
+                                Op::Reduce {
+                                    v_id: dv,
+                                    axis,
+                                    operator: ReduceOpType::Sum,
+                                },
+                                shape.clone(),
+                            );
+                        }
+                    }
+                    contributions.push((v_id.get(), dv));
+                }
+                Op::Roll { v_id, shift, axis } => {
+                    let dv = self.grad_node(
+                        Op::Roll {
+                            v_id: g,
+                            shift: -shift,
+                            axis: *axis,
+                        },
+                        node.shape.clone(),
+                    );
+                    contributions.push((v_id.get(), dv));
+                }
+                Op::Flip { v_id, axis } => {
+                    let dv = self.grad_node(
+                        Op::Flip {
+                            v_id: g,
+                            axis: *axis,
+                        },
+                        node.shape.clone(),
+                    );
+                    contributions.push((v_id.get(), dv));
+                }
+                Op::BinaryOp { operator, .. } => {
+                    crate::bail!("backward is not supported through {operator:?}")
+                }
+                Op::UnaryOp { operator, .. } => {
+                    crate::bail!("backward is not supported through {operator:?}")
+                }
+                Op::Reduce { operator, .. } => {
+                    crate::bail!("backward is not supported through {operator:?} reductions")
+                }
+                op => crate::bail!("backward is not supported through {}", op.name()),
+            }
+
+            for (input, contribution) in contributions {
+                let shape = nodes[input].shape.clone();
+                grads[input] = Some(match grads[input].take() {
+                    Some(prev) => self.grad_binary(&prev, &contribution, BinaryOpType::Add, &shape),
+                    None => contribution,
+                });
+            }
+        }
+
+        let grads = nodes
+            .iter()
+            .zip(grads)
+            .enumerate()
+            .filter(|(_, (node, _))| node.op.input_ids().is_empty())
+            .filter_map(|(i, (_, grad))| Some((i, grad?)))
+            .collect();
+        Ok(Gradients {
+            graph: self.clone(),
+            grads,
+        })
+    }
+
+    /// Append a contiguous gradient node.
+    fn grad_node(&mut self, op: Op<T>, shape: Vec<usize>) -> GraphTensorId {
+        let id = self.next_id();
+        let strides = contiguous_strides(&shape);
+        self.add_op_with_shape(op, shape, &strides, &id);
+        id
+    }
+
+    fn grad_unary(
+        &mut self,
+        v: &GraphTensorId,
+        operator: UnaryOpType,
+        shape: &[usize],
+    ) -> GraphTensorId {
+        self.grad_node(
+            Op::UnaryOp {
+                v_id: v.clone(),
+                operator,
+            },
+            shape.to_vec(),
+        )
+    }
+
+    fn grad_binary(
+        &mut self,
+        l: &GraphTensorId,
+        r: &GraphTensorId,
+        operator: BinaryOpType,
+        shape: &[usize],
+    ) -> GraphTensorId {
+        self.grad_node(
+            Op::BinaryOp {
+                l_id: l.clone(),
+                r_id: r.clone(),
+                operator,
+            },
+            shape.to_vec(),
+        )
+    }
+
+    /// A view of the batched matrix `v` with its last two axes swapped, giving `shape`.
+    fn grad_transpose(
+        &mut self,
+        v: &GraphTensorId,
+        strides: &[usize],
+        shape: [usize; 3],
+    ) -> GraphTensorId {
+        let id = self.next_id();
+        self.add_op_with_shape(
+            Op::Permute { v_id: v.clone() },
+            shape.to_vec(),
+            &[strides[0], strides[2], strides[1]],
+            &id,
+        );
+        id
+    }
+}
+
+/// The strides of a view of the contiguous gradient of `view` that has the shape of its input
+/// `v`, if `view` only reshapes a contiguous `v` or reorders its axes.
+fn view_grad_strides<T: DType>(
+    v: &crate::GraphNode<T>,
+    view: &crate::GraphNode<T>,
+) -> Option<Vec<usize>> {
+    if is_contiguous_strides(&v.shape, &v.strides)
+        && is_contiguous_strides(&view.shape, &view.strides)
+    {
+        return Some(contiguous_strides(&v.shape));
+    }
+    // Match each axis of `v` to the view axis that walks the same buffer positions.
+    let grad_strides = contiguous_strides(&view.shape);
+    let mut used = vec![false; view.shape.len()];
+    let mut strides = Vec::with_capacity(v.shape.len());
+    for (&dim, &stride) in v.shape.iter().zip(&v.strides) {
+        if dim == 1 {
+            strides.push(0);
+            continue;
+        }
+        let axis = (0..view.shape.len())
+            .find(|&j| !used[j] && view.shape[j] == dim && view.strides[j] == stride)?;
+        used[axis] = true;
+        strides.push(grad_strides[axis]);
+    }
+    let covered = view
+        .shape
+        .iter()
+        .zip(&used)
+        .all(|(&dim, &used)| used || dim == 1);
+    covered.then_some(strides)
+}
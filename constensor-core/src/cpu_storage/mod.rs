@@ -2,27 +2,44 @@ use petgraph::algo::toposort;
 use petgraph::graphmap::DiGraphMap;
 use std::{borrow::Cow, marker::PhantomData};
 
+pub(crate) use pool::SharedPool;
 use pool::{BufferPool, PooledBuffer};
-use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
+use rayon::iter::{
+    IndexedParallelIterator, IntoParallelIterator, IntoParallelRefMutIterator, ParallelIterator,
+};
 
 use crate::device::Dev;
 use crate::storage::Storage;
-use crate::tensor::contiguous_strides;
+use crate::tensor::{contiguous_strides, is_contiguous_strides};
 use crate::Shape;
 use crate::{
     storage::{BackendDevice, BackendStorage},
-    CompiledGraph, DType, GraphNode, Op, Result,
+    ArgReduceType, CompiledGraph, Context, DType, GraphNode, IndexMode, NodeProfile, Op,
+    ProfileReport, ReduceMode, ReduceOpType, Result, RunStats,
 };
 use rand::Rng;
 use rand_distr::{Distribution, Normal};
 
+#[cfg(feature = "numa")]
+mod numa;
 mod pool;
 // Concurrency primitives for dynamic DAG scheduler
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 
 pub struct CpuDevice;
 
+/// Settings for a single run, shared by the evaluation of every node.
+#[derive(Clone, Default)]
+struct EvalOptions {
+    reduce_mode: ReduceMode,
+    /// Fail at the first node that outputs a NaN or infinity.
+    check_finite: bool,
+    /// Nanoseconds spent computing each node, recorded only when profiling.
+    timings: Option<Arc<Vec<AtomicU64>>>,
+}
+
 #[derive(Clone)]
 pub struct CpuStorage<T: DType>(pub(crate) Vec<T>);
 
@@ -51,33 +68,8 @@ impl BackendDevice for CpuDevice {
 
         for node in graph.iter() {
             let idx = node.id.get();
-            match &node.op {
-                Op::BinaryOp { l_id, r_id, .. } => {
-                    dep_graph.add_edge(l_id.get(), idx, ());
-                    dep_graph.add_edge(r_id.get(), idx, ());
-                }
-                Op::UnaryOp { v_id, .. } => {
-                    dep_graph.add_edge(v_id.get(), idx, ());
-                }
-                Op::FusedMulAdd { a_id, b_id, c_id } => {
-                    dep_graph.add_edge(a_id.get(), idx, ());
-                    dep_graph.add_edge(b_id.get(), idx, ());
-                    dep_graph.add_edge(c_id.get(), idx, ());
-                }
-                Op::MatMul {
-                    l_id, r_id, o_id, ..
-                } => {
-                    dep_graph.add_edge(l_id.get(), idx, ());
-                    dep_graph.add_edge(r_id.get(), idx, ());
-                    if let Some(o_id) = o_id {
-                        dep_graph.add_edge(o_id.get(), idx, ());
-                    }
-                }
-                Op::Permute { v_id } => {
-                    dep_graph.add_edge(v_id.get(), idx, ());
-                }
-                // NoOp, Fill/Arange, Rand/Randn don’t create incoming edges
-                Op::NoOp | Op::Fill { .. } | Op::Arange { .. } | Op::Rand | Op::Randn { .. } => {}
+            for src in node.op.input_ids() {
+                dep_graph.add_edge(src.get(), idx, ());
             }
         }
 
@@ -87,6 +79,10 @@ impl BackendDevice for CpuDevice {
         Ok(CompiledGraph::Cpu {
             order,
             graph,
+            pool: BufferPool::shared(),
+            threads: None,
+            reduce_mode: ReduceMode::default(),
+            check_finite: false,
             ghost: PhantomData,
         })
     }
@@ -95,22 +91,347 @@ impl BackendDevice for CpuDevice {
         &self,
         graph: &CompiledGraph<S, T, D>,
     ) -> Result<Self::Storage<T>> {
-        use std::sync::atomic::{AtomicUsize, Ordering};
-        use std::sync::{mpsc, Arc, Mutex, RwLock};
+        // Extract the compiled node list
+        #[allow(irrefutable_let_patterns)]
+        let CompiledGraph::Cpu {
+            graph: node_graph,
+            pool,
+            threads,
+            reduce_mode,
+            check_finite,
+            ..
+        } = graph
+        else {
+            unreachable!("Expected CPU compiled graph");
+        };
+        let final_idx = node_graph
+            .len()
+            .checked_sub(1)
+            .ok_or(crate::Error::EmptyGraph)?;
+        let (mut outputs, _) = self.run_nodes_tracked(
+            node_graph,
+            &[final_idx],
+            pool,
+            threads.as_deref(),
+            EvalOptions {
+                reduce_mode: *reduce_mode,
+                check_finite: *check_finite,
+                timings: None,
+            },
+        )?;
+        Ok(outputs.remove(0))
+    }
+}
 
-        // Thread-safe buffer pool
-        let pool: Arc<Mutex<BufferPool<T>>> = Arc::new(Mutex::new(BufferPool::new()));
+impl CpuDevice {
+    /// Like [`BackendDevice::run_graph`], but also reports how the buffer pool was used.
+    pub(crate) fn run_graph_tracked<S: Shape, T: DType + Send + Sync + 'static, D: Dev>(
+        &self,
+        graph: &CompiledGraph<S, T, D>,
+    ) -> Result<(CpuStorage<T>, RunStats)> {
+        #[allow(irrefutable_let_patterns)]
+        let CompiledGraph::Cpu {
+            graph: node_graph,
+            pool,
+            threads,
+            reduce_mode,
+            check_finite,
+            ..
+        } = graph
+        else {
+            unreachable!("Expected CPU compiled graph");
+        };
+        let final_idx = node_graph
+            .len()
+            .checked_sub(1)
+            .ok_or(crate::Error::EmptyGraph)?;
+        let (mut outputs, stats) = self.run_nodes_tracked(
+            node_graph,
+            &[final_idx],
+            pool,
+            threads.as_deref(),
+            EvalOptions {
+                reduce_mode: *reduce_mode,
+                check_finite: *check_finite,
+                timings: None,
+            },
+        )?;
+        Ok((outputs.remove(0), stats))
+    }
 
-        // Extract the compiled node list
+    /// Like [`BackendDevice::run_graph`], but copies the result into `out`. The run's own output
+    /// buffer goes back to the pool, so repeated runs into the same storage do not allocate.
+    pub(crate) fn run_graph_into<S: Shape, T: DType + Send + Sync + 'static, D: Dev>(
+        &self,
+        graph: &CompiledGraph<S, T, D>,
+        out: &mut CpuStorage<T>,
+    ) -> Result<()> {
+        let result = self.run_graph(graph)?;
+        out.0.clear();
+        out.0.extend_from_slice(&result.0);
+        #[allow(irrefutable_let_patterns)]
+        if let CompiledGraph::Cpu { pool, .. } = graph {
+            pool.lock().unwrap().recycle_buffer(result.0);
+        }
+        Ok(())
+    }
+
+    /// Like [`BackendDevice::run_graph`], but evaluates `tile_elems` output elements at a time.
+    /// Every node must be elementwise over the output's elements, so each tile only needs the
+    /// same range of every intermediate.
+    pub(crate) fn run_graph_tiled<S: Shape, T: DType + Send + Sync + 'static, D: Dev>(
+        &self,
+        graph: &CompiledGraph<S, T, D>,
+        tile_elems: usize,
+    ) -> Result<CpuStorage<T>> {
         #[allow(irrefutable_let_patterns)]
         let CompiledGraph::Cpu {
-            graph: node_graph, ..
+            graph: node_graph,
+            pool,
+            threads,
+            reduce_mode,
+            check_finite,
+            ..
         } = graph
         else {
             unreachable!("Expected CPU compiled graph");
         };
+        let final_idx = node_graph
+            .len()
+            .checked_sub(1)
+            .ok_or(crate::Error::EmptyGraph)?;
+        if tile_elems == 0 {
+            return Err(crate::Error::InvalidArgument(
+                "run_tiled needs tiles of at least one element".to_string(),
+            ));
+        }
+        let n = S::element_count();
+        for node in node_graph {
+            match node.op {
+                Op::NoOp => continue,
+                Op::Fill { .. }
+                | Op::Arange { .. }
+                | Op::Const { .. }
+                | Op::Rand
+                | Op::Randn { .. }
+                | Op::BinaryOp { .. }
+                | Op::UnaryOp { .. }
+                | Op::CustomUnary { .. }
+                | Op::FusedMulAdd { .. }
+                | Op::NanToNum { .. }
+                | Op::Clamp { .. }
+                | Op::MaskedFill { .. } => {}
+                ref op => crate::bail!(
+                    "run_tiled only supports elementwise graphs, got {}",
+                    op.name()
+                ),
+            }
+            if node.shape.iter().product::<usize>() != n
+                || !is_contiguous_strides(&node.shape, &node.strides)
+            {
+                crate::bail!(
+                    "run_tiled needs every node to be a contiguous {:?} tensor, got {:?} with strides {:?}",
+                    S::shape(),
+                    node.shape,
+                    node.strides
+                );
+            }
+        }
+
+        let mut out = Vec::with_capacity(n);
+        for start in (0..n).step_by(tile_elems) {
+            let len = tile_elems.min(n - start);
+            let tile = node_graph
+                .iter()
+                .map(|node| {
+                    let op = match &node.op {
+                        // Materialize the tile's entries with the same index-based formula.
+                        Op::Arange { start: a, step, .. } => {
+                            let a = a.to_f64();
+                            let data = (start..start + len)
+                                .map(|i| T::from_f64(a + i as f64 * step))
+                                .collect();
+                            Op::Const {
+                                data: Arc::new(data),
+                            }
+                        }
+                        Op::Const { data } => Op::Const {
+                            data: Arc::new(data[start..start + len].to_vec()),
+                        },
+                        op => op.clone(),
+                    };
+                    GraphNode {
+                        op,
+                        shape: vec![len],
+                        strides: vec![1],
+                        id: node.id.clone(),
+                    }
+                })
+                .collect::<Vec<_>>();
+            let (mut outputs, _) = self.run_nodes_tracked(
+                &tile,
+                &[final_idx],
+                pool,
+                threads.as_deref(),
+                EvalOptions {
+                    reduce_mode: *reduce_mode,
+                    check_finite: *check_finite,
+                    timings: None,
+                },
+            )?;
+            let result = outputs.remove(0);
+            out.extend_from_slice(&result.0);
+            pool.lock().unwrap().recycle_buffer(result.0);
+        }
+        Ok(CpuStorage(out))
+    }
+
+    /// Like [`BackendDevice::run_graph`], but also measures the time spent computing each node.
+    pub(crate) fn run_graph_profiled<S: Shape, T: DType + Send + Sync + 'static, D: Dev>(
+        &self,
+        graph: &CompiledGraph<S, T, D>,
+    ) -> Result<(CpuStorage<T>, ProfileReport)> {
+        #[allow(irrefutable_let_patterns)]
+        let CompiledGraph::Cpu {
+            graph: node_graph,
+            pool,
+            threads,
+            reduce_mode,
+            check_finite,
+            ..
+        } = graph
+        else {
+            unreachable!("Expected CPU compiled graph");
+        };
+        let final_idx = node_graph
+            .len()
+            .checked_sub(1)
+            .ok_or(crate::Error::EmptyGraph)?;
+        let timings: Arc<Vec<_>> =
+            Arc::new((0..node_graph.len()).map(|_| AtomicU64::new(0)).collect());
+        let (mut outputs, _) = self.run_nodes_tracked(
+            node_graph,
+            &[final_idx],
+            pool,
+            threads.as_deref(),
+            EvalOptions {
+                reduce_mode: *reduce_mode,
+                check_finite: *check_finite,
+                timings: Some(timings.clone()),
+            },
+        )?;
+        let nodes = timings
+            .iter()
+            .enumerate()
+            .map(|(node, nanos)| NodeProfile {
+                node,
+                elapsed: Duration::from_nanos(nanos.load(Ordering::Relaxed)),
+            })
+            .collect();
+        Ok((outputs.remove(0), ProfileReport { nodes }))
+    }
+
+    /// Evaluate the graph until every node in `outputs` is computed, returning their buffers in that order.
+    pub(crate) fn run_nodes<T: DType + Send + Sync + 'static>(
+        &self,
+        node_graph: &[GraphNode<T>],
+        outputs: &[usize],
+    ) -> Result<Vec<CpuStorage<T>>> {
+        let pool = Arc::new(Mutex::new(BufferPool::new()));
+        Ok(self
+            .run_nodes_tracked(node_graph, outputs, &pool, None, EvalOptions::default())?
+            .0)
+    }
+
+    /// Give `graph` a dedicated thread pool of `n` threads for its evaluation.
+    pub(crate) fn with_threads<S: Shape, T: DType, D: Dev>(
+        &self,
+        mut graph: CompiledGraph<S, T, D>,
+        n: usize,
+    ) -> Result<CompiledGraph<S, T, D>> {
+        match &mut graph {
+            CompiledGraph::Cpu { threads, .. } => {
+                let builder = rayon::ThreadPoolBuilder::new().num_threads(n);
+                #[cfg(feature = "numa")]
+                let builder = builder.start_handler(numa::pin_current_thread);
+                let pool = builder
+                    .build()
+                    .with_context(|| format!("failed to build a thread pool of {n} threads"))?;
+                *threads = Some(Arc::new(pool));
+            }
+            #[cfg(feature = "cuda")]
+            CompiledGraph::Cuda { .. } => {
+                return Err(crate::Error::UnsupportedOp {
+                    op: "Thread counts",
+                    backend: "CUDA",
+                })
+            }
+        }
+        Ok(graph)
+    }
+
+    /// Make `graph` fail as soon as a node outputs a NaN or infinity, if `enabled`.
+    pub(crate) fn with_check_finite<S: Shape, T: DType, D: Dev>(
+        &self,
+        mut graph: CompiledGraph<S, T, D>,
+        enabled: bool,
+    ) -> CompiledGraph<S, T, D> {
+        match &mut graph {
+            CompiledGraph::Cpu { check_finite, .. } => *check_finite = enabled,
+            #[cfg(feature = "cuda")]
+            CompiledGraph::Cuda { .. } => {}
+        }
+        graph
+    }
+
+    /// Make `graph` accumulate its reductions according to `mode`.
+    pub(crate) fn with_reduce_mode<S: Shape, T: DType, D: Dev>(
+        &self,
+        mut graph: CompiledGraph<S, T, D>,
+        mode: ReduceMode,
+    ) -> CompiledGraph<S, T, D> {
+        match &mut graph {
+            CompiledGraph::Cpu { reduce_mode, .. } => *reduce_mode = mode,
+            #[cfg(feature = "cuda")]
+            CompiledGraph::Cuda { .. } => {}
+        }
+        graph
+    }
+
+    /// Total capacity of the buffers the compiled graphs keep cached between runs, in bytes.
+    pub(crate) fn pool_capacity_bytes(&self) -> usize {
+        pool::shared_capacity_bytes()
+    }
+
+    /// Drop the buffers the compiled graphs keep cached between runs.
+    pub(crate) fn clear_pool(&self) {
+        pool::clear_shared()
+    }
+
+    /// Cap the buffer pools of the graphs compiled from now on at `bytes`.
+    pub(crate) fn set_pool_max_bytes(&self, bytes: usize) {
+        pool::set_max_size(bytes)
+    }
+
+    /// [`CpuDevice::run_nodes`] drawing buffers from `pool`, also returning the pool usage of the run.
+    ///
+    /// Nodes run on `threads`, or the global rayon pool if it is `None`.
+    fn run_nodes_tracked<T: DType + Send + Sync + 'static>(
+        &self,
+        node_graph: &[GraphNode<T>],
+        outputs: &[usize],
+        pool: &SharedPool<T>,
+        threads: Option<&rayon::ThreadPool>,
+        options: EvalOptions,
+    ) -> Result<(Vec<CpuStorage<T>>, RunStats)> {
+        let start = {
+            let mut pool = pool.lock().unwrap();
+            pool.reset_peak();
+            pool.metrics()
+        };
+
         // Clone into an Arc for sharing
-        let node_graph = Arc::new(node_graph.clone());
+        let node_graph = Arc::new(node_graph.to_vec());
         let n = node_graph.len();
 
         // Prepare slots for results and strides
@@ -119,57 +440,37 @@ impl BackendDevice for CpuDevice {
         let results_strides: Arc<Vec<RwLock<Option<Vec<usize>>>>> =
             Arc::new((0..n).map(|_| RwLock::new(None)).collect());
 
+        // Arg reductions run their source graph on a scheduler of its own, which would wait on
+        // this pool if started from one of its tasks, so they are computed up front here.
+        for (idx, node) in node_graph.iter().enumerate() {
+            if let Op::ArgReduce { v, axis, kind } = &node.op {
+                let positions = v.arg_reduce_cpu(*axis, *kind)?;
+                let mut buf = pool.lock().unwrap().get_empty_buffer(positions.len());
+                buf.extend(positions.into_iter().map(|p| T::from_f64(p as f64)));
+                *results[idx].write().unwrap() = Some(PooledBuffer::new(buf, pool.clone()));
+            }
+        }
+
         // Build adjacency: children lists and indegree counts
         let mut children = vec![Vec::new(); n];
         let indegree_vec = (0..n).map(|_| AtomicUsize::new(0)).collect::<Vec<_>>();
         for node in node_graph.iter() {
             let dst = node.id.get();
-            match &node.op {
-                Op::BinaryOp { l_id, r_id, .. } => {
-                    let p1 = l_id.get();
-                    let p2 = r_id.get();
-                    children[p1].push(dst);
-                    children[p2].push(dst);
-                    indegree_vec[dst].fetch_add(2, Ordering::SeqCst);
-                }
-                Op::UnaryOp { v_id, .. } => {
-                    let p = v_id.get();
-                    children[p].push(dst);
-                    indegree_vec[dst].fetch_add(1, Ordering::SeqCst);
-                }
-                Op::FusedMulAdd { a_id, b_id, c_id } => {
-                    for &p in &[a_id.get(), b_id.get(), c_id.get()] {
-                        children[p].push(dst);
-                        indegree_vec[dst].fetch_add(1, Ordering::SeqCst);
-                    }
-                }
-                Op::MatMul {
-                    l_id, r_id, o_id, ..
-                } => {
-                    let p1 = l_id.get();
-                    let p2 = r_id.get();
-                    children[p1].push(dst);
-                    children[p2].push(dst);
-                    indegree_vec[dst].fetch_add(2, Ordering::SeqCst);
-                    if let Some(o) = o_id {
-                        let p3 = o.get();
-                        children[p3].push(dst);
-                        indegree_vec[dst].fetch_add(1, Ordering::SeqCst);
-                    }
-                }
-                Op::Permute { v_id } => {
-                    let p = v_id.get();
-                    children[p].push(dst);
-                    indegree_vec[dst].fetch_add(1, Ordering::SeqCst);
-                }
-                _ => {}
+            for src in node.op.input_ids() {
+                children[src.get()].push(dst);
+                indegree_vec[dst].fetch_add(1, Ordering::SeqCst);
             }
         }
         let indegree = Arc::new(indegree_vec);
         let children = Arc::new(children);
 
-        // Channel to signal when the final node completes
-        let final_idx = n - 1;
+        // Channel to signal when each requested output completes
+        let mut is_output = vec![false; n];
+        for &o in outputs {
+            is_output[o] = true;
+        }
+        let pending = is_output.iter().filter(|o| **o).count();
+        let is_output = Arc::new(is_output);
         let (tx, rx) = mpsc::channel();
 
         // Spawn initial tasks for nodes with zero indegree
@@ -181,8 +482,10 @@ impl BackendDevice for CpuDevice {
                 let results_strides = results_strides.clone();
                 let indegree = indegree.clone();
                 let children = children.clone();
+                let is_output = is_output.clone();
+                let options = options.clone();
                 let tx = tx.clone();
-                rayon::spawn(move || {
+                let task = move || {
                     eval_node(
                         idx,
                         &node_graph,
@@ -191,24 +494,57 @@ impl BackendDevice for CpuDevice {
                         &results_strides,
                         &indegree,
                         &children,
-                        final_idx,
+                        &is_output,
+                        &options,
                         tx,
                     );
-                });
+                };
+                // Children are spawned from within the task, and so stay on the same pool.
+                match threads {
+                    Some(threads) => threads.spawn(task),
+                    None => rayon::spawn(task),
+                }
             }
         }
         // Drop the extra sender in main thread
         drop(tx);
 
-        // Wait for the final node to complete
-        rx.recv()
-            .expect("Failed to receive completion of final node");
+        // Wait for the outputs to complete, or for the first node to fail
+        for _ in 0..pending {
+            rx.recv()
+                .expect("Failed to receive completion of output node")?;
+        }
+
+        // Extract and return the requested results. Outputs that are still read by other nodes, or
+        // requested more than once, are copied rather than moved out.
+        let storages = outputs
+            .iter()
+            .enumerate()
+            .map(|(i, &o)| {
+                let mut slot = results[o].write().unwrap();
+                let output = if !children[o].is_empty() || outputs[i + 1..].contains(&o) {
+                    slot.as_ref().expect("Output result missing").to_vec()
+                } else {
+                    slot.take().expect("Output result missing").into_inner()
+                };
+                CpuStorage(output)
+            })
+            .collect();
+
+        // Return the remaining intermediates to the pool for the next run, once every node that
+        // might still read them has finished and dropped its sender.
+        while rx.recv().is_ok() {}
+        for slot in results.iter() {
+            slot.write().unwrap().take();
+        }
 
-        // Extract and return the final result
-        let mut final_lock = results[final_idx].write().unwrap();
-        let pooled = final_lock.take().expect("Final result missing");
-        let output = pooled.into_inner();
-        Ok(CpuStorage(output))
+        let metrics = pool.lock().unwrap().metrics();
+        let stats = RunStats {
+            allocated: metrics.misses - start.misses,
+            reused: metrics.hits - start.hits,
+            peak_live: metrics.peak_live,
+        };
+        Ok((storages, stats))
     }
 }
 
@@ -222,8 +558,9 @@ fn eval_node<T: DType + Send + Sync + 'static>(
     results_strides: &Arc<Vec<RwLock<Option<Vec<usize>>>>>,
     indegree: &Arc<Vec<AtomicUsize>>,
     children: &Arc<Vec<Vec<usize>>>,
-    final_idx: usize,
-    tx: mpsc::Sender<()>,
+    is_output: &Arc<Vec<bool>>,
+    options: &EvalOptions,
+    tx: mpsc::Sender<Result<()>>,
 ) {
     // Prepare RNG for random ops
     let mut rng = rand::rng();
@@ -232,18 +569,31 @@ fn eval_node<T: DType + Send + Sync + 'static>(
     let out_elem_count: usize = out_shape.iter().product();
 
     // Compute this node's buffer
+    let start = options.timings.as_ref().map(|_| Instant::now());
     let computed: PooledBuffer<T> = match &node.op {
         Op::Fill { v } => {
             let mut buf = pool.lock().unwrap().get_empty_buffer(out_elem_count);
             buf.extend(std::iter::repeat_n(*v, out_elem_count));
             PooledBuffer::new(buf, pool.clone())
         }
-        Op::Arange { start, step, stop } => {
+        Op::Arange { start, step, .. } => {
+            // Index-based rather than accumulating, so rounding can never add or drop an element.
+            let start = start.to_f64();
             let mut buf = pool.lock().unwrap().get_empty_buffer(out_elem_count);
-            let mut x = start.to_f64();
-            while x < stop.to_f64() {
-                buf.push(T::from_f64(x));
-                x += step.to_f64();
+            buf.extend((0..out_elem_count).map(|i| T::from_f64(start + i as f64 * step)));
+            PooledBuffer::new(buf, pool.clone())
+        }
+        Op::Const { data } => {
+            let mut buf = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            buf.extend_from_slice(data);
+            PooledBuffer::new(buf, pool.clone())
+        }
+        Op::Eye => {
+            let n = node.shape[1];
+            let mut buf = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            buf.resize(out_elem_count, T::ZERO);
+            for i in 0..n {
+                buf[i * n + i] = T::ONE;
             }
             PooledBuffer::new(buf, pool.clone())
         }
@@ -266,75 +616,174 @@ fn eval_node<T: DType + Send + Sync + 'static>(
         }
         Op::UnaryOp { v_id, operator } => {
             let src_guard = results[v_id.get()].read().unwrap();
-            let src = src_guard.as_ref().unwrap();
+            let src = row_major(src_guard.as_ref().unwrap(), &node_graph[v_id.get()]);
             let op_fn = operator.to_closure();
             let mut out = pool.lock().unwrap().get_buffer(out_elem_count);
             out.par_iter_mut()
-                .zip(&**src)
+                .zip(&*src)
                 .for_each(|(o, x)| *o = op_fn(*x));
             PooledBuffer::new(out, pool.clone())
         }
+        Op::CustomUnary { v_id, f, .. } => {
+            let src_guard = results[v_id.get()].read().unwrap();
+            let src = row_major(src_guard.as_ref().unwrap(), &node_graph[v_id.get()]);
+            let mut out = pool.lock().unwrap().get_buffer(out_elem_count);
+            out.par_iter_mut().zip(&*src).for_each(|(o, x)| *o = f(*x));
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::NanToNum {
+            v_id,
+            nan,
+            posinf,
+            neginf,
+        } => {
+            let src_guard = results[v_id.get()].read().unwrap();
+            let src = row_major(src_guard.as_ref().unwrap(), &node_graph[v_id.get()]);
+            let mut out = pool.lock().unwrap().get_buffer(out_elem_count);
+            out.par_iter_mut().zip(&*src).for_each(|(o, x)| {
+                let v = x.to_f64();
+                *o = if v.is_nan() {
+                    *nan
+                } else if v == f64::INFINITY {
+                    *posinf
+                } else if v == f64::NEG_INFINITY {
+                    *neginf
+                } else {
+                    *x
+                };
+            });
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::Clamp { v_id, min, max } => {
+            let src_guard = results[v_id.get()].read().unwrap();
+            let src = row_major(src_guard.as_ref().unwrap(), &node_graph[v_id.get()]);
+            let mut out = pool.lock().unwrap().get_buffer(out_elem_count);
+            // NaN compares false both ways, so it passes through.
+            out.par_iter_mut().zip(&*src).for_each(|(o, x)| {
+                *o = if *x < *min {
+                    *min
+                } else if *x > *max {
+                    *max
+                } else {
+                    *x
+                };
+            });
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::MaskedFill {
+            v_id,
+            mask_id,
+            value,
+        } => {
+            let src_guard = results[v_id.get()].read().unwrap();
+            let mask_guard = results[mask_id.get()].read().unwrap();
+            let src = row_major(src_guard.as_ref().unwrap(), &node_graph[v_id.get()]);
+            let mask = row_major(mask_guard.as_ref().unwrap(), &node_graph[mask_id.get()]);
+            let mut out = pool.lock().unwrap().get_buffer(out_elem_count);
+            out.par_iter_mut()
+                .zip(&*src)
+                .zip(&*mask)
+                .for_each(|((o, x), m)| *o = if *m != T::ZERO { *value } else { *x });
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::Triangular {
+            v_id,
+            upper,
+            diagonal,
+        } => {
+            let src_guard = results[v_id.get()].read().unwrap();
+            let src = row_major(src_guard.as_ref().unwrap(), &node_graph[v_id.get()]);
+            let (m, n) = (
+                out_shape[out_shape.len() - 2],
+                out_shape[out_shape.len() - 1],
+            );
+            let mut out = pool.lock().unwrap().get_buffer(out_elem_count);
+            out.par_iter_mut()
+                .zip(&*src)
+                .enumerate()
+                .for_each(|(i, (o, x))| {
+                    let offset = (i % n) as isize - ((i / n) % m) as isize;
+                    let keep = if *upper {
+                        offset >= *diagonal
+                    } else {
+                        offset <= *diagonal
+                    };
+                    *o = if keep { *x } else { T::ZERO };
+                });
+            PooledBuffer::new(out, pool.clone())
+        }
         Op::BinaryOp {
             l_id,
             r_id,
             operator,
         } => {
+            let (l_node, r_node) = (&node_graph[l_id.get()], &node_graph[r_id.get()]);
             if l_id.is_inplace() {
                 let mut left = results[l_id.get()].write().unwrap().take().unwrap();
+                make_row_major(&mut left, l_node);
                 let right_guard = results[r_id.get()].read().unwrap();
-                let right = right_guard.as_ref().unwrap();
-                T::binary_simd_op_inplace_lhs(&mut left, right, *operator);
+                let right = row_major(right_guard.as_ref().unwrap(), r_node);
+                T::binary_simd_op_inplace_lhs(&mut left, &right, *operator);
                 left
             } else if r_id.is_inplace() {
                 let mut right = results[r_id.get()].write().unwrap().take().unwrap();
+                make_row_major(&mut right, r_node);
                 let left_guard = results[l_id.get()].read().unwrap();
-                let left = left_guard.as_ref().unwrap();
-                T::binary_simd_op_inplace_rhs(left, &mut right, *operator);
+                let left = row_major(left_guard.as_ref().unwrap(), l_node);
+                T::binary_simd_op_inplace_rhs(&left, &mut right, *operator);
                 right
             } else {
                 let left_guard = results[l_id.get()].read().unwrap();
-                let left = left_guard.as_ref().unwrap();
+                let left = row_major(left_guard.as_ref().unwrap(), l_node);
                 let right_guard = results[r_id.get()].read().unwrap();
-                let right = right_guard.as_ref().unwrap();
+                let right = row_major(right_guard.as_ref().unwrap(), r_node);
                 let mut out = pool.lock().unwrap().get_buffer(out_elem_count);
-                T::binary_simd_op(left, right, &mut out, *operator);
+                T::binary_simd_op(&left, &right, &mut out, *operator);
                 PooledBuffer::new(out, pool.clone())
             }
         }
         Op::FusedMulAdd { a_id, b_id, c_id } => {
+            let (a_node, b_node, c_node) = (
+                &node_graph[a_id.get()],
+                &node_graph[b_id.get()],
+                &node_graph[c_id.get()],
+            );
             if a_id.is_inplace() {
                 let mut a_buf = results[a_id.get()].write().unwrap().take().unwrap();
+                make_row_major(&mut a_buf, a_node);
                 let b_guard = results[b_id.get()].read().unwrap();
-                let b_buf = b_guard.as_ref().unwrap();
+                let b_buf = row_major(b_guard.as_ref().unwrap(), b_node);
                 let c_guard = results[c_id.get()].read().unwrap();
-                let c_buf = c_guard.as_ref().unwrap();
-                T::fma_op_inplace_a(&mut a_buf, b_buf, c_buf);
+                let c_buf = row_major(c_guard.as_ref().unwrap(), c_node);
+                T::fma_op_inplace_a(&mut a_buf, &b_buf, &c_buf);
                 a_buf
             } else if b_id.is_inplace() {
                 let mut b_buf = results[b_id.get()].write().unwrap().take().unwrap();
+                make_row_major(&mut b_buf, b_node);
                 let a_guard = results[a_id.get()].read().unwrap();
-                let a_buf = a_guard.as_ref().unwrap();
+                let a_buf = row_major(a_guard.as_ref().unwrap(), a_node);
                 let c_guard = results[c_id.get()].read().unwrap();
-                let c_buf = c_guard.as_ref().unwrap();
-                T::fma_op_inplace_b(a_buf, &mut b_buf, c_buf);
+                let c_buf = row_major(c_guard.as_ref().unwrap(), c_node);
+                T::fma_op_inplace_b(&a_buf, &mut b_buf, &c_buf);
                 b_buf
             } else if c_id.is_inplace() {
                 let mut c_buf = results[c_id.get()].write().unwrap().take().unwrap();
+                make_row_major(&mut c_buf, c_node);
                 let a_guard = results[a_id.get()].read().unwrap();
-                let a_buf = a_guard.as_ref().unwrap();
+                let a_buf = row_major(a_guard.as_ref().unwrap(), a_node);
                 let b_guard = results[b_id.get()].read().unwrap();
-                let b_buf = b_guard.as_ref().unwrap();
-                T::fma_op_inplace_c(a_buf, b_buf, &mut c_buf);
+                let b_buf = row_major(b_guard.as_ref().unwrap(), b_node);
+                T::fma_op_inplace_c(&a_buf, &b_buf, &mut c_buf);
                 c_buf
             } else {
                 let a_guard = results[a_id.get()].read().unwrap();
-                let a_buf = a_guard.as_ref().unwrap();
+                let a_buf = row_major(a_guard.as_ref().unwrap(), a_node);
                 let b_guard = results[b_id.get()].read().unwrap();
-                let b_buf = b_guard.as_ref().unwrap();
+                let b_buf = row_major(b_guard.as_ref().unwrap(), b_node);
                 let c_guard = results[c_id.get()].read().unwrap();
-                let c_buf = c_guard.as_ref().unwrap();
+                let c_buf = row_major(c_guard.as_ref().unwrap(), c_node);
                 let mut out = pool.lock().unwrap().get_buffer(out_elem_count);
-                T::fma_op(a_buf, b_buf, c_buf, &mut out);
+                T::fma_op(&a_buf, &b_buf, &c_buf, &mut out);
                 PooledBuffer::new(out, pool.clone())
             }
         }
@@ -346,10 +795,11 @@ fn eval_node<T: DType + Send + Sync + 'static>(
             alpha,
             beta,
         } => {
-            let shape = &node.shape;
-            let b = shape[0];
-            let m = shape[1];
-            let n = shape[2];
+            // The output may be larger than the product, so take its dims from the operands.
+            let l_shape = &node_graph[l_id.get()].shape;
+            let b = l_shape[0];
+            let m = l_shape[1];
+            let n = node_graph[r_id.get()].shape[2];
             let (mut out_buf, out_stride) = if let Some(o) = o_id {
                 if o.is_inplace() {
                     let buf = results[o.get()].write().unwrap().take().unwrap();
@@ -365,7 +815,9 @@ fn eval_node<T: DType + Send + Sync + 'static>(
                     let buf_clone = buf_guard.as_ref().unwrap();
                     let st_guard = results_strides[o.get()].read().unwrap();
                     let st = st_guard.as_ref().unwrap().clone();
-                    (PooledBuffer::new((*buf_clone).clone(), pool.clone()), st)
+                    let mut buf = pool.lock().unwrap().get_empty_buffer(buf_clone.len());
+                    buf.extend_from_slice(buf_clone);
+                    (PooledBuffer::new(buf, pool.clone()), st)
                 }
             } else {
                 let st = contiguous_strides(&[b, m, n]);
@@ -380,20 +832,31 @@ fn eval_node<T: DType + Send + Sync + 'static>(
             let a_str = a_str_guard.as_ref().unwrap();
             let b_str_guard = results_strides[r_id.get()].read().unwrap();
             let b_str = b_str_guard.as_ref().unwrap();
-            T::launch_gemm(
-                a_buf,
-                a_str,
-                b_buf,
-                b_str,
-                b,
-                m,
-                n,
-                *k,
-                &mut out_buf,
-                &out_stride,
-                *alpha,
-                *beta,
-            );
+            if options.reduce_mode == ReduceMode::Compensated && !T::INTEGRAL {
+                compensated_gemm(
+                    (a_buf, a_str),
+                    (b_buf, b_str),
+                    [b, m, n, *k],
+                    (&mut out_buf, &out_stride),
+                    *alpha,
+                    *beta,
+                );
+            } else {
+                T::launch_gemm(
+                    a_buf,
+                    a_str,
+                    b_buf,
+                    b_str,
+                    b,
+                    m,
+                    n,
+                    *k,
+                    &mut out_buf,
+                    &out_stride,
+                    *alpha,
+                    *beta,
+                );
+            }
             out_buf
         }
         Op::Permute { v_id } => {
@@ -401,18 +864,275 @@ fn eval_node<T: DType + Send + Sync + 'static>(
                 results[v_id.get()].write().unwrap().take().unwrap()
             } else {
                 let buf_guard = results[v_id.get()].read().unwrap();
-                let buf = buf_guard.as_ref().unwrap();
-                PooledBuffer::new((*buf).clone(), pool.clone())
+                let src = buf_guard.as_ref().unwrap();
+                let mut buf = pool.lock().unwrap().get_empty_buffer(src.len());
+                buf.extend_from_slice(src);
+                PooledBuffer::new(buf, pool.clone())
+            }
+        }
+        Op::Contiguous { v_id } => {
+            let src_guard = results[v_id.get()].read().unwrap();
+            let src_node = &node_graph[v_id.get()];
+            let mut out = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            extend_contiguous(
+                &mut out,
+                src_guard.as_ref().unwrap(),
+                0,
+                &src_node.shape,
+                &src_node.strides,
+            );
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::Stack { ids } => {
+            let mut out = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            for id in ids {
+                let src_guard = results[id.get()].read().unwrap();
+                let src = src_guard.as_ref().unwrap();
+                let src_node = &node_graph[id.get()];
+                extend_contiguous(&mut out, src, 0, &src_node.shape, &src_node.strides);
+            }
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::Concat { ids, axis } => {
+            let mut out = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            let srcs = ids
+                .iter()
+                .map(|id| (results[id.get()].read().unwrap(), &node_graph[id.get()]))
+                .collect::<Vec<_>>();
+            // For every index into the leading dims, append each input's trailing block in turn.
+            let outer_shape = &node.shape[..*axis];
+            let mut outer = vec![0; *axis];
+            for _ in 0..outer_shape.iter().product::<usize>() {
+                for (src_guard, src_node) in &srcs {
+                    let src = src_guard.as_ref().unwrap();
+                    let base = strided_offset(&outer, &src_node.strides);
+                    extend_contiguous(
+                        &mut out,
+                        src,
+                        base,
+                        &src_node.shape[*axis..],
+                        &src_node.strides[*axis..],
+                    );
+                }
+                advance_index(&mut outer, outer_shape);
+            }
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::Slice {
+            v_id, offset, axis, ..
+        } => {
+            let mut out = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            let src_guard = results[v_id.get()].read().unwrap();
+            let src = src_guard.as_ref().unwrap();
+            let src_strides = &node_graph[v_id.get()].strides;
+            let base = offset * src_strides[*axis];
+            extend_contiguous(&mut out, src, base, &node.shape, src_strides);
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::Broadcast { v_id } => {
+            let src_guard = results[v_id.get()].read().unwrap();
+            let src_node = &node_graph[v_id.get()];
+            // Repeated axes read the same entry over and over.
+            let strides = src_node
+                .shape
+                .iter()
+                .zip(&node.shape)
+                .zip(&src_node.strides)
+                .map(|((&from, &to), &stride)| if from == to { stride } else { 0 })
+                .collect::<Vec<_>>();
+            let mut out = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            extend_contiguous(
+                &mut out,
+                src_guard.as_ref().unwrap(),
+                0,
+                &node.shape,
+                &strides,
+            );
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::Diagonal { v_id, offset } => {
+            let src_guard = results[v_id.get()].read().unwrap();
+            let src = src_guard.as_ref().unwrap();
+            let strides = &node_graph[v_id.get()].strides;
+            let (row, col) = ((-offset).max(0) as usize, (*offset).max(0) as usize);
+            let mut out = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            out.extend(
+                (0..out_elem_count).map(|i| src[(row + i) * strides[0] + (col + i) * strides[1]]),
+            );
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::Roll { v_id, shift, axis } => {
+            let src_guard = results[v_id.get()].read().unwrap();
+            let src = src_guard.as_ref().unwrap();
+            let src_strides = &node_graph[v_id.get()].strides;
+            let len = node.shape[*axis];
+            let mut out = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            if out_elem_count > 0 {
+                // Output position `i` reads input position `i - shift`, wrapped around the axis.
+                let back = len - shift.rem_euclid(len as isize) as usize;
+                let mut index = vec![0; node.shape.len()];
+                for _ in 0..out_elem_count {
+                    let i = index[*axis];
+                    index[*axis] = (i + back) % len;
+                    out.push(src[strided_offset(&index, src_strides)]);
+                    index[*axis] = i;
+                    advance_index(&mut index, &node.shape);
+                }
+            }
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::Flip { v_id, axis } => {
+            let src_guard = results[v_id.get()].read().unwrap();
+            let src = src_guard.as_ref().unwrap();
+            let src_strides = &node_graph[v_id.get()].strides;
+            let len = node.shape[*axis];
+            let mut out = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            let mut index = vec![0; node.shape.len()];
+            for _ in 0..out_elem_count {
+                // Output position `i` reads the mirrored input position `len - 1 - i`.
+                let i = index[*axis];
+                index[*axis] = len - 1 - i;
+                out.push(src[strided_offset(&index, src_strides)]);
+                index[*axis] = i;
+                advance_index(&mut index, &node.shape);
+            }
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::Pad {
+            v_id,
+            before,
+            value,
+            ..
+        } => {
+            let src_guard = results[v_id.get()].read().unwrap();
+            let src = src_guard.as_ref().unwrap();
+            let src_node = &node_graph[v_id.get()];
+            let out_strides = contiguous_strides(&node.shape);
+            let mut out = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            out.extend(std::iter::repeat_n(*value, out_elem_count));
+            // Copy the source into the interior, shifted by the leading padding.
+            let mut index = vec![0; src_node.shape.len()];
+            for _ in 0..src_node.shape.iter().product::<usize>() {
+                let out_offset = index
+                    .iter()
+                    .zip(before)
+                    .zip(&out_strides)
+                    .map(|((i, b), s)| (i + b) * s)
+                    .sum::<usize>();
+                out[out_offset] = src[strided_offset(&index, &src_node.strides)];
+                advance_index(&mut index, &src_node.shape);
+            }
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::Reduce {
+            v_id,
+            axis,
+            operator,
+        } => {
+            let src_guard = results[v_id.get()].read().unwrap();
+            let src_node = &node_graph[v_id.get()];
+            let mut out = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            let reduced = reduce(
+                &mut out,
+                (
+                    src_guard.as_ref().unwrap(),
+                    &src_node.shape,
+                    &src_node.strides,
+                ),
+                *axis,
+                *operator,
+                options.reduce_mode,
+            );
+            if let Err(e) = reduced {
+                let _ = tx.send(Err(e));
+                return;
+            }
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::ArgReduce { .. } => results[idx]
+            .write()
+            .unwrap()
+            .take()
+            .expect("arg reductions are computed before the run starts"),
+        Op::Gather {
+            v_id,
+            idx_id,
+            axis,
+            mode,
+        } => {
+            let v_guard = results[v_id.get()].read().unwrap();
+            let idx_guard = results[idx_id.get()].read().unwrap();
+            let v_node = &node_graph[v_id.get()];
+            let idx_node = &node_graph[idx_id.get()];
+            let mut out = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            let gathered = gather(
+                &mut out,
+                (v_guard.as_ref().unwrap(), &v_node.shape, &v_node.strides),
+                (
+                    idx_guard.as_ref().unwrap(),
+                    &idx_node.shape,
+                    &idx_node.strides,
+                ),
+                *axis,
+                *mode,
+            );
+            if let Err(e) = gathered {
+                let _ = tx.send(Err(e));
+                return;
+            }
+            PooledBuffer::new(out, pool.clone())
+        }
+        Op::Scatter {
+            v_id,
+            idx_id,
+            src_id,
+            axis,
+            mode,
+        } => {
+            let v_guard = results[v_id.get()].read().unwrap();
+            let idx_guard = results[idx_id.get()].read().unwrap();
+            let src_guard = results[src_id.get()].read().unwrap();
+            let v_node = &node_graph[v_id.get()];
+            let idx_node = &node_graph[idx_id.get()];
+            let src_node = &node_graph[src_id.get()];
+            let mut out = pool.lock().unwrap().get_empty_buffer(out_elem_count);
+            extend_contiguous(
+                &mut out,
+                v_guard.as_ref().unwrap(),
+                0,
+                &v_node.shape,
+                &v_node.strides,
+            );
+            let scattered = scatter(
+                &mut out,
+                &v_node.shape,
+                (idx_guard.as_ref().unwrap(), &idx_node.strides),
+                (src_guard.as_ref().unwrap(), &src_node.strides),
+                &idx_node.shape,
+                *axis,
+                *mode,
+            );
+            if let Err(e) = scattered {
+                let _ = tx.send(Err(e));
+                return;
             }
+            PooledBuffer::new(out, pool.clone())
         }
         Op::NoOp => panic!("NoOp should not be evaluated"),
     };
+    if let (Some(timings), Some(start)) = (&options.timings, start) {
+        timings[idx].store(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+    if options.check_finite && !T::INTEGRAL && computed.iter().any(|x| !x.to_f64().is_finite()) {
+        let _ = tx.send(Err(crate::Error::NonFinite { node: idx }));
+        return;
+    }
     // store result and strides
     *results[idx].write().unwrap() = Some(computed);
     *results_strides[idx].write().unwrap() = Some(node.strides.clone());
-    // signal final
-    if idx == final_idx {
-        let _ = tx.send(());
+    // signal a requested output
+    if is_output[idx] {
+        let _ = tx.send(Ok(()));
     }
     // schedule children
     for &child in &children[idx] {
@@ -423,12 +1143,284 @@ fn eval_node<T: DType + Send + Sync + 'static>(
             let rs2 = results_strides.clone();
             let indeg2 = indegree.clone();
             let ch2 = children.clone();
+            let out2 = is_output.clone();
+            let options2 = options.clone();
             let tx2 = tx.clone();
             rayon::spawn(move || {
                 eval_node(
-                    child, &ng2, &pool2, &res2, &rs2, &indeg2, &ch2, final_idx, tx2,
+                    child, &ng2, &pool2, &res2, &rs2, &indeg2, &ch2, &out2, &options2, tx2,
                 );
             });
         }
     }
 }
+
+/// Append the elements of `src` starting at `base`, laid out with `strides`, to `out` in row-major order.
+pub(crate) fn extend_contiguous<T: DType>(
+    out: &mut Vec<T>,
+    src: &[T],
+    base: usize,
+    shape: &[usize],
+    strides: &[usize],
+) {
+    let numel: usize = shape.iter().product();
+    if is_contiguous_strides(shape, strides) {
+        out.extend_from_slice(&src[base..base + numel]);
+        return;
+    }
+    let mut index = vec![0; shape.len()];
+    for _ in 0..numel {
+        out.push(src[base + strided_offset(&index, strides)]);
+        advance_index(&mut index, shape);
+    }
+}
+
+/// Borrow `src` in row-major order, gathering it first if `node` is a strided view.
+fn row_major<'a, T: DType>(src: &'a [T], node: &GraphNode<T>) -> Cow<'a, [T]> {
+    if is_contiguous_strides(&node.shape, &node.strides) {
+        return Cow::Borrowed(src);
+    }
+    let mut out = Vec::with_capacity(src.len());
+    extend_contiguous(&mut out, src, 0, &node.shape, &node.strides);
+    Cow::Owned(out)
+}
+
+/// Reorder a buffer laid out with `node`'s strides into row-major order, e.g. before an op
+/// reuses it in place.
+fn make_row_major<T: DType>(buf: &mut [T], node: &GraphNode<T>) {
+    if let Cow::Owned(gathered) = row_major(buf, node) {
+        buf.copy_from_slice(&gathered);
+    }
+}
+
+/// Advance a multi-dimensional index in row-major order, last axis fastest.
+fn advance_index(index: &mut [usize], shape: &[usize]) {
+    for axis in (0..shape.len()).rev() {
+        index[axis] += 1;
+        if index[axis] < shape[axis] {
+            break;
+        }
+        index[axis] = 0;
+    }
+}
+
+fn strided_offset(index: &[usize], strides: &[usize]) -> usize {
+    index.iter().zip(strides).map(|(i, s)| i * s).sum()
+}
+
+/// Convert an index value to a position along an axis of length `dim`.
+fn resolve_index<T: DType>(value: T, dim: usize, mode: IndexMode) -> Result<usize> {
+    let x = value.to_f64();
+    match mode {
+        IndexMode::Clamp => Ok((x as usize).min(dim.saturating_sub(1))),
+        IndexMode::Error => {
+            if x >= 0. && x < dim as f64 && x.fract() == 0. {
+                Ok(x as usize)
+            } else {
+                Err(crate::Error::InvalidArgument(format!(
+                    "index {x} is out of range for an axis of length {dim}"
+                )))
+            }
+        }
+    }
+}
+
+/// A source buffer with its shape and strides.
+type Strided<'a, T> = (&'a [T], &'a [usize], &'a [usize]);
+
+/// Axes at least this long are summed in parallel by [`ReduceMode::Fast`].
+const PARALLEL_SUM_MIN_LEN: usize = 1 << 14;
+
+/// Append `v` reduced along `axis` to `out`, in row-major order of the reduced shape. An empty
+/// axis sums to zero, and has no maximum.
+fn reduce<T: DType>(
+    out: &mut Vec<T>,
+    (v, v_shape, v_strides): Strided<'_, T>,
+    axis: usize,
+    operator: ReduceOpType,
+    mode: ReduceMode,
+) -> Result<()> {
+    let len = v_shape[axis];
+    let mut out_shape = v_shape.to_vec();
+    out_shape[axis] = 1;
+    if len == 0 {
+        match operator {
+            ReduceOpType::Sum => {
+                out.extend(std::iter::repeat_n(T::ZERO, out_shape.iter().product()));
+                return Ok(());
+            }
+            ReduceOpType::Max => {
+                return Err(crate::Error::InvalidArgument(format!(
+                    "cannot take {operator:?} along axis {axis} of length 0"
+                )));
+            }
+        }
+    }
+    let mut index = vec![0; out_shape.len()];
+    for _ in 0..out_shape.iter().product::<usize>() {
+        let base = strided_offset(&index, v_strides);
+        let value = |k: usize| v[base + k * v_strides[axis]];
+        let mut values = (0..len).map(value);
+        let first = values.next().unwrap();
+        out.push(match operator {
+            ReduceOpType::Sum if mode == ReduceMode::Fast && len >= PARALLEL_SUM_MIN_LEN => {
+                let chunk = len.div_ceil(rayon::current_num_threads());
+                let partials = (0..len.div_ceil(chunk))
+                    .into_par_iter()
+                    .map(|c| {
+                        let start = c * chunk;
+                        (start + 1..(start + chunk).min(len))
+                            .fold(value(start), |acc, k| acc + value(k))
+                    })
+                    .collect::<Vec<_>>();
+                partials.into_iter().reduce(|acc, x| acc + x).unwrap()
+            }
+            ReduceOpType::Sum if mode == ReduceMode::Compensated => kahan_sum((0..len).map(value)),
+            ReduceOpType::Sum => values.fold(first, |acc, x| acc + x),
+            ReduceOpType::Max => values.fold(first, |acc, x| if x > acc { x } else { acc }),
+        });
+        advance_index(&mut index, &out_shape);
+    }
+    Ok(())
+}
+
+/// The positions or counts picked by `kind` along `axis` of `v`, in row-major order of the
+/// reduced shape. Ties go to the lowest position, and an empty axis counts to zero but has no
+/// extremum.
+pub(crate) fn arg_reduce<T: DType>(
+    (v, v_shape, v_strides): Strided<'_, T>,
+    axis: usize,
+    kind: ArgReduceType,
+) -> Result<Vec<u32>> {
+    let len = v_shape[axis];
+    if len == 0 && kind != ArgReduceType::CountNonzero {
+        return Err(crate::Error::InvalidArgument(format!(
+            "cannot take {kind:?} along axis {axis} of length 0"
+        )));
+    }
+    if u32::try_from(len).is_err() {
+        return Err(crate::Error::InvalidArgument(format!(
+            "cannot take {kind:?} along axis {axis} of length {len}, which does not fit in u32"
+        )));
+    }
+    let mut out_shape = v_shape.to_vec();
+    out_shape[axis] = 1;
+    let numel = out_shape.iter().product::<usize>();
+    let mut out = Vec::with_capacity(numel);
+    let mut index = vec![0; out_shape.len()];
+    for _ in 0..numel {
+        let base = strided_offset(&index, v_strides);
+        let value = |k: usize| v[base + k * v_strides[axis]];
+        let better = |x: T, best: T| match kind {
+            ArgReduceType::ArgMin => x < best,
+            _ => x > best,
+        };
+        let picked = match kind {
+            ArgReduceType::CountNonzero => (0..len).filter(|&k| value(k).to_f64() != 0.0).count(),
+            _ => (1..len).fold(0, |best, k| {
+                if better(value(k), value(best)) {
+                    k
+                } else {
+                    best
+                }
+            }),
+        };
+        out.push(picked as u32);
+        advance_index(&mut index, &out_shape);
+    }
+    Ok(out)
+}
+
+/// Sum `values` left to right with Kahan compensation. Integers are exact, so they are summed
+/// plainly.
+fn kahan_sum<T: DType>(values: impl Iterator<Item = T>) -> T {
+    if T::INTEGRAL {
+        return values.fold(T::ZERO, |acc, x| acc + x);
+    }
+    let (mut sum, mut c) = (T::ZERO, T::ZERO);
+    for x in values {
+        let y = x - c;
+        let t = sum + y;
+        // The low-order bits of `y` lost when adding it to `sum`.
+        c = (t - sum) - y;
+        sum = t;
+    }
+    sum
+}
+
+/// `out = alpha * out + beta * lhs @ rhs` for (B x M x K) * (B x K x N), with each dot product
+/// summed by [`kahan_sum`]. `out` is only read when `alpha` is nonzero.
+fn compensated_gemm<T: DType>(
+    (lhs, lhs_stride): (&[T], &[usize]),
+    (rhs, rhs_stride): (&[T], &[usize]),
+    [b, m, n, k]: [usize; 4],
+    (out, out_stride): (&mut [T], &[usize]),
+    alpha: T,
+    beta: T,
+) {
+    for batch in 0..b {
+        for i in 0..m {
+            for j in 0..n {
+                let sum = kahan_sum((0..k).map(|p| {
+                    lhs[batch * lhs_stride[0] + i * lhs_stride[1] + p * lhs_stride[2]]
+                        * rhs[batch * rhs_stride[0] + p * rhs_stride[1] + j * rhs_stride[2]]
+                }));
+                let idx = batch * out_stride[0] + i * out_stride[1] + j * out_stride[2];
+                out[idx] = if alpha == T::ZERO {
+                    beta * sum
+                } else {
+                    alpha * out[idx] + beta * sum
+                };
+            }
+        }
+    }
+}
+
+/// Append `v` gathered along `axis` at the positions in `idx` to `out`, in the index layout.
+fn gather<T: DType>(
+    out: &mut Vec<T>,
+    (v, v_shape, v_strides): Strided<'_, T>,
+    (idx, idx_shape, idx_strides): Strided<'_, T>,
+    axis: usize,
+    mode: IndexMode,
+) -> Result<()> {
+    let mut index = vec![0; idx_shape.len()];
+    for _ in 0..idx_shape.iter().product::<usize>() {
+        let pos = resolve_index(
+            idx[strided_offset(&index, idx_strides)],
+            v_shape[axis],
+            mode,
+        )?;
+        let mut v_index = index.clone();
+        v_index[axis] = pos;
+        out.push(v[strided_offset(&v_index, v_strides)]);
+        advance_index(&mut index, idx_shape);
+    }
+    Ok(())
+}
+
+/// Write `src` into the contiguous `out` along `axis` at the positions in `idx`.
+fn scatter<T: DType>(
+    out: &mut [T],
+    out_shape: &[usize],
+    (idx, idx_strides): (&[T], &[usize]),
+    (src, src_strides): (&[T], &[usize]),
+    idx_shape: &[usize],
+    axis: usize,
+    mode: IndexMode,
+) -> Result<()> {
+    let out_strides = contiguous_strides(out_shape);
+    let mut index = vec![0; idx_shape.len()];
+    for _ in 0..idx_shape.iter().product::<usize>() {
+        let pos = resolve_index(
+            idx[strided_offset(&index, idx_strides)],
+            out_shape[axis],
+            mode,
+        )?;
+        let mut out_index = index.clone();
+        out_index[axis] = pos;
+        out[strided_offset(&out_index, &out_strides)] = src[strided_offset(&index, src_strides)];
+        advance_index(&mut index, idx_shape);
+    }
+    Ok(())
+}
@@ -0,0 +1,54 @@
+//! NUMA placement for the `numa` feature.
+//!
+//! Linux places a page on the node of the thread that first writes it, so buffers are
+//! first-touched in parallel by the threads that later compute on them, and the threads of a
+//! dedicated pool are pinned so they stay on their node. Elsewhere this is a no-op.
+
+use std::mem::MaybeUninit;
+
+use rayon::{iter::ParallelIterator, slice::ParallelSliceMut};
+
+use crate::DType;
+
+/// Buffers smaller than this many bytes are left to the allocator; touching them in parallel
+/// costs more than it saves.
+const FIRST_TOUCH_MIN_BYTES: usize = 1 << 20;
+
+/// Write zeros to the spare capacity of `buf` from the current rayon pool, so that its pages
+/// are placed near the threads that split work over it the same way.
+pub(crate) fn first_touch<T: DType>(mut buf: Vec<T>) -> Vec<T> {
+    let spare = buf.spare_capacity_mut();
+    if std::mem::size_of_val(spare) >= FIRST_TOUCH_MIN_BYTES {
+        let chunk = spare.len().div_ceil(rayon::current_num_threads());
+        spare.par_chunks_mut(chunk).for_each(|chunk| {
+            chunk.fill(MaybeUninit::new(T::ZERO));
+        });
+    }
+    buf
+}
+
+/// Pin the calling thread, the `index`-th worker of a pool, to one of the CPUs this process may
+/// run on, wrapping around if there are more workers than CPUs.
+#[cfg(target_os = "linux")]
+pub(crate) fn pin_current_thread(index: usize) {
+    // SAFETY: `cpu_set_t` is a plain bit set, and both calls only read or write the set passed.
+    unsafe {
+        let mut allowed: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of_val(&allowed), &mut allowed) != 0 {
+            return;
+        }
+        let cpus = (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &allowed))
+            .collect::<Vec<_>>();
+        if cpus.is_empty() {
+            return;
+        }
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpus[index % cpus.len()], &mut set);
+        // Pinning is only a hint for placement, so a failure leaves the thread unpinned.
+        libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn pin_current_thread(_index: usize) {}
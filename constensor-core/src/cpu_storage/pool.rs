@@ -1,13 +1,65 @@
+use std::env;
 use std::mem;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
 
 use crate::DType;
 
-/// Max size of all buffers, in bytes.
+/// Default max size of all buffers, in bytes.
 /// Currently 4GB (1024 MB).
 const MAX_BUFFERS_SIZE: usize = 4 * 1024 * 1024 * 1024;
-/// When total pooled bytes exceed this, trim largest buffers down to this level.
-const TRIM_THRESHOLD: usize = MAX_BUFFERS_SIZE / 2;
+/// Environment variable overriding the max size of all buffers, in bytes.
+const MAX_BUFFERS_SIZE_ENV: &str = "CONSTENSOR_CPU_POOL_MAX_BYTES";
+/// The max size set by [`crate::Cpu::with_pool_max_bytes`], which takes precedence over
+/// [`MAX_BUFFERS_SIZE_ENV`].
+static MAX_BUFFERS_SIZE_OVERRIDE: Mutex<Option<usize>> = Mutex::new(None);
+/// The pools of the compiled CPU graphs, which are dropped along with their graphs.
+static SHARED_POOLS: Mutex<Vec<Weak<dyn CachedBuffers>>> = Mutex::new(Vec::new());
+
+/// A shared pool of any dtype, as kept in [`SHARED_POOLS`].
+trait CachedBuffers: Send + Sync {
+    fn capacity_bytes(&self) -> usize;
+    fn clear(&self);
+}
+
+impl<T: DType> CachedBuffers for Mutex<BufferPool<T>> {
+    fn capacity_bytes(&self) -> usize {
+        self.lock().unwrap().capacity_bytes()
+    }
+
+    fn clear(&self) {
+        self.lock().unwrap().clear();
+    }
+}
+
+/// Cap the pools created from now on at `max_size` bytes.
+pub fn set_max_size(max_size: usize) {
+    *MAX_BUFFERS_SIZE_OVERRIDE.lock().unwrap() = Some(max_size);
+}
+
+/// The pools of the compiled graphs that are still alive.
+fn shared_pools() -> Vec<Arc<dyn CachedBuffers>> {
+    SHARED_POOLS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .collect()
+}
+
+/// Total capacity of the buffers cached by the pools of every compiled graph, in bytes.
+pub fn shared_capacity_bytes() -> usize {
+    shared_pools()
+        .iter()
+        .map(|pool| pool.capacity_bytes())
+        .sum()
+}
+
+/// Drop the buffers cached by the pools of every compiled graph.
+pub fn clear_shared() {
+    for pool in shared_pools() {
+        pool.clear();
+    }
+}
 
 /// Tracks pool usage statistics.
 #[derive(Debug, Clone)]
@@ -20,12 +72,18 @@ pub struct PoolMetrics {
     pub misses: usize,
     /// Number of times a buffer was dropped due to pool size cap.
     pub drops: usize,
+    /// Number of buffers currently handed out and not yet recycled.
+    pub live: usize,
+    /// Largest value `live` has reached.
+    pub peak_live: usize,
 }
 
 #[derive(Debug)]
 /// A simple buffer pool to reuse Vec allocations across graph evaluation.
 pub struct BufferPool<T> {
     pool: Vec<Vec<T>>,
+    /// Buffers are not retained once the pool would hold more than this many bytes.
+    max_size: usize,
     /// Usage statistics for this pool.
     pub metrics: PoolMetrics,
 }
@@ -52,7 +110,10 @@ impl<T: DType> PooledBuffer<T> {
     /// Consume the wrapper and return the inner Vec without recycling.
     pub fn into_inner(mut self) -> Vec<T> {
         let buf = std::mem::take(&mut self.buf);
-        self.pool = None;
+        if let Some(pool) = self.pool.take() {
+            // The buffer leaves the pool for good, e.g. as a run's output.
+            pool.lock().unwrap().release_buffer();
+        }
         buf
     }
 }
@@ -82,14 +143,42 @@ impl<T: DType> Drop for PooledBuffer<T> {
 }
 
 impl<T: DType> BufferPool<T> {
+    /// Create a pool capped at the size set by [`crate::Cpu::with_pool_max_bytes`], falling back
+    /// to `CONSTENSOR_CPU_POOL_MAX_BYTES` bytes, or 4GB if neither is set.
     pub fn new() -> Self {
+        let max_size = MAX_BUFFERS_SIZE_OVERRIDE
+            .lock()
+            .unwrap()
+            .unwrap_or_else(|| {
+                env::var(MAX_BUFFERS_SIZE_ENV)
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(MAX_BUFFERS_SIZE)
+            });
+        Self::with_max_size(max_size)
+    }
+
+    /// Create a pool for a compiled graph, reached by [`clear_shared`] while it is alive.
+    pub fn shared() -> SharedPool<T> {
+        let pool = Arc::new(Mutex::new(Self::new()));
+        let mut pools = SHARED_POOLS.lock().unwrap();
+        pools.retain(|pool| pool.strong_count() > 0);
+        pools.push(Arc::downgrade(&pool) as Weak<dyn CachedBuffers>);
+        pool
+    }
+
+    /// Create a pool that retains at most `max_size` bytes of buffers.
+    pub fn with_max_size(max_size: usize) -> Self {
         BufferPool {
             pool: Vec::new(),
+            max_size,
             metrics: PoolMetrics {
                 current_size: 0,
                 hits: 0,
                 misses: 0,
                 drops: 0,
+                live: 0,
+                peak_live: 0,
             },
         }
     }
@@ -98,6 +187,9 @@ impl<T: DType> BufferPool<T> {
     ///
     /// Returns an uninitialized vector with capacity and len of `capacity`.
     pub fn get_empty_buffer(&mut self, capacity: usize) -> Vec<T> {
+        self.metrics.live += 1;
+        self.metrics.peak_live = self.metrics.peak_live.max(self.metrics.live);
+
         // Find the smallest buf that can fit this capacity.
         let mut smallest_found_buf = None;
         for i in 0..self.pool.len() {
@@ -135,7 +227,10 @@ impl<T: DType> BufferPool<T> {
         } else {
             // record an allocation miss
             self.metrics.misses += 1;
-            Vec::with_capacity(capacity)
+            let buf = Vec::with_capacity(capacity);
+            #[cfg(feature = "numa")]
+            let buf = super::numa::first_touch(buf);
+            buf
         }
     }
 
@@ -150,10 +245,16 @@ impl<T: DType> BufferPool<T> {
         buf
     }
 
+    /// Stop counting a handed out buffer as live without taking it back.
+    pub fn release_buffer(&mut self) {
+        self.metrics.live = self.metrics.live.saturating_sub(1);
+    }
+
     /// Return a Vec back into the pool for reuse.
     pub fn recycle_buffer(&mut self, buf: Vec<T>) {
+        self.release_buffer();
         let buffer_bytes = buf.capacity() * mem::size_of::<T>();
-        if self.metrics.current_size + buffer_bytes <= MAX_BUFFERS_SIZE {
+        if self.metrics.current_size + buffer_bytes <= self.max_size {
             self.metrics.current_size += buffer_bytes;
             self.pool.push(buf);
             debug_assert_eq!(
@@ -172,9 +273,9 @@ impl<T: DType> BufferPool<T> {
         // Otherwise drop buf and do not grow the pool further
     }
 
-    /// Remove largest buffers until total pooled bytes ≤ TRIM_THRESHOLD.
+    /// Remove largest buffers until total pooled bytes ≤ half the max size.
     fn trim_excess(&mut self) {
-        while self.metrics.current_size > TRIM_THRESHOLD {
+        while self.metrics.current_size > self.max_size / 2 {
             // Find index of largest buffer by byte capacity
             let mut max_idx = 0;
             let mut max_bytes = 0;
@@ -199,8 +300,23 @@ impl<T: DType> BufferPool<T> {
         );
     }
 
+    /// Total capacity of the buffers currently cached for reuse, in bytes.
+    pub fn capacity_bytes(&self) -> usize {
+        self.metrics.current_size
+    }
+
+    /// Drop every cached buffer, releasing its memory.
+    pub fn clear(&mut self) {
+        self.pool.clear();
+        self.metrics.current_size = 0;
+    }
+
+    /// Start a new peak measurement from the buffers handed out right now.
+    pub fn reset_peak(&mut self) {
+        self.metrics.peak_live = self.metrics.live;
+    }
+
     /// Returns a snapshot of current pool metrics.
-    #[allow(unused)]
     pub fn metrics(&self) -> PoolMetrics {
         self.metrics.clone()
     }
@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use cudarc::nvrtc::CompileError;
 
 /// cudarc related errors
@@ -26,6 +28,12 @@ pub enum CudaError {
 
     #[error("error when compiling to ptx: {err}")]
     PtxCompileError { err: CompileError },
+
+    #[error(
+        "CUDA headers not found, searched {searched:?} for include/cuda.h; set CUDA_PATH to the \
+        toolkit root"
+    )]
+    MissingHeaders { searched: Vec<PathBuf> },
 }
 
 impl From<CudaError> for crate::Error {
@@ -1,7 +1,8 @@
 use cudarc::{
     cublas::CudaBlas,
     driver::{
-        CudaEvent, CudaFunction, CudaModule, CudaSlice, CudaStream, LaunchConfig, PushKernelArg,
+        sys::CUdevice_attribute, CudaEvent, CudaFunction, CudaModule, CudaSlice, CudaStream,
+        DevicePtr, DevicePtrMut, LaunchConfig, PushKernelArg,
     },
     nvrtc::{CompileOptions, Ptx},
 };
@@ -15,23 +16,32 @@ use std::{
     borrow::Cow,
     collections::{HashMap, VecDeque},
     fs,
-    hash::{DefaultHasher, Hash, Hasher},
+    hash::{Hash, Hasher},
     marker::PhantomData,
     ops::Deref,
-    path::{Path, PathBuf},
+    path::PathBuf,
 };
 
 use crate::{
     cpu_storage::CpuStorage,
     device::Dev,
+    graph::{matmul_dims, KernelInfo, StableHasher},
     storage::{BackendDevice, BackendStorage, Storage},
-    tensor::contiguous_strides,
+    tensor::{contiguous_strides, is_contiguous_strides},
     CompiledGraph, DType, GraphNode, Op, Result, Shape,
 };
 
 pub(crate) mod error;
 pub(crate) mod util;
 
+/// The error for an op the CUDA backend cannot run yet.
+fn unsupported(op: &'static str) -> crate::Error {
+    crate::Error::UnsupportedOp {
+        op,
+        backend: "CUDA",
+    }
+}
+
 pub struct CudaRng(cudarc::curand::CudaRng);
 unsafe impl Send for CudaRng {}
 
@@ -43,9 +53,13 @@ pub struct CudaDevice {
     module_cache_order: Arc<Mutex<VecDeque<String>>>,
     streams: Arc<Vec<Arc<CudaStream>>>,
     stream_index: Arc<AtomicUsize>,
+    /// The compute capability, e.g. `sm_86`, which namespaces the on-disk PTX cache.
+    arch: String,
 }
 
 const MAX_CACHED_KERNELS: usize = 128;
+/// Environment variable overriding the root of the on-disk PTX cache.
+const PTX_CACHE_ENV: &str = "CONSTENSOR_PTX_CACHE";
 
 impl CudaDevice {
     pub(crate) fn new(ordinal: usize) -> Result<Self> {
@@ -58,6 +72,12 @@ impl CudaDevice {
         }
         let streams = Arc::new(pool);
         let stream_index = Arc::new(AtomicUsize::new(0));
+        let major = context
+            .attribute(CUdevice_attribute::CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MAJOR)
+            .w()?;
+        let minor = context
+            .attribute(CUdevice_attribute::CU_DEVICE_ATTRIBUTE_COMPUTE_CAPABILITY_MINOR)
+            .w()?;
         Ok(Self {
             context,
             stream,
@@ -65,6 +85,7 @@ impl CudaDevice {
             module_cache_order: Arc::new(Mutex::new(VecDeque::new())),
             streams,
             stream_index,
+            arch: format!("sm_{major}{minor}"),
         })
     }
 
@@ -78,6 +99,52 @@ impl CudaDevice {
         self.stream.clone()
     }
 
+    /// Block until all work queued on device `ordinal`, by any graph, has completed.
+    pub(crate) fn synchronize_ordinal(ordinal: usize) -> Result<()> {
+        cudarc::driver::CudaContext::new(ordinal)
+            .w()?
+            .synchronize()
+            .w()
+    }
+
+    /// The free and total memory of device `ordinal` in bytes, from `cuMemGetInfo`.
+    pub(crate) fn memory_info(ordinal: usize) -> Result<(usize, usize)> {
+        cudarc::driver::CudaContext::new(ordinal)
+            .w()?
+            .bind_to_thread()
+            .w()?;
+        cudarc::driver::result::mem_get_info().w()
+    }
+
+    /// Like [`BackendDevice::run_graph`], but copies the result into `out`'s existing buffer.
+    pub(crate) fn run_graph_into<S: Shape, T: DType, D: Dev>(
+        &self,
+        graph: &CompiledGraph<S, T, D>,
+        out: &mut CudaStorage<T>,
+    ) -> Result<()> {
+        let result = self.run_graph(graph)?;
+        let stream = self.stream();
+        // Neither a pending write to `out` nor the run may still be in flight when copying.
+        stream.wait(&out.event).w()?;
+        stream.wait(&result.event).w()?;
+        stream.memcpy_dtod(&result.slice, &mut out.slice).w()?;
+        out.event.record(&stream).w()?;
+        Ok(())
+    }
+
+    /// Copy host data into a new buffer on this device.
+    pub(crate) fn upload<T: DType>(&self, data: &[T]) -> Result<CudaStorage<T>> {
+        let stream = self.stream();
+        let slice = stream.memcpy_stod(data).w()?;
+        let event = self.context.new_event(None).w()?;
+        event.record(&stream).w()?;
+        Ok(CudaStorage {
+            slice,
+            device: self.clone(),
+            event,
+        })
+    }
+
     pub(crate) fn load_func(&self, function_name: &str, ptx: Ptx) -> Result<CudaFunction> {
         // If we've already loaded this kernel, skip reloading
         {
@@ -104,6 +171,57 @@ impl CudaDevice {
         }
         Ok(func)
     }
+
+    /// Load `function_name` from `template_kernel`, reusing PTX cached on disk by an earlier run.
+    ///
+    /// Cached files are keyed by the device's compute capability and a hash of the source. If one
+    /// fails to load, e.g. because it was truncated, it is deleted and the kernel recompiled.
+    pub(crate) fn load_or_compile_func(
+        &self,
+        function_name: &str,
+        template_kernel: String,
+    ) -> Result<CudaFunction> {
+        if let Some(module) = self.modules.read().unwrap().get(function_name) {
+            return module.load_function(function_name).w();
+        }
+
+        let path = self.ptx_cache_path(function_name, &template_kernel);
+        if let Some(path) = &path {
+            if let Ok(src) = fs::read_to_string(path) {
+                match self.load_func(function_name, Ptx::from_src(src)) {
+                    Ok(func) => return Ok(func),
+                    Err(_) => {
+                        let _ = fs::remove_file(path);
+                    }
+                }
+            }
+        }
+
+        let ptx = compile_ptx(template_kernel)?;
+        if let Some(path) = &path {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // Write to a temporary file first so a concurrent run never reads a partial file.
+            let tmp = path.with_extension(format!("ptx.{}.tmp", std::process::id()));
+            fs::write(&tmp, ptx.to_src())?;
+            fs::rename(&tmp, path)?;
+        }
+        self.load_func(function_name, ptx)
+    }
+
+    fn ptx_cache_path(&self, function_name: &str, template_kernel: &str) -> Option<PathBuf> {
+        let mut hasher = StableHasher::default();
+        template_kernel.hash(&mut hasher);
+        let root = match std::env::var_os(PTX_CACHE_ENV) {
+            Some(root) => PathBuf::from(root),
+            None => dirs::home_dir()?.join(".cache/constensor/ptx"),
+        };
+        Some(
+            root.join(&self.arch)
+                .join(format!("{function_name}_{:016x}.ptx", hasher.finish())),
+        )
+    }
 }
 
 impl Deref for CudaDevice {
@@ -121,8 +239,10 @@ pub struct CudaStorage<T: DType> {
 }
 
 impl<T: DType> BackendStorage<T> for CudaStorage<T> {
-    fn to_cpu_storage(&self) -> Result<Cow<CpuStorage<T>>> {
-        let data = self.device.stream().memcpy_dtov(&self.slice).w()?;
+    fn to_cpu_storage(&self) -> Result<Cow<'_, CpuStorage<T>>> {
+        let stream = self.device.stream();
+        stream.wait(&self.event).w()?;
+        let data = stream.memcpy_dtov(&self.slice).w()?;
         Ok(Cow::Owned(CpuStorage(data)))
     }
     fn cast<U: DType>(&self) -> Result<Storage<U>> {
@@ -155,29 +275,15 @@ impl<T: DType> BackendStorage<T> for CudaStorage<T> {
             U::C_NAME,
         );
 
-        // Always recompile PTX to avoid using stale cached files
-        let ptx = compile_ptx(template_kernel.clone())?;
-
-        let ptx_str = ptx.to_src();
-        if let Some(home) = dirs::home_dir() {
-            let path = format!(
-                "{}/.cache/constensor/ptx/{function_name}.ptx",
-                home.display()
-            );
-            let path = Path::new(&path);
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent)?;
-            }
-            fs::write(path, ptx_str)?;
-        }
+        let func = self
+            .device
+            .load_or_compile_func(&function_name, template_kernel)?;
 
         let stream = self.device.select_stream();
         let n_elems = self.slice.len();
 
         let out = unsafe { stream.alloc::<U>(n_elems) }.w()?;
 
-        let func = self.device.load_func(&function_name, ptx)?;
-
         let cfg = LaunchConfig::for_num_elems(n_elems as u32);
 
         let mut builder = stream.launch_builder(&func);
@@ -198,12 +304,55 @@ impl<T: DType> BackendStorage<T> for CudaStorage<T> {
     }
 }
 
+impl<T: DType> CudaStorage<T> {
+    /// Block until the kernel writing this storage has completed.
+    pub(crate) fn synchronize(&self) -> Result<()> {
+        self.event.synchronize().w()
+    }
+
+    /// Copy this buffer to `device`, directly between the GPUs if it lives on another one.
+    pub(crate) fn copy_to(&self, device: &CudaDevice) -> Result<CudaStorage<T>> {
+        let stream = device.stream();
+        let mut slice = unsafe { stream.alloc::<T>(self.slice.len()) }.w()?;
+        if self.device.context.ordinal() == device.context.ordinal() {
+            stream.wait(&self.event).w()?;
+            stream.memcpy_dtod(&self.slice, &mut slice).w()?;
+        } else {
+            // The event belongs to the other context, so wait for it on the host instead.
+            self.synchronize()?;
+            let src_stream = self.device.stream();
+            let (src, _record_src) = self.slice.device_ptr(&src_stream);
+            let (dst, _record_dst) = slice.device_ptr_mut(&stream);
+            unsafe {
+                cudarc::driver::sys::cuMemcpyPeerAsync(
+                    dst,
+                    device.context.cu_ctx(),
+                    src,
+                    self.device.context.cu_ctx(),
+                    self.slice.len() * std::mem::size_of::<T>(),
+                    stream.cu_stream(),
+                )
+            }
+            .result()
+            .w()?;
+        }
+        let event = device.context.new_event(None).w()?;
+        event.record(&stream).w()?;
+        Ok(CudaStorage {
+            slice,
+            device: device.clone(),
+            event,
+        })
+    }
+}
+
 pub enum CudaCompiledKernel<T: DType> {
     /// JIT‑compiled element‑wise kernel produced by `compile_kernel`.
     ElementWise {
         func: CudaFunction,
-        slice: CudaSlice<T>,
         shape: Vec<usize>,
+        /// Nodes computed by earlier kernels, passed in as input buffers.
+        inputs: Vec<usize>,
         order: usize,
     },
     /// Matrix–multiplication kernel to be executed through cuBLAS.
@@ -241,6 +390,12 @@ pub enum CudaCompiledKernel<T: DType> {
         elem_count: usize,
         order: usize,
     },
+    /// Host data, e.g. from constant folding, uploaded on every run.
+    Const {
+        data: Arc<Vec<T>>,
+        stream: Arc<CudaStream>,
+        order: usize,
+    },
 }
 
 #[derive(Debug)]
@@ -252,24 +407,31 @@ impl Name {
 }
 
 /// Can assume that the type T is available.
+///
+/// Nodes in `inputs` are read from the kernel's input buffers (`in0`, `in1`, ...) instead of being
+/// computed inline.
 fn handle_node<T: DType>(
     current_name: &mut usize,
     header: &mut String,
     op: &GraphNode<T>,
     graph: &[GraphNode<T>],
+    inputs: &[usize],
 ) -> String {
+    if let Some(pos) = inputs.iter().position(|&id| id == op.id.get()) {
+        return format!("(in{pos}[i])");
+    }
     match &op.op {
         Op::BinaryOp {
             l_id,
             r_id,
             operator,
         } => {
-            let l_name = handle_node(current_name, header, &graph[l_id.get()], graph);
-            let r_name = handle_node(current_name, header, &graph[r_id.get()], graph);
-            format!("({l_name} {} {r_name})", operator.as_c_op())
+            let l_name = handle_node(current_name, header, &graph[l_id.get()], graph, inputs);
+            let r_name = handle_node(current_name, header, &graph[r_id.get()], graph, inputs);
+            operator.fill_in_c_op::<T>(l_name, r_name)
         }
         Op::UnaryOp { v_id, operator } => {
-            let v_name = handle_node(current_name, header, &graph[v_id.get()], graph);
+            let v_name = handle_node(current_name, header, &graph[v_id.get()], graph, inputs);
             operator.fill_in_c_op(v_name)
         }
         Op::Fill { v } => {
@@ -285,40 +447,162 @@ fn handle_node<T: DType>(
         } => {
             *current_name += 1;
             let name = Name(*current_name);
+            // Evaluate in double like the CPU backend, so the values match for half precision and
+            // for integer types whose range does not cover every index.
             *header += &format!(
-                "T {} = static_cast<T>(i) * static_cast<T>({step:?}) + static_cast<T>({start:?});\n",
-                name.to_name()
+                "T {} = static_cast<T>(static_cast<double>(i) * {:?} + {:?});\n",
+                name.to_name(),
+                step,
+                start.to_f64()
             );
             format!("({})", name.to_name())
         }
         Op::FusedMulAdd { a_id, b_id, c_id } => {
-            let a_name = handle_node(current_name, header, &graph[a_id.get()], graph);
-            let b_name = handle_node(current_name, header, &graph[b_id.get()], graph);
-            let c_name = handle_node(current_name, header, &graph[c_id.get()], graph);
-            #[cfg(feature = "slow_integral_fma_cuda")]
+            let a_name = handle_node(current_name, header, &graph[a_id.get()], graph, inputs);
+            let b_name = handle_node(current_name, header, &graph[b_id.get()], graph, inputs);
+            let c_name = handle_node(current_name, header, &graph[c_id.get()], graph, inputs);
+            // A round trip through double is inexact for integers beyond 2^53, so they are
+            // multiplied and added exactly in their own type.
             if T::INTEGRAL {
                 use crate::graph::BinaryOpType;
-                let mul_op = BinaryOpType::Mul.to_c_op();
-                let add_op = BinaryOpType::Add.to_c_op();
+                let mul_op = BinaryOpType::Mul.as_c_op();
+                let add_op = BinaryOpType::Add.as_c_op();
                 format!("({a_name} {mul_op} {b_name} {add_op} {c_name})")
             } else {
                 format!("( static_cast<T>(fma(static_cast<double>({a_name}), static_cast<double>({b_name}), static_cast<double>({c_name}))))")
             }
-            #[cfg(not(feature = "slow_integral_fma_cuda"))]
-            format!("( static_cast<T>(fma(static_cast<double>({a_name}), static_cast<double>({b_name}), static_cast<double>({c_name}))))")
+        }
+        Op::NanToNum {
+            v_id,
+            nan,
+            posinf,
+            neginf,
+        } => {
+            let v_name = handle_node(current_name, header, &graph[v_id.get()], graph, inputs);
+            *current_name += 1;
+            let name = Name(*current_name).to_name();
+            *header += &format!("T {name} = {v_name};\n");
+            format!(
+                "(isnan(static_cast<double>({name})) ? static_cast<T>({nan:?}) : isinf(static_cast<double>({name})) ? ({name} > static_cast<T>(0) ? static_cast<T>({posinf:?}) : static_cast<T>({neginf:?})) : {name})"
+            )
+        }
+        Op::Clamp { v_id, min, max } => {
+            let v_name = handle_node(current_name, header, &graph[v_id.get()], graph, inputs);
+            *current_name += 1;
+            let name = Name(*current_name).to_name();
+            *header += &format!("T {name} = {v_name};\n");
+            format!(
+                "({name} < static_cast<T>({min:?}) ? static_cast<T>({min:?}) : {name} > static_cast<T>({max:?}) ? static_cast<T>({max:?}) : {name})"
+            )
+        }
+        Op::MaskedFill {
+            v_id,
+            mask_id,
+            value,
+        } => {
+            let v_name = handle_node(current_name, header, &graph[v_id.get()], graph, inputs);
+            let m_name = handle_node(current_name, header, &graph[mask_id.get()], graph, inputs);
+            format!("({m_name} != static_cast<T>(0) ? static_cast<T>({value:?}) : {v_name})")
+        }
+        Op::Triangular {
+            v_id,
+            upper,
+            diagonal,
+        } => {
+            let v_name = handle_node(current_name, header, &graph[v_id.get()], graph, inputs);
+            let (m, n) = (op.shape[op.shape.len() - 2], op.shape[op.shape.len() - 1]);
+            let cmp = if *upper { ">=" } else { "<=" };
+            format!(
+                "((static_cast<long long>(i % {n}) - static_cast<long long>((i / {n}) % {m}) {cmp} {diagonal}LL) ? {v_name} : static_cast<T>(0))"
+            )
         }
         Op::NoOp => unreachable!("no-op ops should never be reached."),
         Op::Permute { v_id } => {
-            let name = handle_node(current_name, header, &graph[v_id.get()], graph);
+            let name = handle_node(current_name, header, &graph[v_id.get()], graph, inputs);
             format!("({})", name)
         }
-        Op::MatMul { .. } | Op::Rand | Op::Randn { .. } => {
-            unreachable!("op should have its own split!")
+        // Only reached for contiguous inputs, see `compile`.
+        Op::Contiguous { v_id } => {
+            handle_node(current_name, header, &graph[v_id.get()], graph, inputs)
+        }
+        Op::Eye => {
+            let n = op.shape[1];
+            *current_name += 1;
+            let name = Name(*current_name);
+            *header += &format!(
+                "T {} = (i / {n} == i % {n}) ? static_cast<T>(1) : static_cast<T>(0);\n",
+                name.to_name()
+            );
+            format!("({})", name.to_name())
+        }
+        Op::MatMul { .. } | Op::Rand | Op::Randn { .. } | Op::Const { .. } => {
+            unreachable!("op has its own kernel and is passed in as an input")
+        }
+        Op::Stack { .. }
+        | Op::Concat { .. }
+        | Op::Slice { .. }
+        | Op::Roll { .. }
+        | Op::Flip { .. }
+        | Op::Diagonal { .. }
+        | Op::Broadcast { .. }
+        | Op::Pad { .. }
+        | Op::Gather { .. }
+        | Op::Scatter { .. }
+        | Op::Reduce { .. }
+        | Op::ArgReduce { .. }
+        | Op::CustomUnary { .. } => {
+            unreachable!("op is rejected during compilation")
+        }
+    }
+}
+
+/// The nodes that the element-wise kernel computing `root` reads from buffers, in first-use order.
+///
+/// These are the nodes marked in `is_buffer` that `root` depends on without passing through another
+/// such node.
+fn kernel_inputs<T: DType>(root: usize, graph: &[GraphNode<T>], is_buffer: &[bool]) -> Vec<usize> {
+    fn visit<T: DType>(
+        idx: usize,
+        graph: &[GraphNode<T>],
+        is_buffer: &[bool],
+        inputs: &mut Vec<usize>,
+    ) {
+        for src in graph[idx].op.input_ids() {
+            let src = src.get();
+            if is_buffer[src] {
+                if !inputs.contains(&src) {
+                    inputs.push(src);
+                }
+            } else {
+                visit(src, graph, is_buffer, inputs);
+            }
+        }
+    }
+    let mut inputs = Vec::new();
+    visit(root, graph, is_buffer, &mut inputs);
+    inputs
+}
+
+/// `root` and the nodes inlined into the kernel computing it, in graph order.
+fn fused_nodes<T: DType>(root: usize, graph: &[GraphNode<T>], is_buffer: &[bool]) -> Vec<usize> {
+    let mut nodes = vec![root];
+    let mut stack = vec![root];
+    while let Some(idx) = stack.pop() {
+        for src in graph[idx].op.input_ids() {
+            let src = src.get();
+            if !is_buffer[src] && !nodes.contains(&src) {
+                nodes.push(src);
+                stack.push(src);
+            }
         }
     }
+    nodes.sort_unstable();
+    nodes
 }
 
-fn cuda_include_dir() -> Option<PathBuf> {
+/// The first of the CUDA toolkit roots from the environment or the usual install locations
+/// that has the headers.
+fn cuda_include_dir() -> Result<PathBuf> {
     // NOTE: copied from cudarc build.rs.
     let env_vars = [
         "CUDA_PATH",
@@ -326,7 +610,6 @@ fn cuda_include_dir() -> Option<PathBuf> {
         "CUDA_TOOLKIT_ROOT_DIR",
         "CUDNN_LIB",
     ];
-    #[allow(unused)]
     let env_vars = env_vars
         .into_iter()
         .map(std::env::var)
@@ -342,12 +625,22 @@ fn cuda_include_dir() -> Option<PathBuf> {
         "C:/CUDA",
     ];
 
-    #[allow(unused)]
     let roots = roots.into_iter().map(Into::<PathBuf>::into);
 
-    env_vars
-        .chain(roots)
+    find_include_dir(env_vars.chain(roots).collect())
+}
+
+fn find_include_dir(candidates: Vec<PathBuf>) -> Result<PathBuf> {
+    match candidates
+        .iter()
         .find(|path| path.join("include").join("cuda.h").is_file())
+    {
+        Some(path) => Ok(path.clone()),
+        None => Err(error::CudaError::MissingHeaders {
+            searched: candidates,
+        }
+        .into()),
+    }
 }
 
 fn compile_ptx(template_kernel: String) -> Result<Ptx> {
@@ -356,11 +649,7 @@ fn compile_ptx(template_kernel: String) -> Result<Ptx> {
         // Compile PTX without hardcoding an architecture so it can JIT to the current device
         CompileOptions {
             use_fast_math: Some(true),
-            include_paths: vec![cuda_include_dir()
-                .unwrap()
-                .join("include")
-                .display()
-                .to_string()],
+            include_paths: vec![cuda_include_dir()?.join("include").display().to_string()],
             ..Default::default()
         },
     )
@@ -371,25 +660,37 @@ impl CudaDevice {
     fn run_kernel<T: DType>(
         &self,
         func: &CudaFunction,
-        data: &CudaSlice<T>,
         shape: &[usize],
+        inputs: &[&CudaStorage<T>],
     ) -> Result<CudaStorage<T>> {
         let n_elems: usize = shape.iter().product();
         let stream = self.select_stream();
 
+        // Order this kernel after the ones producing its inputs, without blocking the host
+        for input in inputs {
+            stream.wait(&input.event).w()?;
+        }
+
         let cfg = LaunchConfig::for_num_elems(n_elems as u32);
 
-        let mut builder = stream.launch_builder(func);
-        builder.arg(data);
-        builder.arg(&n_elems);
-        unsafe { builder.launch(cfg).w()? };
+        // Every run writes a buffer of its own, so concurrent runs of one graph do not race.
+        let mut out = unsafe { stream.alloc::<T>(n_elems) }.w()?;
+        {
+            let mut builder = stream.launch_builder(func);
+            builder.arg(&mut out);
+            for input in inputs {
+                builder.arg(&input.slice);
+            }
+            builder.arg(&n_elems);
+            unsafe { builder.launch(cfg).w()? };
+        }
 
         // Record an event once this kernel completes
         let event = self.context.new_event(None).w()?;
         event.record(&stream).w()?;
 
         Ok(CudaStorage {
-            slice: data.clone(),
+            slice: out,
             device: self.clone(),
             event,
         })
@@ -399,20 +700,26 @@ impl CudaDevice {
         &self,
         header: String,
         body: String,
-        shape: Vec<usize>,
-    ) -> Result<(CudaFunction, CudaSlice<T>)> {
+        n_inputs: usize,
+    ) -> Result<CudaFunction> {
         // Module name is based on hash of body and header
-        let mut hasher = DefaultHasher::new();
+        let mut hasher = StableHasher::default();
         body.hash(&mut hasher);
         header.hash(&mut hasher);
         let function_name = format!("jit_kernel_{}_{}", hasher.finish(), T::NAME);
+        let params = (0..n_inputs)
+            .map(|i| format!("const T *in{i}, "))
+            .collect::<String>();
+        let c_params = (0..n_inputs)
+            .map(|i| format!("const {} *in{i}, ", T::C_NAME))
+            .collect::<String>();
+        let args = (0..n_inputs)
+            .map(|i| format!("in{i}, "))
+            .collect::<String>();
 
         // If we've already compiled this kernel, skip PTX compilation
         if let Some(module) = self.modules.read().unwrap().get(&function_name) {
-            let func = module.load_function(&function_name).w()?;
-            let n_elems: usize = shape.iter().product();
-            let data = unsafe { self.stream.alloc::<T>(n_elems) }.w()?;
-            return Ok((func, data));
+            return module.load_function(&function_name).w();
         }
 
         let template_kernel = format!(
@@ -423,7 +730,7 @@ impl CudaDevice {
             {}
 
             template <typename T>
-            __device__ void {function_name}_kernel(T *buf, const size_t numel) {{
+            __device__ void {function_name}_kernel(T *buf, {params}const size_t numel) {{
                 for (unsigned int i = blockIdx.x * blockDim.x + threadIdx.x; i < numel;
                     i += blockDim.x * gridDim.x) {{
                     {header}
@@ -431,8 +738,8 @@ impl CudaDevice {
                 }}
             }}
             
-            extern "C" __global__ void {function_name}({} *buf, const size_t numel) {{
-                {function_name}_kernel(buf, numel);
+            extern "C" __global__ void {function_name}({} *buf, {c_params}const size_t numel) {{
+                {function_name}_kernel(buf, {args}numel);
             }}
 
             "#,
@@ -440,30 +747,7 @@ impl CudaDevice {
             T::C_NAME,
         );
 
-        // Always recompile PTX to avoid using stale cached files
-        let ptx = compile_ptx(template_kernel.clone())?;
-
-        let ptx_str = ptx.to_src();
-        if let Some(home) = dirs::home_dir() {
-            let path = format!(
-                "{}/.cache/constensor/ptx/{function_name}.ptx",
-                home.display()
-            );
-            let path = Path::new(&path);
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent)?;
-            }
-            fs::write(path, ptx_str)?;
-        }
-
-        let n_elems = shape.iter().product();
-        let stream = self.stream();
-
-        let data = unsafe { stream.alloc::<T>(n_elems) }.w()?;
-
-        let func = self.load_func(&function_name, ptx)?;
-
-        Ok((func, data))
+        self.load_or_compile_func(&function_name, template_kernel)
     }
 }
 
@@ -482,44 +766,73 @@ impl BackendDevice for CudaDevice {
 
         for (idx, node) in graph.iter().enumerate() {
             match &node.op {
-                Op::BinaryOp { l_id, r_id, .. } => {
-                    dep_graph.add_edge(l_id.get(), idx, ());
-                    dep_graph.add_edge(r_id.get(), idx, ());
-                }
-                Op::UnaryOp { v_id, .. } => {
-                    dep_graph.add_edge(v_id.get(), idx, ());
-                }
-                Op::FusedMulAdd { a_id, b_id, c_id } => {
-                    dep_graph.add_edge(a_id.get(), idx, ());
-                    dep_graph.add_edge(b_id.get(), idx, ());
-                    dep_graph.add_edge(c_id.get(), idx, ());
-                }
-                Op::MatMul {
-                    l_id, r_id, o_id, ..
-                } => {
-                    dep_graph.add_edge(l_id.get(), idx, ());
-                    dep_graph.add_edge(r_id.get(), idx, ());
-                    if let Some(o_id) = o_id {
-                        dep_graph.add_edge(o_id.get(), idx, ());
+                Op::Stack { .. } => return Err(unsupported("Stack")),
+                Op::Concat { .. } => return Err(unsupported("Concat")),
+                Op::Slice { .. } => return Err(unsupported("Slice")),
+                Op::Roll { .. } => return Err(unsupported("Roll")),
+                Op::Flip { .. } => return Err(unsupported("Flip")),
+                Op::Diagonal { .. } => return Err(unsupported("Diagonal")),
+                Op::Broadcast { .. } => return Err(unsupported("Broadcast")),
+                Op::Pad { .. } => return Err(unsupported("Pad")),
+                Op::Gather { .. } => return Err(unsupported("Gather")),
+                Op::Scatter { .. } => return Err(unsupported("Scatter")),
+                Op::Reduce { .. } => return Err(unsupported("Reduce")),
+                Op::ArgReduce { .. } => return Err(unsupported("ArgReduce")),
+                // A Rust function cannot be compiled into a kernel.
+                Op::CustomUnary { .. } => return Err(unsupported("CustomUnary")),
+                // Kernels index every input as row-major, so only a no-op copy can be fused.
+                Op::Contiguous { v_id } => {
+                    let src = &graph[v_id.get()];
+                    if !is_contiguous_strides(&src.shape, &src.strides) {
+                        return Err(unsupported("Contiguous of a strided view"));
                     }
                 }
-                Op::Permute { v_id } => {
-                    dep_graph.add_edge(v_id.get(), idx, ());
-                }
-                // These don’t create incoming edges
-                Op::NoOp | Op::Fill { .. } | Op::Rand | Op::Randn { .. } | Op::Arange { .. } => {}
+                _ => {}
+            }
+            for src in node.op.input_ids() {
+                dep_graph.add_edge(src.get(), idx, ());
             }
         }
 
         // Compute topological order
         let order = toposort(&dep_graph, None).expect("Cycle detected in graph!");
 
-        // New kernel and grouping logic with matmul input tracking
+        // Nodes computed by their own kernel into a buffer, which later kernels read as inputs.
+        // Everything else is element-wise and inlined into the kernel of each buffer needing it.
+        let mut consumers = vec![Vec::new(); graph.len()];
+        for (idx, node) in graph.iter().enumerate() {
+            for src in node.op.input_ids() {
+                consumers[src.get()].push(idx);
+            }
+        }
+        let last = graph.len() - 1;
+        let is_buffer = graph
+            .iter()
+            .enumerate()
+            .map(|(idx, node)| {
+                let has_own_kernel = |op: &Op<T>| {
+                    matches!(
+                        op,
+                        Op::MatMul { .. } | Op::Rand | Op::Randn { .. } | Op::Const { .. }
+                    )
+                };
+                let consumers = &consumers[idx];
+                has_own_kernel(&node.op)
+                    || idx == last
+                    || consumers.iter().any(|&c| {
+                        has_own_kernel(&graph[c].op) || graph[c].shape != node.shape
+                    })
+                    // Shared intermediates are computed once, but cheap init ops are recomputed.
+                    || (consumers.len() > 1 && !node.op.input_ids().is_empty())
+            })
+            .collect::<Vec<_>>();
+
         let mut kernels = Vec::<CudaCompiledKernel<T>>::new();
-        let mut matmuls = Vec::<CudaCompiledKernel<T>>::new();
-        let mut splits: Vec<(Vec<usize>, Vec<usize>)> = Vec::new();
+        let mut plan = Vec::new();
 
         for &idx in &order {
+            let launched = kernels.len();
+            let mut nodes = vec![idx];
             match &graph[idx].op {
                 Op::MatMul {
                     l_id,
@@ -529,22 +842,17 @@ impl BackendDevice for CudaDevice {
                     alpha,
                     beta,
                 } => {
-                    let l_shape = &graph[l_id.get()].shape;
-                    let r_shape = &graph[r_id.get()].shape;
                     let l_stride = &graph[l_id.get()].strides;
                     let r_stride = &graph[r_id.get()].strides;
-                    assert_eq!(l_shape.len(), 3);
-                    assert_eq!(r_shape.len(), 3);
-                    assert_eq!(l_stride.len(), 3);
-                    assert_eq!(r_stride.len(), 3);
-                    let (b, m, _k) = (l_shape[0], l_shape[1], l_shape[2]);
-                    let n = r_shape[2];
+                    // The output may be larger than the product, see `Op::MatMul`.
+                    let [b, m, n, _k] =
+                        matmul_dims(&graph[l_id.get()].shape, &graph[r_id.get()].shape, None)?;
 
                     // Select our stream
                     let stream = self.select_stream();
                     let cublas = CudaBlas::new(stream.clone()).unwrap();
 
-                    matmuls.push(CudaCompiledKernel::MatMul {
+                    kernels.push(CudaCompiledKernel::MatMul {
                         l_id: l_id.get(),
                         r_id: r_id.get(),
                         o_id: o_id.as_ref().map(|id| id.get()),
@@ -568,7 +876,7 @@ impl BackendDevice for CudaDevice {
                         cudarc::curand::CudaRng::new(0, stream.clone()).w()?,
                     )));
 
-                    matmuls.push(CudaCompiledKernel::Rand {
+                    kernels.push(CudaCompiledKernel::Rand {
                         rng: curand,
                         stream,
                         elem_count: graph[idx].shape.iter().product(),
@@ -581,7 +889,7 @@ impl BackendDevice for CudaDevice {
                         cudarc::curand::CudaRng::new(0, stream.clone()).w()?,
                     )));
 
-                    matmuls.push(CudaCompiledKernel::Randn {
+                    kernels.push(CudaCompiledKernel::Randn {
                         mean: *mean,
                         std: *std,
                         rng: curand,
@@ -590,69 +898,40 @@ impl BackendDevice for CudaDevice {
                         order: idx,
                     });
                 }
-                _ => {
-                    let shape_key = graph[idx].shape.clone();
-                    // Group only when same shape and this op depends on the last split node
-                    let should_group = if let Some((last_group, _)) = splits.last_mut() {
-                        let last_idx = *last_group.last().unwrap();
-                        if graph[last_idx].shape == shape_key {
-                            match &graph[idx].op {
-                                Op::BinaryOp { l_id, r_id, .. } => {
-                                    l_id.get() == last_idx || r_id.get() == last_idx
-                                }
-                                Op::UnaryOp { v_id, .. } => v_id.get() == last_idx,
-                                Op::FusedMulAdd { a_id, b_id, c_id } => {
-                                    a_id.get() == last_idx
-                                        || b_id.get() == last_idx
-                                        || c_id.get() == last_idx
-                                }
-                                Op::Permute { v_id } => v_id.get() == last_idx,
-                                // Init ops always start new group
-                                Op::NoOp
-                                | Op::Fill { .. }
-                                | Op::Arange { .. }
-                                | Op::Rand
-                                | Op::Randn { .. }
-                                | Op::MatMul { .. } => false,
-                            }
-                        } else {
-                            false
-                        }
-                    } else {
-                        false
-                    };
-                    if should_group {
-                        splits.last_mut().unwrap().0.push(idx);
-                    } else {
-                        splits.push((vec![idx], shape_key));
-                    }
+                Op::Const { data } => kernels.push(CudaCompiledKernel::Const {
+                    data: data.clone(),
+                    stream: self.select_stream(),
+                    order: idx,
+                }),
+                _ if is_buffer[idx] => {
+                    let inputs = kernel_inputs(idx, &graph, &is_buffer);
+                    let mut header = String::new();
+                    let body = handle_node(&mut 0, &mut header, &graph[idx], &graph, &inputs);
+                    let shape = graph[idx].shape.clone();
+                    let func = self.compile_kernel::<T>(header, body, inputs.len())?;
+                    kernels.push(CudaCompiledKernel::ElementWise {
+                        func,
+                        shape,
+                        inputs,
+                        order: idx,
+                    });
+                    nodes = fused_nodes(idx, &graph, &is_buffer);
                 }
+                // Inlined into the kernels of the buffers that depend on it
+                _ => {}
+            }
+            if kernels.len() > launched {
+                plan.push(KernelInfo {
+                    nodes,
+                    output: idx,
+                    shape: graph[idx].shape.clone(),
+                });
             }
         }
 
-        // Compile element‑wise splits first so matmul inputs are ready
-        for (sub_order, shape) in splits {
-            let mut header = String::new();
-            let body = handle_node(
-                &mut 0,
-                &mut header,
-                &graph[*sub_order.last().unwrap()],
-                &graph,
-            );
-            let (func, slice) =
-                self.compile_kernel::<T>(header.clone(), body.clone(), shape.clone())?;
-            kernels.push(CudaCompiledKernel::ElementWise {
-                func,
-                slice,
-                shape,
-                order: *sub_order.iter().max().unwrap(),
-            });
-        }
-        // Then append all MatMul kernels
-        kernels.extend(matmuls);
-
         Ok(CompiledGraph::Cuda {
             kernels,
+            plan,
             ghost: PhantomData,
         })
     }
@@ -662,7 +941,7 @@ impl BackendDevice for CudaDevice {
         graph: &CompiledGraph<S, T, D>,
     ) -> Result<Self::Storage<T>> {
         #[allow(irrefutable_let_patterns)]
-        let CompiledGraph::Cuda { kernels, ghost: _ } = graph
+        let CompiledGraph::Cuda { kernels, .. } = graph
         else {
             unreachable!()
         };
@@ -673,11 +952,15 @@ impl BackendDevice for CudaDevice {
             match kernel {
                 CudaCompiledKernel::ElementWise {
                     func,
-                    slice,
                     shape,
+                    inputs,
                     order,
                 } => {
-                    let storage = self.run_kernel::<T>(func, slice, shape)?;
+                    let inputs = inputs
+                        .iter()
+                        .map(|id| last_storage.get(id).expect("input storage missing"))
+                        .collect::<Vec<_>>();
+                    let storage = self.run_kernel::<T>(func, shape, &inputs)?;
                     last_storage.insert(order, storage);
                 }
                 CudaCompiledKernel::MatMul {
@@ -701,19 +984,21 @@ impl BackendDevice for CudaDevice {
                     let lhs = last_storage.get(&l_id).expect("lhs storage missing");
                     let rhs = last_storage.get(&r_id).expect("rhs storage missing");
 
-                    // Wait for prior kernels
-                    lhs.event.synchronize().w()?;
-                    rhs.event.synchronize().w()?;
+                    // Order the GEMM after prior kernels, without blocking the host
+                    stream.wait(&lhs.event).w()?;
+                    stream.wait(&rhs.event).w()?;
 
-                    let elems = b * m * n;
                     // prepare output buffer, copy initial if provided
-                    let mut out = unsafe { stream.alloc::<T>(elems) }.w()?;
-                    if let Some(o_idx) = o_id {
+                    let mut out = if let Some(o_idx) = o_id {
                         let init = last_storage.get(&o_idx).expect("output storage missing");
                         // ensure the initial output is ready
-                        init.event.synchronize().w()?;
-                        self.stream().memcpy_dtod(&init.slice, &mut out).w()?;
-                    }
+                        stream.wait(&init.event).w()?;
+                        let mut out = unsafe { stream.alloc::<T>(init.slice.len()) }.w()?;
+                        stream.memcpy_dtod(&init.slice, &mut out).w()?;
+                        out
+                    } else {
+                        unsafe { stream.alloc::<T>(b * m * n) }.w()?
+                    };
 
                     let o_stride = o_stride
                         .clone()
@@ -771,6 +1056,22 @@ impl BackendDevice for CudaDevice {
                     let event = self.context.new_event(None).w()?;
                     event.record(stream).w()?;
 
+                    let storage = CudaStorage {
+                        slice,
+                        device: self.clone(),
+                        event,
+                    };
+                    last_storage.insert(order, storage);
+                }
+                CudaCompiledKernel::Const {
+                    data,
+                    stream,
+                    order,
+                } => {
+                    let slice = stream.memcpy_stod(data.as_slice()).w()?;
+                    let event = self.context.new_event(None).w()?;
+                    event.record(stream).w()?;
+
                     let storage = CudaStorage {
                         slice,
                         device: self.clone(),
@@ -781,7 +1082,22 @@ impl BackendDevice for CudaDevice {
             }
         }
 
-        let key = *last_storage.keys().max().unwrap();
+        // The output is the last node, which always has its own kernel
+        let key = *last_storage.keys().max().ok_or(crate::Error::EmptyGraph)?;
         Ok(last_storage.remove(&key).unwrap())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    #[test]
+    fn missing_headers_lists_searched_paths() {
+        let searched = PathBuf::from("/nonexistent/cuda");
+        let err = super::find_include_dir(vec![searched]).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.contains("/nonexistent/cuda"), "{msg}");
+        assert!(msg.contains("cuda.h"), "{msg}");
+    }
+}
@@ -51,6 +51,23 @@ pub(crate) fn gemm_config<T>(
             mnk: (m, n, k),
         })?
     };
+    // The output rows may be further apart than `n`, e.g. when writing into a larger buffer.
+    let out_m1 = out_stride[out_stride.len() - 1];
+    let out_m2 = out_stride[out_stride.len() - 2];
+    let ldc = if (out_m1 == 1 || n == 1) && (out_m2 >= n || m == 1) {
+        if m == 1 {
+            n
+        } else {
+            out_m2
+        }
+    } else {
+        Err(Error::MatMulNonContiguous {
+            lhs_stride: lhs_stride.to_vec(),
+            rhs_stride: rhs_stride.to_vec(),
+            out_stride: out_stride.to_vec(),
+            mnk: (m, n, k),
+        })?
+    };
     // The setup below was copied from:
     // https://github.com/lebedov/scikit-cuda/blob/7e7300474286019c917a6c8a4bca59405c64fbce/tests/test_cublas.py#L531
     let gemm = GemmConfig {
@@ -61,7 +78,7 @@ pub(crate) fn gemm_config<T>(
         k: k as i32,
         lda,
         ldb,
-        ldc: n as i32,
+        ldc: ldc as i32,
         transa,
         transb,
     };
@@ -3,27 +3,73 @@ use crate::cuda_backend::CudaDevice;
 use crate::{
     cpu_storage::CpuDevice,
     storage::{BackendDevice, Storage},
-    CompiledGraph, DType, GraphNode, Result, Shape,
+    CompiledGraph, DType, GraphNode, ProfileReport, Result, RunStats, Shape,
 };
 
-/// Marker trait for devices
-pub trait Dev: Clone {
+/// Marker trait for devices. Devices are plain markers, so compiled graphs and tensors can be
+/// shared between threads.
+pub trait Dev: Clone + Send + Sync {
     fn resolve() -> Result<Device>;
 }
 
+/// The CPU backend, which evaluates graphs on rayon threads.
+///
+/// Results are identical bit for bit across runs, machines and thread counts by default. Passing
+/// [`crate::ReduceMode::Fast`] to [`CompiledGraph::with_reduce_mode`] splits long float sums per
+/// thread instead, which is faster but rounds differently with the size of the pool.
 #[derive(Clone)]
 pub struct Cpu;
 
+impl Cpu {
+    /// Bytes of buffers kept cached for reuse by later runs, summed over every CPU graph that
+    /// is compiled and not yet dropped.
+    pub fn pool_capacity_bytes() -> usize {
+        CpuDevice.pool_capacity_bytes()
+    }
+
+    /// Drop the buffers every compiled CPU graph keeps cached for reuse by later runs, e.g. to
+    /// return memory after a burst of large graphs.
+    pub fn clear_pool() {
+        CpuDevice.clear_pool()
+    }
+
+    /// Cap the buffers each CPU graph compiled from now on keeps cached between runs at `bytes`.
+    /// Without a cap, `CONSTENSOR_CPU_POOL_MAX_BYTES` is read, defaulting to 4GB.
+    ///
+    /// The setting is process-wide, since graphs name the device only by type.
+    pub fn with_pool_max_bytes(self, bytes: usize) -> Self {
+        CpuDevice.set_pool_max_bytes(bytes);
+        self
+    }
+}
+
 impl Dev for Cpu {
     fn resolve() -> Result<Device> {
         Ok(Device::Cpu)
     }
 }
 
+/// A CUDA GPU. Compiled kernels are cached as PTX in `~/.cache/constensor/ptx`, or in the
+/// directory named by `CONSTENSOR_PTX_CACHE` if it is set.
 #[cfg(feature = "cuda")]
 #[derive(Clone)]
 pub struct Cuda<const ORD: usize>;
 
+#[cfg(feature = "cuda")]
+impl<const ORD: usize> Cuda<ORD> {
+    /// Block until all work queued on this device has completed, e.g. after several
+    /// [`CompiledGraph::run_async`] calls.
+    pub fn synchronize() -> Result<()> {
+        CudaDevice::synchronize_ordinal(ORD)
+    }
+
+    /// The `(free, total)` memory of this device in bytes, e.g. to size batches so a long
+    /// pipeline does not run out of memory. Other processes may allocate concurrently.
+    pub fn memory_info() -> Result<(usize, usize)> {
+        CudaDevice::memory_info(ORD)
+    }
+}
+
 #[cfg(feature = "cuda")]
 macro_rules! cuda_device {
     ($ord:expr) => {
@@ -71,6 +117,15 @@ pub enum Device {
 }
 
 impl Device {
+    /// Store row-major host data on this device.
+    pub(crate) fn upload<T: DType>(&self, data: Vec<T>) -> Result<Storage<T>> {
+        match self {
+            #[cfg(feature = "cuda")]
+            Self::Cuda(cuda) => Ok(Storage::Cuda(cuda.upload(&data)?)),
+            Self::Cpu => Ok(Storage::Cpu(crate::cpu_storage::CpuStorage(data))),
+        }
+    }
+
     pub fn run_graph<S: Shape, T: DType, D: Dev>(
         &self,
         graph: &CompiledGraph<S, T, D>,
@@ -82,6 +137,107 @@ impl Device {
         }
     }
 
+    /// Run `graph`, writing the result into the existing buffer `out`.
+    pub fn run_graph_into<S: Shape, T: DType, D: Dev>(
+        &self,
+        graph: &CompiledGraph<S, T, D>,
+        out: &mut Storage<T>,
+    ) -> Result<()> {
+        match (self, out) {
+            #[cfg(feature = "cuda")]
+            (Self::Cuda(cuda), Storage::Cuda(out)) => cuda.run_graph_into::<S, T, D>(graph, out),
+            (Self::Cpu, Storage::Cpu(out)) => CpuDevice.run_graph_into::<S, T, D>(graph, out),
+            #[cfg(feature = "cuda")]
+            _ => crate::bail!("run_into requires a tensor on the device the graph runs on"),
+        }
+    }
+
+    /// Run `graph` and report how buffers were allocated. Only the CPU backend tracks this.
+    pub fn run_graph_tracked<S: Shape, T: DType, D: Dev>(
+        &self,
+        graph: &CompiledGraph<S, T, D>,
+    ) -> Result<(Storage<T>, RunStats)> {
+        match self {
+            #[cfg(feature = "cuda")]
+            Self::Cuda(_) => Err(crate::Error::UnsupportedOp {
+                op: "Allocation tracking",
+                backend: "CUDA",
+            }),
+            Self::Cpu => {
+                let (storage, stats) = CpuDevice.run_graph_tracked::<S, T, D>(graph)?;
+                Ok((Storage::Cpu(storage), stats))
+            }
+        }
+    }
+
+    /// Run `graph` a tile of `tile_elems` output elements at a time. Only the CPU backend tiles.
+    pub fn run_graph_tiled<S: Shape, T: DType, D: Dev>(
+        &self,
+        graph: &CompiledGraph<S, T, D>,
+        tile_elems: usize,
+    ) -> Result<Storage<T>> {
+        match self {
+            #[cfg(feature = "cuda")]
+            Self::Cuda(_) => Err(crate::Error::UnsupportedOp {
+                op: "Tiled runs",
+                backend: "CUDA",
+            }),
+            Self::Cpu => Ok(Storage::Cpu(
+                CpuDevice.run_graph_tiled::<S, T, D>(graph, tile_elems)?,
+            )),
+        }
+    }
+
+    /// Run `graph` and time each node. Only the CPU backend profiles runs.
+    pub fn run_graph_profiled<S: Shape, T: DType, D: Dev>(
+        &self,
+        graph: &CompiledGraph<S, T, D>,
+    ) -> Result<(Storage<T>, ProfileReport)> {
+        match self {
+            #[cfg(feature = "cuda")]
+            Self::Cuda(_) => Err(crate::Error::UnsupportedOp {
+                op: "Profiling",
+                backend: "CUDA",
+            }),
+            Self::Cpu => {
+                let (storage, report) = CpuDevice.run_graph_profiled::<S, T, D>(graph)?;
+                Ok((Storage::Cpu(storage), report))
+            }
+        }
+    }
+
+    /// Check that a graph compiled for several outputs can run on this device.
+    pub fn check_multi<T: DType>(&self, _graph: &[GraphNode<T>]) -> Result<()> {
+        match self {
+            #[cfg(feature = "cuda")]
+            Self::Cuda(_) => Err(crate::Error::UnsupportedOp {
+                op: "Multi-output graphs",
+                backend: "CUDA",
+            }),
+            Self::Cpu => Ok(()),
+        }
+    }
+
+    /// Evaluate `graph` and return the storage of each node in `outputs`.
+    pub fn run_nodes<T: DType>(
+        &self,
+        graph: &[GraphNode<T>],
+        outputs: &[usize],
+    ) -> Result<Vec<Storage<T>>> {
+        match self {
+            #[cfg(feature = "cuda")]
+            Self::Cuda(_) => Err(crate::Error::UnsupportedOp {
+                op: "Multi-output graphs",
+                backend: "CUDA",
+            }),
+            Self::Cpu => Ok(CpuDevice
+                .run_nodes(graph, outputs)?
+                .into_iter()
+                .map(Storage::Cpu)
+                .collect()),
+        }
+    }
+
     pub fn compile<S: Shape, T: DType, D: Dev>(
         &self,
         graph: Vec<GraphNode<T>>,
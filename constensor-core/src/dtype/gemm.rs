@@ -11,6 +11,8 @@ pub trait GemmDispatch {
 
     #[allow(clippy::too_many_arguments)]
     // Matrix multiplication: (B x M x K) * (B x K x N) = (B x M x N)
+    // Every operand is read or written through its batch, row and column strides, so `out` may
+    // be a block of a larger buffer.
     fn launch_gemm(
         lhs: &[Self],
         lhs_stride: &[usize],
@@ -133,7 +135,8 @@ macro_rules! instantiate_gemm_cuda {
 }
 
 macro_rules! instantiate_gemm {
-    ($rt:ident, $init:expr, NAIVE) => {
+    // Naive triple loop summing in the wider `$acc`, then rounding once to `$rt`
+    ($rt:ident, $acc:ty, NAIVE) => {
         impl GemmDispatch for $rt {
             fn launch_gemm(
                 lhs: &[Self],
@@ -163,17 +166,25 @@ macro_rules! instantiate_gemm {
                 let out_rs = out_stride[1];
                 let out_cs = out_stride[2];
 
+                let (alpha, beta) = (<$acc>::from(alpha), <$acc>::from(beta));
                 for batch_idx in 0..b {
                     for i in 0..m {
                         for j in 0..n {
-                            let mut sum = $init;
+                            let mut sum = <$acc>::default();
                             for p in 0..k {
                                 let lhs_val = lhs[batch_idx * lhs_bs + i * lhs_rs + p * lhs_cs];
                                 let rhs_val = rhs[batch_idx * rhs_bs + p * rhs_rs + j * rhs_cs];
-                                sum += beta * lhs_val * rhs_val;
+                                sum += <$acc>::from(lhs_val) * <$acc>::from(rhs_val);
                             }
                             let out_idx = batch_idx * out_bs + i * out_rs + j * out_cs;
-                            out[out_idx] = alpha * out[out_idx] + sum;
+                            // Like `gemm`, only read the destination when it contributes, as it
+                            // may hold uninitialized pool memory otherwise.
+                            let acc = if alpha == <$acc>::default() {
+                                beta * sum
+                            } else {
+                                alpha * <$acc>::from(out[out_idx]) + beta * sum
+                            };
+                            out[out_idx] = <$rt as crate::DType>::from_f64(acc.into());
                         }
                     }
                 }
@@ -201,36 +212,40 @@ macro_rules! instantiate_gemm {
             ) where
                 Self: Sized,
             {
-                let num_threads = num_cpus::get();
+                // The size of the pool this runs on, e.g. one set by `CompiledGraph::with_threads`.
+                let num_threads = rayon::current_num_threads();
                 let parallelism = if num_threads > 1 {
                     Parallelism::Rayon(num_threads)
                 } else {
                     Parallelism::None
                 };
 
-                debug_assert_eq!(lhs.len(), b * m * k);
+                debug_assert!(lhs.len() >= b * m * k);
                 debug_assert_eq!(lhs_stride.len(), 3);
-                debug_assert_eq!(rhs.len(), b * k * n);
+                debug_assert!(rhs.len() >= b * k * n);
                 debug_assert_eq!(rhs_stride.len(), 3);
-                debug_assert_eq!(out.len(), b * m * n);
+                debug_assert!(out.len() >= b * m * n);
                 debug_assert_eq!(out_stride.len(), 3);
 
-                // cs = stride[-1], rs = stride[-2]
+                // bs = stride[-3], cs = stride[-1], rs = stride[-2]
+                let dst_bs = out_stride[0];
                 let dst_cs = out_stride[2];
                 let dst_rs = out_stride[1];
 
+                let lhs_bs = lhs_stride[0];
                 let lhs_cs = lhs_stride[2];
                 let lhs_rs = lhs_stride[1];
 
+                let rhs_bs = rhs_stride[0];
                 let rhs_cs = rhs_stride[2];
                 let rhs_rs = rhs_stride[1];
 
                 let read_dst = alpha != $zero;
 
                 for b in 0..b {
-                    let lhs_p = &lhs[b * m * k..];
-                    let rhs_p = &rhs[b * k * n..];
-                    let out_p = &mut out[b * m * n..];
+                    let lhs_p = &lhs[b * lhs_bs..];
+                    let rhs_p = &rhs[b * rhs_bs..];
+                    let out_p = &mut out[b * dst_bs..];
 
                     unsafe {
                         gemm(
@@ -298,13 +313,35 @@ macro_rules! instantiate_gemm {
                 let out_rs = out_stride[1];
                 let out_cs = out_stride[2];
 
-                debug_assert_eq!(lhs.len(), b * m * k);
+                debug_assert!(lhs.len() >= b * m * k);
                 debug_assert_eq!(lhs_stride.len(), 3);
-                debug_assert_eq!(rhs.len(), b * k * n);
+                debug_assert!(rhs.len() >= b * k * n);
                 debug_assert_eq!(rhs_stride.len(), 3);
-                debug_assert_eq!(out.len(), b * m * n);
+                debug_assert!(out.len() >= b * m * n);
                 debug_assert_eq!(out_stride.len(), 3);
 
+                // The blocks below read `rhs` and write `out` as contiguous rows.
+                if out_cs != 1 || rhs_cs != 1 {
+                    for batch_idx in 0..b {
+                        for i in 0..m {
+                            for j in 0..n {
+                                let mut sum = $init;
+                                for p in 0..k {
+                                    sum += lhs[batch_idx * lhs_bs + i * lhs_rs + p * lhs_cs]
+                                        * rhs[batch_idx * rhs_bs + p * rhs_rs + j * rhs_cs];
+                                }
+                                let out_idx = batch_idx * out_bs + i * out_rs + j * out_cs;
+                                out[out_idx] = if alpha == $init {
+                                    beta * sum
+                                } else {
+                                    alpha * out[out_idx] + beta * sum
+                                };
+                            }
+                        }
+                    }
+                    return;
+                }
+
                 for batch in 0..b {
                     // Compute base pointers once per batch
                     let lhs_base = unsafe { lhs.as_ptr().add(batch * lhs_bs) };
@@ -387,9 +424,9 @@ instantiate_gemm!(i32, 0, SIMD);
 instantiate_gemm!(i64, 0, SIMD);
 instantiate_gemm!(f32, 0., GEMM);
 instantiate_gemm!(f64, 0., GEMM);
+// Half precision uses the naive implementation to avoid CPU SIMD half-precision assembly
+// requirements. It accumulates in f32, since summing in half precision stalls for large `k`.
 #[cfg(feature = "bfloat")]
-// Use naive implementation for bf16 to avoid CPU SIMD half-precision assembly requirements
-instantiate_gemm!(bf16, bf16::from_f32(0.), NAIVE);
+instantiate_gemm!(bf16, f32, NAIVE);
 #[cfg(feature = "half")]
-// Use naive implementation for f16 to avoid CPU SIMD half-precision assembly requirements
-instantiate_gemm!(f16, f16::from_f32(0.), NAIVE);
+instantiate_gemm!(f16, f32, NAIVE);
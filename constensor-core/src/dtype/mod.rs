@@ -1,6 +1,6 @@
 use std::{
     fmt::Debug,
-    ops::{Add, Div, Mul, Sub},
+    ops::{Add, Div, Mul, Rem, Sub},
 };
 
 #[cfg(feature = "bfloat")]
@@ -275,10 +275,12 @@ impl Loggable for f16 {
 
 pub trait DTypeOps:
     Copy
+    + PartialOrd
     + Add<Output = Self>
     + Div<Output = Self>
     + Sub<Output = Self>
     + Mul<Output = Self>
+    + Rem<Output = Self>
     + Sqrtable
     + Expable
     + Loggable
@@ -341,24 +343,40 @@ maybe_neg!(f64);
 
 /// Marker trait for tensor datatypes.
 pub trait DType:
-    Debug + Clone + DTypeOps + Send + Sync + MaybeNeg + DeviceReprLike + 'static
+    Debug + Clone + PartialEq + DTypeOps + Send + Sync + MaybeNeg + DeviceReprLike + 'static
 {
     const ZERO: Self;
     const ONE: Self;
+    /// A small value for stabilizing divisions: the machine epsilon for floats, zero for integers.
+    const EPSILON: Self;
+    /// The smallest finite value.
+    const MIN: Self;
+    /// The largest finite value.
+    const MAX: Self;
     const C_NAME: &'static str;
     const C_DEP: Option<&'static str>;
     const INTEGRAL: bool;
 
+    /// Widen to `f64`. Exact, except that `i64` values beyond 2^53 round to the nearest `f64`.
     fn to_f64(&self) -> f64;
+    /// Convert from `f64` like an `as` cast. Integers truncate toward zero, saturate at
+    /// [`DType::MIN`] and [`DType::MAX`], and map NaN to zero. Floats round to nearest, and
+    /// values beyond their range become infinite.
     fn from_f64(x: f64) -> Self;
+    /// The bit pattern of this value, zero-extended to `u64`. Unlike `==`, this tells `-0.0`
+    /// from `0.0` and every NaN equals itself, so it is used to hash and compare graphs.
+    fn to_bits(&self) -> u64;
 }
 
 macro_rules! dtype {
-    ($rt:ident, $zero:expr, $one:expr, $c_repr:expr, $integral:expr) => {
+    ($rt:ident, $zero:expr, $one:expr, $epsilon:expr, $c_repr:expr, $integral:expr, $bits:expr) => {
         impl DTypeOps for $rt {}
         impl DType for $rt {
             const ZERO: $rt = $zero;
             const ONE: $rt = $one;
+            const EPSILON: $rt = $epsilon;
+            const MIN: $rt = $rt::MIN;
+            const MAX: $rt = $rt::MAX;
             const C_NAME: &'static str = $c_repr;
             const C_DEP: Option<&'static str> = None;
             const INTEGRAL: bool = $integral;
@@ -369,16 +387,24 @@ macro_rules! dtype {
             fn from_f64(x: f64) -> Self {
                 x as $rt
             }
+            fn to_bits(&self) -> u64 {
+                #[allow(clippy::redundant_closure_call)]
+                ($bits)(*self)
+            }
         }
     };
 }
 
-dtype!(u8, 0u8, 1u8, "uint8_t", true);
-dtype!(u32, 0u32, 1u32, "uint32_t", true);
-dtype!(i32, 0i32, 1i32, "int", true);
-dtype!(i64, 0i64, 1i64, "int64_t", true);
-dtype!(f32, 0f32, 1f32, "float", false);
-dtype!(f64, 0f64, 1f64, "double", false);
+dtype!(u8, 0u8, 1u8, 0u8, "uint8_t", true, u64::from);
+dtype!(u32, 0u32, 1u32, 0u32, "uint32_t", true, u64::from);
+dtype!(i32, 0i32, 1i32, 0i32, "int", true, |x: i32| u64::from(
+    x as u32
+));
+dtype!(i64, 0i64, 1i64, 0i64, "int64_t", true, |x: i64| x as u64);
+dtype!(f32, 0f32, 1f32, f32::EPSILON, "float", false, |x: f32| {
+    u64::from(x.to_bits())
+});
+dtype!(f64, 0f64, 1f64, f64::EPSILON, "double", false, f64::to_bits);
 
 #[cfg(feature = "half")]
 impl DTypeOps for f16 {}
@@ -390,6 +416,9 @@ maybe_neg!(f16);
 impl DType for f16 {
     const ZERO: f16 = f16::from_f64_const(0.0);
     const ONE: f16 = f16::from_f64_const(1.0);
+    const EPSILON: f16 = f16::EPSILON;
+    const MIN: f16 = f16::MIN;
+    const MAX: f16 = f16::MAX;
     const C_NAME: &'static str = "__half";
     const C_DEP: Option<&'static str> = Some("#include \"cuda_fp16.h\"");
     const INTEGRAL: bool = false;
@@ -400,6 +429,9 @@ impl DType for f16 {
     fn from_f64(x: f64) -> Self {
         Self::from_f64_const(x)
     }
+    fn to_bits(&self) -> u64 {
+        u64::from(f16::to_bits(*self))
+    }
 }
 #[cfg(feature = "bfloat")]
 impl DTypeOps for bf16 {}
@@ -411,6 +443,9 @@ maybe_neg!(bf16);
 impl DType for bf16 {
     const ZERO: bf16 = bf16::from_f64_const(0.0);
     const ONE: bf16 = bf16::from_f64_const(1.0);
+    const EPSILON: bf16 = bf16::EPSILON;
+    const MIN: bf16 = bf16::MIN;
+    const MAX: bf16 = bf16::MAX;
     const C_NAME: &'static str = "__nv_bfloat16";
     const C_DEP: Option<&'static str> = Some("#include \"cuda_bf16.h\"");
     const INTEGRAL: bool = false;
@@ -421,4 +456,7 @@ impl DType for bf16 {
     fn from_f64(x: f64) -> Self {
         Self::from_f64_const(x)
     }
+    fn to_bits(&self) -> u64 {
+        u64::from(bf16::to_bits(*self))
+    }
 }
@@ -49,22 +49,8 @@ macro_rules! simd_supported {
 
             // Define SIMD and scalar operations based on the chosen operation
             let simd_op = |l: std::simd::Simd<$t, { Self::BLOCK_SIZE }>,
-                           r: std::simd::Simd<$t, { Self::BLOCK_SIZE }>| {
-                match op {
-                    BinaryOpType::Add => l + r,
-                    BinaryOpType::Mul => l * r,
-                    BinaryOpType::Sub => l - r,
-                    BinaryOpType::Div => l / r,
-                }
-            };
-            let scalar_op = |l: Self, r: Self| {
-                match op {
-                    BinaryOpType::Add => l + r,
-                    BinaryOpType::Mul => l * r,
-                    BinaryOpType::Sub => l - r,
-                    BinaryOpType::Div => l / r,
-                }
-            };
+                           r: std::simd::Simd<$t, { Self::BLOCK_SIZE }>| op.apply_simd(l, r);
+            let scalar_op = |l: Self, r: Self| op.apply(l, r);
 
             // Vectorized loop
             for i in 0..n_blocks {
@@ -94,22 +80,8 @@ macro_rules! simd_supported {
 
             // Define SIMD and scalar operations based on the chosen operation
             let simd_op = |l: std::simd::Simd<$t, { Self::BLOCK_SIZE }>,
-                           r: std::simd::Simd<$t, { Self::BLOCK_SIZE }>| {
-                match op {
-                    BinaryOpType::Add => l + r,
-                    BinaryOpType::Mul => l * r,
-                    BinaryOpType::Sub => l - r,
-                    BinaryOpType::Div => l / r,
-                }
-            };
-            let scalar_op = |l: Self, r: Self| {
-                match op {
-                    BinaryOpType::Add => l + r,
-                    BinaryOpType::Mul => l * r,
-                    BinaryOpType::Sub => l - r,
-                    BinaryOpType::Div => l / r,
-                }
-            };
+                           r: std::simd::Simd<$t, { Self::BLOCK_SIZE }>| op.apply_simd(l, r);
+            let scalar_op = |l: Self, r: Self| op.apply(l, r);
 
             // Vectorized loop
             for i in 0..n_blocks {
@@ -139,22 +111,8 @@ macro_rules! simd_supported {
 
             // Define SIMD and scalar operations based on the chosen operation
             let simd_op = |l: std::simd::Simd<$t, { Self::BLOCK_SIZE }>,
-                           r: std::simd::Simd<$t, { Self::BLOCK_SIZE }>| {
-                match op {
-                    BinaryOpType::Add => l + r,
-                    BinaryOpType::Mul => l * r,
-                    BinaryOpType::Sub => l - r,
-                    BinaryOpType::Div => l / r,
-                }
-            };
-            let scalar_op = |l: Self, r: Self| {
-                match op {
-                    BinaryOpType::Add => l + r,
-                    BinaryOpType::Mul => l * r,
-                    BinaryOpType::Sub => l - r,
-                    BinaryOpType::Div => l / r,
-                }
-            };
+                           r: std::simd::Simd<$t, { Self::BLOCK_SIZE }>| op.apply_simd(l, r);
+            let scalar_op = |l: Self, r: Self| op.apply(l, r);
 
             // Vectorized loop
             for i in 0..n_blocks {
@@ -416,12 +374,7 @@ macro_rules! simd_supported {
 
                 out.par_iter_mut()
                     .zip(a.par_iter().zip(b))
-                    .for_each(|(out, (lhs, rhs))| *out = match op {
-                        BinaryOpType::Add => *lhs + rhs,
-                        BinaryOpType::Mul => *lhs * rhs,
-                        BinaryOpType::Sub => *lhs - rhs,
-                        BinaryOpType::Div => *lhs / rhs,
-                    });
+                    .for_each(|(out, (lhs, rhs))| *out = op.apply(*lhs, *rhs));
             }
 
             fn binary_simd_op_inplace_lhs(a: &mut [Self], b: &[Self], op: BinaryOpType)
@@ -431,12 +384,7 @@ macro_rules! simd_supported {
                 use rayon::prelude::*;
 
                 a.par_iter_mut().zip(b)
-                    .for_each(|(lhs, rhs)| *lhs = match op {
-                        BinaryOpType::Add => *lhs + rhs,
-                        BinaryOpType::Mul => *lhs * rhs,
-                        BinaryOpType::Sub => *lhs - rhs,
-                        BinaryOpType::Div => *lhs / rhs,
-                    });
+                    .for_each(|(lhs, rhs)| *lhs = op.apply(*lhs, *rhs));
             }
 
             fn binary_simd_op_inplace_rhs(a: &[Self], b: &mut[Self], op: BinaryOpType)
@@ -446,12 +394,7 @@ macro_rules! simd_supported {
                 use rayon::prelude::*;
 
                 b.par_iter_mut().zip(a)
-                    .for_each(|(rhs, lhs)| *rhs = match op {
-                        BinaryOpType::Add => *lhs + *rhs,
-                        BinaryOpType::Mul => *lhs * *rhs,
-                        BinaryOpType::Sub => *lhs - *rhs,
-                        BinaryOpType::Div => *lhs / *rhs,
-                    });
+                    .for_each(|(rhs, lhs)| *rhs = op.apply(*lhs, *rhs));
             }
 
             fn fma_op(a: &[Self], b: &[Self], c: &[Self], out: &mut Vec<Self>)
@@ -28,6 +28,31 @@ pub enum Error {
         context: String,
     },
 
+    #[error("shape mismatch: expected {expected:?}, got {got:?}")]
+    ShapeMismatch {
+        expected: Vec<usize>,
+        got: Vec<usize>,
+    },
+
+    /// A node output a NaN or infinity while running with [`crate::Graph::check_finite`].
+    #[error("node {node} produced a NaN or infinite value")]
+    NonFinite { node: usize },
+
+    /// A graph without any ops was compiled or run.
+    #[error("the graph has no ops")]
+    EmptyGraph,
+
+    /// An argument outside the range an operation accepts, e.g. an axis past the rank.
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+
+    /// An op or feature the backend cannot run.
+    #[error("{op} is not supported on the {backend} backend yet")]
+    UnsupportedOp {
+        op: &'static str,
+        backend: &'static str,
+    },
+
     #[error("matmul is only supported for contiguous tensors lstride: {lhs_stride:?} rstride: {rhs_stride:?} ostride: {out_stride:?} mnk: {mnk:?}")]
     MatMulNonContiguous {
         lhs_stride: Vec<usize>,
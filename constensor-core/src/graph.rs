@@ -1,22 +1,34 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::simd::{
+    cmp::{SimdPartialEq, SimdPartialOrd},
+    Mask, Select, Simd, SimdElement,
+};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::{
     collections::HashMap,
     env,
     fmt::Display,
     fs,
-    hash::Hash,
+    hash::{Hash, Hasher},
     marker::PhantomData,
+    ops::{Add, Div, Mul, Range, Rem, Sub},
     path::Path,
     process::Command,
     sync::{Arc, RwLock, RwLockReadGuard},
+    time::Duration,
 };
 
-use crate::{device::Dev, tensor::concretetensor::from_storage, DType, Result, Shape, Tensor};
+use crate::{
+    cpu_storage::CpuDevice,
+    device::Dev,
+    storage::Storage,
+    tensor::{concretetensor::from_storage_strided, contiguous_strides},
+    DType, Error, GraphTensor, Result, Shape, Tensor, R1, R2,
+};
 
 use petgraph::Graph as PetGraph;
 use petgraph::{dot::Dot, graph::NodeIndex};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct GraphNode<T: DType> {
     pub op: Op<T>,
     pub shape: Vec<usize>,
@@ -24,10 +36,15 @@ pub struct GraphNode<T: DType> {
     pub id: GraphTensorId,
 }
 
+/// A list of operations, recorded by building [`GraphTensor`]s and run once compiled.
+///
+/// Cloning a `Graph` yields another handle to the same graph, like cloning an `Arc`: ops added
+/// through either handle appear in both. Use [`Graph::deep_clone`] for an independent copy.
 #[derive(Clone)]
 pub struct Graph<T: DType> {
     data: Arc<RwLock<Vec<GraphNode<T>>>>,
     id: Arc<RwLock<usize>>,
+    check_finite: Arc<AtomicBool>,
 }
 
 impl<T: DType> Graph<T> {
@@ -36,6 +53,7 @@ impl<T: DType> Graph<T> {
         Self {
             data: Arc::new(RwLock::new(Vec::new())),
             id: Arc::new(RwLock::new(0)),
+            check_finite: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -46,14 +64,102 @@ impl<T: DType> Graph<T> {
 
     /// Append an operation to the graph
     pub(crate) fn add_op<S: Shape>(&self, op: Op<T>, strides: &[usize], id: &GraphTensorId) {
+        self.add_op_with_shape(op, S::shape(), strides, id);
+    }
+
+    /// Append an operation whose output shape is only known at runtime.
+    pub(crate) fn add_op_with_shape(
+        &self,
+        op: Op<T>,
+        shape: Vec<usize>,
+        strides: &[usize],
+        id: &GraphTensorId,
+    ) {
         self.data.write().unwrap().push(GraphNode {
             op,
-            shape: S::shape(),
+            shape,
             strides: strides.to_vec(),
             id: id.clone(),
         });
     }
 
+    /// A hash of the graph's structure: every node's op kind, operand ids, constant values, shape,
+    /// strides and id, with [`GraphTensor::map_cpu`] functions hashed by name. Call it after [`Graph::optimize`] to hash the optimized op list.
+    ///
+    /// Independently built graphs with the same nodes hash equal, so this can key a cache of
+    /// compiled graphs such as [`CompiledGraphCache`]. The hash does not depend on a random seed
+    /// or on std's unspecified default hasher, so it is also stable across processes running the
+    /// same build, e.g. for an on-disk cache.
+    pub fn structural_hash(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Estimate the buffers the CPU backend needs to run this graph, evaluating nodes in order.
+    ///
+    /// Call it after [`Graph::optimize`], which decides the ops that reuse an input buffer in
+    /// place. The CPU backend currently keeps intermediates until the end of a run, so
+    /// [`MemoryReport::total_bytes`] is what a run holds at its peak, while
+    /// [`MemoryReport::peak_bytes`] is reachable by releasing each buffer after its last use.
+    pub fn memory_report(&self) -> MemoryReport {
+        let ops = self.data.read().unwrap();
+        let n = ops.len();
+        let mut last_use = (0..n).collect::<Vec<_>>();
+        for (i, node) in ops.iter().enumerate() {
+            for id in node.op.input_ids() {
+                last_use[id.get()] = i;
+            }
+        }
+        if let Some(last) = last_use.last_mut() {
+            *last = n - 1;
+        }
+
+        // Each node writes to a new buffer, unless it takes over the buffer of an in-place input.
+        let mut buffer_of = vec![0; n];
+        let mut buffer_bytes = Vec::new();
+        // A buffer is live from its first writer until the last read of any node stored in it.
+        let mut buffer_live = Vec::new();
+        let mut nodes = Vec::with_capacity(n);
+        for (i, node) in ops.iter().enumerate() {
+            let bytes = node.shape.iter().product::<usize>() * std::mem::size_of::<T>();
+            buffer_of[i] = match node.op.input_ids().into_iter().find(|id| id.is_inplace()) {
+                Some(id) => buffer_of[id.get()],
+                None => {
+                    buffer_bytes.push(0);
+                    buffer_live.push(i..i);
+                    buffer_bytes.len() - 1
+                }
+            };
+            buffer_bytes[buffer_of[i]] = buffer_bytes[buffer_of[i]].max(bytes);
+            let live = &mut buffer_live[buffer_of[i]];
+            live.end = live.end.max(last_use[i] + 1);
+            nodes.push(NodeMemory {
+                buffer: buffer_of[i],
+                bytes,
+                live: i..last_use[i] + 1,
+            });
+        }
+
+        let (mut peak_live_buffers, mut peak_bytes) = (0, 0);
+        for i in 0..n {
+            let live = buffer_live
+                .iter()
+                .zip(&buffer_bytes)
+                .filter(|(live, _)| live.contains(&i));
+            peak_live_buffers = peak_live_buffers.max(live.clone().count());
+            peak_bytes = peak_bytes.max(live.map(|(_, bytes)| bytes).sum());
+        }
+
+        MemoryReport {
+            buffers: buffer_bytes.len(),
+            total_bytes: buffer_bytes.iter().sum(),
+            peak_live_buffers,
+            peak_bytes,
+            nodes,
+        }
+    }
+
     /// Generate the next unique tensor ID
     #[must_use]
     pub(crate) fn next_id(&mut self) -> GraphTensorId {
@@ -62,6 +168,15 @@ impl<T: DType> Graph<T> {
         next
     }
 
+    /// Clear every in-place flag, so that each node's input buffer survives the whole run.
+    pub(crate) fn clear_inplace(&self) {
+        for node in self.data.write().unwrap().iter_mut() {
+            for id in node.op.input_ids_mut() {
+                *id = GraphTensorId::out_of_place(id.get());
+            }
+        }
+    }
+
     pub fn to_petgraph(&self) -> PetGraph<String, String> {
         let ops = self.data.read().unwrap();
         let mut g = PetGraph::<String, String>::new();
@@ -82,19 +197,63 @@ impl<T: DType> Graph<T> {
                         } => {
                             format!("Arange(start={start:?}, step={step:?}, stop={stop:?})")
                         }
+                        Op::Eye => "Eye".to_string(),
+                        Op::Const { data } => format!("Const(n={})", data.len()),
                         Op::Rand => "Rand".to_string(),
                         Op::Randn { mean, std } => {
                             format!("Randn(mean={mean:?}, std={std:?})")
                         }
                         Op::BinaryOp { operator, .. } => format!("BinOp({})", operator.as_c_op()),
                         Op::UnaryOp { operator, .. } => format!("UnOp({operator:?})"),
+                        Op::CustomUnary { name, .. } => format!("CustomUnary({name})"),
                         Op::FusedMulAdd { .. } => "FMA".to_string(),
                         // Matrix multiplication
-                        Op::MatMul { .. } => "MatMul".to_string(),
+                        Op::MatMul { k, alpha, beta, .. } => {
+                            format!("MatMul(k={k}, alpha={alpha:?}, beta={beta:?})")
+                        }
                         Op::Permute { v_id: _ } => "Permute".to_string(),
+                        Op::Contiguous { .. } => "Contiguous".to_string(),
+                        Op::Stack { ids } => format!("Stack(n={})", ids.len()),
+                        Op::Concat { ids, axis } => format!("Concat(n={}, axis={axis})", ids.len()),
+                        Op::NanToNum {
+                            nan,
+                            posinf,
+                            neginf,
+                            ..
+                        } => format!("NanToNum(nan={nan:?}, posinf={posinf:?}, neginf={neginf:?})"),
+                        Op::Clamp { min, max, .. } => format!("Clamp(min={min:?}, max={max:?})"),
+                        Op::MaskedFill { value, .. } => format!("MaskedFill({value:?})"),
+                        Op::Triangular {
+                            upper, diagonal, ..
+                        } => {
+                            let name = if *upper { "Triu" } else { "Tril" };
+                            format!("{name}(diagonal={diagonal})")
+                        }
+                        Op::Reduce { axis, operator, .. } => {
+                            format!("Reduce({operator:?}, axis={axis})")
+                        }
+                        Op::ArgReduce { v, axis, kind } => {
+                            format!("ArgReduce({kind:?}, axis={axis}) of {v:?}")
+                        }
+                        Op::Pad {
+                            before,
+                            after,
+                            value,
+                            ..
+                        } => format!("Pad(before={before:?}, after={after:?}, value={value:?})"),
+                        Op::Gather { axis, mode, .. } => format!("Gather(axis={axis}, {mode:?})"),
+                        Op::Scatter { axis, mode, .. } => format!("Scatter(axis={axis}, {mode:?})"),
+                        Op::Slice {
+                            offset, len, axis, ..
+                        } => format!("Slice(axis={axis}, {offset}..{})", offset + len),
+                        Op::Roll { shift, axis, .. } => format!("Roll(axis={axis}, shift={shift})"),
+                        Op::Flip { axis, .. } => format!("Flip(axis={axis})"),
+                        Op::Broadcast { .. } => "Broadcast".to_string(),
+                        Op::Diagonal { offset, .. } => format!("Diagonal(offset={offset})"),
                         // we already matched NoOp above
                         Op::NoOp => unreachable!(),
                     };
+                    let label = format!("{label}\n{:?} {}", op.shape, T::NAME);
                     let node = g.add_node(label);
                     idx_map.push(Some(node));
                 }
@@ -125,7 +284,7 @@ impl<T: DType> Graph<T> {
                         g.add_edge(src, dst, label.clone());
                     }
                 }
-                Op::UnaryOp { v_id, .. } => {
+                Op::UnaryOp { v_id, .. } | Op::CustomUnary { v_id, .. } => {
                     if let Some(src) = idx_map[v_id.get()] {
                         let mut label = "v".to_string();
                         if v_id.is_inplace() {
@@ -174,7 +333,18 @@ impl<T: DType> Graph<T> {
                         }
                     }
                 }
-                Op::Permute { v_id, .. } => {
+                Op::Permute { v_id, .. }
+                | Op::Contiguous { v_id }
+                | Op::Slice { v_id, .. }
+                | Op::Roll { v_id, .. }
+                | Op::Flip { v_id, .. }
+                | Op::Broadcast { v_id }
+                | Op::Diagonal { v_id, .. }
+                | Op::Pad { v_id, .. }
+                | Op::NanToNum { v_id, .. }
+                | Op::Clamp { v_id, .. }
+                | Op::Triangular { v_id, .. }
+                | Op::Reduce { v_id, .. } => {
                     if let Some(src) = idx_map[v_id.get()] {
                         let mut label = "v".to_string();
                         if v_id.is_inplace() {
@@ -183,8 +353,48 @@ impl<T: DType> Graph<T> {
                         g.add_edge(src, dst, label.clone());
                     }
                 }
-                // NoOp, Fill/Arange, Rand/Randn don’t create incoming edges
-                Op::NoOp | Op::Fill { .. } | Op::Arange { .. } | Op::Rand | Op::Randn { .. } => {}
+                Op::Stack { ids } | Op::Concat { ids, .. } => {
+                    for (i, id) in ids.iter().enumerate() {
+                        if let Some(src) = idx_map[id.get()] {
+                            g.add_edge(src, dst, i.to_string());
+                        }
+                    }
+                }
+                Op::MaskedFill { v_id, mask_id, .. } => {
+                    for (label, src_id) in [("v", v_id), ("mask", mask_id)] {
+                        if let Some(src) = idx_map[src_id.get()] {
+                            g.add_edge(src, dst, label.to_string());
+                        }
+                    }
+                }
+                Op::Gather { v_id, idx_id, .. } => {
+                    for (label, src_id) in [("v", v_id), ("idx", idx_id)] {
+                        if let Some(src) = idx_map[src_id.get()] {
+                            g.add_edge(src, dst, label.to_string());
+                        }
+                    }
+                }
+                Op::Scatter {
+                    v_id,
+                    idx_id,
+                    src_id,
+                    ..
+                } => {
+                    for (label, src_id) in [("v", v_id), ("idx", idx_id), ("src", src_id)] {
+                        if let Some(src) = idx_map[src_id.get()] {
+                            g.add_edge(src, dst, label.to_string());
+                        }
+                    }
+                }
+                // NoOp, Fill/Arange/Eye, Rand/Randn don’t create incoming edges
+                Op::NoOp
+                | Op::Fill { .. }
+                | Op::Arange { .. }
+                | Op::Eye
+                | Op::Const { .. }
+                | Op::Rand
+                | Op::Randn { .. }
+                | Op::ArgReduce { .. } => {}
             }
         }
 
@@ -192,9 +402,25 @@ impl<T: DType> Graph<T> {
     }
 
     /// Produce a DOT format string of this graph.
+    ///
+    /// Nodes are labelled with their op parameters, shape and dtype, and colored by the kind of op.
     pub fn to_dot(&self) -> String {
         let g = self.to_petgraph();
-        format!("{:?}", Dot::with_config(&g, &[]))
+        // Nodes are added in graph order, skipping NoOps.
+        let colors = self
+            .data
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|node| !matches!(node.op, Op::NoOp))
+            .map(|node| node.op.dot_color())
+            .collect::<Vec<_>>();
+        format!(
+            "{}",
+            Dot::with_attr_getters(&g, &[], &|_, _| String::new(), &|_, (idx, _)| {
+                format!("style=filled, fillcolor={}", colors[idx.index()])
+            })
+        )
     }
 
     /// Visualize the graph by saving it to this file.
@@ -218,7 +444,7 @@ impl<T: DType> Graph<T> {
             ])
             .status()?;
         if !status.success() {
-            panic!("Graphviz failed");
+            crate::bail!("Graphviz failed with {status}");
         }
 
         Ok(())
@@ -226,6 +452,7 @@ impl<T: DType> Graph<T> {
 
     /// Optimize by performing constant folding:
     ///   - Fold BinaryOp and UnaryOp when all operands are constant Fill ops.
+    ///   - Fold them into a Const when the operands are Fill, Arange or Const ops otherwise.
     fn optimize_const(&mut self) {
         // Clone current ops for inspection
         let ops = self.data.read().unwrap().clone();
@@ -242,23 +469,45 @@ impl<T: DType> Graph<T> {
                     // both operands are constant fills
                     if let Op::Fill { v: v1 } = &new_ops[l_idx].op {
                         if let Op::Fill { v: v2 } = &new_ops[r_idx].op {
-                            let v = operator.as_closure()(*v1, *v2);
+                            let v = operator.apply(*v1, *v2);
                             new_ops[i] = GraphNode {
                                 op: Op::Fill { v },
                                 ..node.clone()
                             };
+                            continue;
                         }
                     }
+                    // operands are other constants
+                    if let (Some(l), Some(r)) = (
+                        Self::constant_values(&new_ops[l_idx]),
+                        Self::constant_values(&new_ops[r_idx]),
+                    ) {
+                        let data = l.iter().zip(&r).map(|(l, r)| operator.apply(*l, *r));
+                        new_ops[i] = GraphNode {
+                            op: Op::Const {
+                                data: Arc::new(data.collect()),
+                            },
+                            ..node.clone()
+                        };
+                    }
                 }
                 Op::UnaryOp { v_id, operator } => {
                     let idx = v_id.get();
                     // operand is a constant fill
                     if let Op::Fill { v: v0 } = &new_ops[idx].op {
-                        let v = operator.to_closure()(*v0);
+                        let v = operator.apply(*v0);
                         new_ops[i] = GraphNode {
                             op: Op::Fill { v },
                             ..node.clone()
                         };
+                    } else if let Some(values) = Self::constant_values(&new_ops[idx]) {
+                        let data = values.iter().map(|v| operator.apply(*v));
+                        new_ops[i] = GraphNode {
+                            op: Op::Const {
+                                data: Arc::new(data.collect()),
+                            },
+                            ..node.clone()
+                        };
                     }
                 }
                 _ => {}
@@ -268,246 +517,251 @@ impl<T: DType> Graph<T> {
         *self.data.write().unwrap() = new_ops;
     }
 
+    /// The row-major elements of `node` if its op is a constant known without running the graph.
+    fn constant_values(node: &GraphNode<T>) -> Option<Vec<T>> {
+        let n = node.shape.iter().product();
+        match &node.op {
+            Op::Fill { v } => Some(vec![*v; n]),
+            // Evaluated like the CPU backend, so folding does not change any value.
+            Op::Arange { start, step, .. } => {
+                let start = start.to_f64();
+                Some(
+                    (0..n)
+                        .map(|i| T::from_f64(start + i as f64 * step))
+                        .collect(),
+                )
+            }
+            Op::Const { data } => Some(data.to_vec()),
+            _ => None,
+        }
+    }
+
+    /// Point every consumer of a fill at the first fill with the same value and layout, so the
+    /// duplicates become dead code. Values are compared exactly in `T`, except that `-0.0` and
+    /// `0.0` stay apart and NaN fills are never merged.
+    fn optimize_dedup_fill(&mut self) {
+        let mut ops = self.data.read().unwrap().clone();
+        // The fills seen so far, by layout.
+        let mut first = HashMap::<_, Vec<(T, usize)>>::new();
+        let mut target = (0..ops.len()).collect::<Vec<_>>();
+        for (i, node) in ops.iter().enumerate() {
+            if let Op::Fill { v } = &node.op {
+                let same = |w: &T| {
+                    w == v && w.to_f64().is_sign_negative() == v.to_f64().is_sign_negative()
+                };
+                let bucket = first
+                    .entry((node.shape.clone(), node.strides.clone()))
+                    .or_default();
+                match bucket.iter().find(|(w, _)| same(w)) {
+                    Some(&(_, j)) => target[i] = j,
+                    None => bucket.push((*v, i)),
+                }
+            }
+        }
+        for node in &mut ops {
+            for id in node.op.input_ids_mut() {
+                if target[id.get()] != id.get() {
+                    *id = GraphTensorId::out_of_place(target[id.get()]);
+                }
+            }
+        }
+        *self.data.write().unwrap() = ops;
+    }
+
     /// Optimize by looking for mul-add pairs, convert to FMA
     fn optimize_fma(&mut self) {
         let ops = self.data.write().unwrap().clone();
         let mut new_ops = ops.clone();
+        let consumers = Self::count_consumers(&ops);
 
         // This contains the indices of the first of the pair.
         for (x_id, x) in ops.iter().enumerate() {
+            let Some(y) = ops.get(x_id + 1) else {
+                break;
+            };
             if let Op::BinaryOp {
                 l_id: a_id,
                 r_id: b_id,
                 operator: BinaryOpType::Mul,
             } = &x.op
             {
+                // The mul result is removed, so the add must be its only user.
+                if consumers[x_id] != 1 {
+                    continue;
+                }
                 // Check if next op uses this
                 if let Op::BinaryOp {
                     l_id: l_y,
                     r_id: r_y,
                     operator: BinaryOpType::Add,
-                } = &ops[x_id + 1].op
+                } = &y.op
                 {
-                    let y_id = x_id + 1;
-                    if l_y.get() == x_id || r_y.get() == x_id && x.shape == ops[x_id + 1].shape {
+                    if (l_y.get() == x_id || r_y.get() == x_id) && x.shape == y.shape {
                         // Want to see what is being added to the result of the mul
                         let rhs_add = if l_y.get() == x_id { r_y } else { l_y };
-                        new_ops[y_id] = GraphNode {
+                        new_ops[x_id + 1] = GraphNode {
                             op: Op::FusedMulAdd {
                                 a_id: a_id.clone(),
                                 b_id: b_id.clone(),
                                 c_id: rhs_add.clone(),
                             },
-                            ..x.clone()
+                            ..y.clone()
                         };
                         new_ops[x_id] = GraphNode {
                             op: Op::NoOp,
                             ..x.clone()
                         };
-
-                        // Look for ops which actually use this one
-                        for user in new_ops.iter() {
-                            let ids = match &user.op {
-                                Op::Arange {
-                                    start: _,
-                                    step: _,
-                                    stop: _,
-                                    ..
-                                } => vec![],
-                                Op::Rand => vec![],
-                                Op::Randn { mean: _, std: _ } => vec![],
-                                Op::BinaryOp { l_id, r_id, .. } => vec![l_id, r_id],
-                                Op::Fill { v: _, .. } => vec![],
-                                Op::UnaryOp {
-                                    v_id, operator: _, ..
-                                } => vec![v_id],
-                                Op::FusedMulAdd {
-                                    a_id, b_id, c_id, ..
-                                } => {
-                                    vec![a_id, b_id, c_id]
-                                }
-                                Op::MatMul {
-                                    l_id, r_id, o_id, ..
-                                } => o_id
-                                    .as_ref()
-                                    .map(|o| vec![l_id, r_id, o])
-                                    .unwrap_or(vec![l_id, r_id]),
-                                Op::Permute { v_id } => vec![v_id],
-                                Op::NoOp => vec![],
-                            };
-
-                            // We are going to remove the noop so this is necessary to fix the indices.
-                            let used_ids = ids
-                                .into_iter()
-                                .filter(|id| id.get() == y_id)
-                                .collect::<Vec<_>>();
-                            if !used_ids.is_empty() {
-                                for id in used_ids {
-                                    // Tell the ops which use the result of the fma to source from there
-                                    id.set(x_id);
-                                }
-                            }
-                        }
                     }
                 }
             }
         }
 
         // Remove any NoOp entries before storing back to the graph
-        let filtered_ops = new_ops
-            .into_iter()
-            .filter(|op| !matches!(op.op, Op::NoOp))
+        let keep = new_ops
+            .iter()
+            .map(|node| !matches!(node.op, Op::NoOp))
             .collect::<Vec<_>>();
-        *self.data.write().unwrap() = filtered_ops;
+        *self.data.write().unwrap() = Self::retain_nodes(new_ops, &keep);
     }
 
-    /// Count how often each tensor id is used as an input.
-    #[allow(clippy::mutable_key_type)]
-    fn count_input_usage(ops: &[GraphNode<T>]) -> HashMap<GraphTensorId, usize> {
-        #[allow(clippy::mutable_key_type)]
-        let mut usage: HashMap<GraphTensorId, usize> = HashMap::new();
+    /// Count how often each node is used as an input, by node index.
+    fn count_consumers(ops: &[GraphNode<T>]) -> Vec<usize> {
+        let mut consumers = vec![0; ops.len()];
         for op in ops {
-            match &op.op {
-                Op::BinaryOp { l_id, r_id, .. } => {
-                    *usage.entry(l_id.clone()).or_default() += 1;
-                    *usage.entry(r_id.clone()).or_default() += 1;
-                }
-                Op::UnaryOp { v_id, .. } => {
-                    *usage.entry(v_id.clone()).or_default() += 1;
-                }
-                Op::FusedMulAdd {
-                    a_id, b_id, c_id, ..
-                } => {
-                    *usage.entry(a_id.clone()).or_default() += 1;
-                    *usage.entry(b_id.clone()).or_default() += 1;
-                    *usage.entry(c_id.clone()).or_default() += 1;
-                }
-                Op::MatMul {
-                    l_id, r_id, o_id, ..
-                } => {
-                    *usage.entry(l_id.clone()).or_default() += 1;
-                    *usage.entry(r_id.clone()).or_default() += 1;
-                    if let Some(o_id) = o_id {
-                        *usage.entry(o_id.clone()).or_default() += 1;
-                    }
-                }
-                Op::Permute { v_id } => {
-                    *usage.entry(v_id.clone()).or_default() += 1;
-                }
-                // No input usage for these ops
-                Op::NoOp | Op::Fill { .. } | Op::Arange { .. } | Op::Rand | Op::Randn { .. } => {}
+            for id in op.op.input_ids() {
+                consumers[id.get()] += 1;
             }
         }
-        usage
+        consumers
     }
 
-    /// Optimize by inplacing binary operations when inputs are not reused.
-    fn optimize_inplace_bin(&mut self) {
-        let ops = self.data.write().unwrap().clone();
-        let mut new_ops = ops.clone();
-        #[allow(clippy::mutable_key_type)]
-        let usage = Self::count_input_usage(&ops);
-        // Transform eligible BinaryOps into InplaceBinaryOps.
-        for (i, op) in ops.iter().enumerate() {
-            if let Op::BinaryOp {
-                l_id,
-                r_id,
-                operator,
-            } = &op.op
-            {
-                let l_use = usage.get(l_id).copied().unwrap_or(0);
-                let r_use = usage.get(r_id).copied().unwrap_or(0);
-                if l_use <= 1 || r_use <= 1 {
-                    // Choose target for in-place: if both, default to lhs.
-                    let target = if r_use > l_use {
-                        r_id.clone()
-                    } else {
-                        l_id.clone()
-                    };
-                    // Replace with InplaceBinaryOp
-                    new_ops[i] = GraphNode {
-                        op: Op::BinaryOp {
-                            l_id: l_id.clone().to_inplace_if(&target == l_id),
-                            r_id: r_id.clone().to_inplace_if(&target == r_id),
-                            operator: *operator,
-                        },
-                        ..op.clone()
-                    };
-                }
+    /// Keep only the nodes marked in `keep` and renumber all tensor ids to the new positions.
+    fn retain_nodes(ops: Vec<GraphNode<T>>, keep: &[bool]) -> Vec<GraphNode<T>> {
+        // Build new ops and map old indices to new indices
+        let mut index_map = HashMap::new();
+        let mut new_ops = Vec::new();
+        let mut removed = Vec::new();
+        for (old_idx, node) in ops.into_iter().enumerate() {
+            if keep[old_idx] {
+                index_map.insert(old_idx, new_ops.len());
+                new_ops.push(node);
+            } else {
+                removed.push(node.id);
             }
         }
-        // Commit the transformed op list.
-        *self.data.write().unwrap() = new_ops;
+        // Ids may share their storage, so resolve every new index before writing any of them.
+        let updates = new_ops
+            .iter()
+            .flat_map(|node| std::iter::once(&node.id).chain(node.op.input_ids()))
+            .map(|id| (id.clone(), index_map[&id.get()]))
+            .collect::<Vec<_>>();
+        for (id, new_idx) in updates {
+            id.set(new_idx);
+        }
+        // Handles to removed nodes should no longer point into the graph.
+        for id in removed {
+            id.set(usize::MAX);
+        }
+        new_ops
     }
 
-    /// Optimize by inplacing fused multiply-add (FMA) operations when inputs are not reused.
-    fn optimize_inplace_fma(&mut self) {
-        let ops = self.data.write().unwrap().clone();
-        let mut new_ops = ops.clone();
-        #[allow(clippy::mutable_key_type)]
-        let usage = Self::count_input_usage(&ops);
-        for (i, op) in ops.iter().enumerate() {
-            if let Op::FusedMulAdd { a_id, b_id, c_id } = &op.op {
-                let mut target = None;
-                // If an input is used only once, we can reuse its buffer; default order: a_id, then b_id, then c_id
-                if *usage.get(a_id).unwrap_or(&0) <= 1 {
-                    target = Some(a_id.clone());
-                } else if *usage.get(b_id).unwrap_or(&0) <= 1 {
-                    target = Some(b_id.clone());
-                } else if *usage.get(c_id).unwrap_or(&0) <= 1 {
-                    target = Some(c_id.clone());
-                }
-                if let Some(out) = target {
-                    new_ops[i] = GraphNode {
-                        op: Op::FusedMulAdd {
-                            a_id: a_id.clone().to_inplace_if(&out == a_id),
-                            b_id: b_id.clone().to_inplace_if(&out == b_id),
-                            c_id: c_id.clone().to_inplace_if(&out == c_id),
-                        },
-                        ..op.clone()
-                    };
-                }
+    /// A copy of this graph that shares no state with it. Ops added to or optimized in either
+    /// graph leave the other untouched.
+    ///
+    /// Tensors built on this graph keep recording into this graph, not into the copy.
+    pub fn deep_clone(&self) -> Graph<T> {
+        Graph {
+            data: Arc::new(RwLock::new(self.detached_ops())),
+            id: Arc::new(RwLock::new(*self.id.read().unwrap())),
+            check_finite: Arc::new(AtomicBool::new(self.check_finite.load(Ordering::Relaxed))),
+        }
+    }
+
+    /// Copy every op of `other` to the end of this graph, e.g. to stitch together subgraphs built
+    /// separately. Returns the offset added to the copied ids: node `i` of `other` becomes node
+    /// `i + offset` here, so `id.shifted(offset)` refers to the copy of `id`.
+    ///
+    /// The copies read only each other, so connect them to this graph's nodes by building new
+    /// ops on top. `other` is left untouched.
+    pub fn append(&mut self, other: &Graph<T>) -> usize {
+        let mut ops = other.data.read().unwrap().clone();
+        let mut data = self.data.write().unwrap();
+        let offset = data.len();
+        for node in &mut ops {
+            node.id = node.id.shifted(offset);
+            for id in node.op.input_ids_mut() {
+                *id = id.shifted(offset);
             }
         }
-        *self.data.write().unwrap() = new_ops;
+        data.extend(ops);
+        *self.id.write().unwrap() = data.len();
+        offset
     }
 
-    /// Optimize by inplacing the output of a matmul when inputs are not reused.
-    fn optimize_inplace_matmul(&mut self) {
-        let ops = self.data.write().unwrap().clone();
-        let mut new_ops = ops.clone();
-        #[allow(clippy::mutable_key_type)]
-        let usage = Self::count_input_usage(&ops);
-        // Transform eligible BinaryOps into InplaceBinaryOps.
-        for (i, op) in ops.iter().enumerate() {
-            if let Op::MatMul {
-                o_id: Some(o_id),
-                l_id,
-                r_id,
-                k,
-                alpha,
-                beta,
-            } = &op.op
-            {
-                let o_use = usage.get(o_id).copied().unwrap_or(0);
-                if o_use <= 1 {
-                    // Replace with InplaceBinaryOp
-                    new_ops[i] = GraphNode {
-                        op: Op::MatMul {
-                            o_id: Some(o_id.to_inplace()),
-                            l_id: l_id.clone(),
-                            r_id: r_id.clone(),
-                            k: *k,
-                            alpha: *alpha,
-                            beta: *beta,
-                        },
-                        ..op.clone()
-                    };
+    /// A copy of the nodes whose ids have their own storage, so renumbering them does not affect
+    /// this graph or the tensors built on it.
+    fn detached_ops(&self) -> Vec<GraphNode<T>> {
+        let mut ops = self.data.read().unwrap().clone();
+        for node in &mut ops {
+            node.id = node.id.detached();
+            for id in node.op.input_ids_mut() {
+                *id = id.detached();
+            }
+        }
+        ops
+    }
+
+    /// An independent copy of the nodes needed to compute `output`, which becomes the last node.
+    pub(crate) fn detached_subgraph(&self, output: &GraphTensorId) -> Graph<T> {
+        let mut ops = self.detached_ops();
+        let n = output.get() + 1;
+        ops.truncate(n);
+        let mut keep = vec![false; n];
+        keep[n - 1] = true;
+        for i in (0..n).rev() {
+            if keep[i] {
+                for id in ops[i].op.input_ids() {
+                    keep[id.get()] = true;
                 }
             }
         }
-        // Commit the transformed op list.
-        *self.data.write().unwrap() = new_ops;
+        Graph {
+            data: Arc::new(RwLock::new(Self::retain_nodes(ops, &keep))),
+            id: Arc::new(RwLock::new(keep.iter().filter(|k| **k).count())),
+            check_finite: Arc::new(AtomicBool::new(self.check_finite.load(Ordering::Relaxed))),
+        }
+    }
+
+    /// Decide which ops may overwrite an input buffer instead of allocating their output.
+    ///
+    /// Every in-place flag is cleared first, then set on at most one input of each binary op,
+    /// FMA, matmul accumulator or permute: one whose node has this op as its only consumer and is
+    /// not the graph output. Any other consumer would still need the original values.
+    fn optimize_inplace(&mut self) {
+        let mut ops = self.data.read().unwrap().clone();
+        for node in &mut ops {
+            for id in node.op.input_ids_mut() {
+                *id = GraphTensorId::out_of_place(id.get());
+            }
+        }
+        let consumers = Self::count_consumers(&ops);
+        let output = ops.len().saturating_sub(1);
+        let reusable = |id: &GraphTensorId| consumers[id.get()] == 1 && id.get() != output;
+        for node in &mut ops {
+            let candidates: Vec<&mut GraphTensorId> = match &mut node.op {
+                Op::BinaryOp { l_id, r_id, .. } => vec![l_id, r_id],
+                Op::FusedMulAdd { a_id, b_id, c_id } => vec![a_id, b_id, c_id],
+                Op::MatMul {
+                    o_id: Some(o_id), ..
+                } => vec![o_id],
+                Op::Permute { v_id } => vec![v_id],
+                _ => vec![],
+            };
+            if let Some(id) = candidates.into_iter().find(|id| reusable(id)) {
+                *id = id.to_inplace();
+            }
+        }
+        *self.data.write().unwrap() = ops;
     }
 
     /// Remove nodes whose outputs are never used, except the final output node.
@@ -523,212 +777,904 @@ impl<T: DType> Graph<T> {
         // Propagate reachability backwards
         for i in (0..n).rev() {
             if keep[i] {
-                match &old_ops[i].op {
-                    Op::BinaryOp { l_id, r_id, .. } => {
-                        keep[l_id.get()] = true;
-                        keep[r_id.get()] = true;
-                    }
-                    Op::UnaryOp { v_id, .. } => {
-                        keep[v_id.get()] = true;
-                    }
-                    Op::FusedMulAdd {
-                        a_id, b_id, c_id, ..
-                    } => {
-                        keep[a_id.get()] = true;
-                        keep[b_id.get()] = true;
-                        keep[c_id.get()] = true;
-                    }
-                    Op::MatMul {
-                        l_id, r_id, o_id, ..
-                    } => {
-                        keep[l_id.get()] = true;
-                        keep[r_id.get()] = true;
-                        if let Some(o_id) = o_id {
-                            keep[o_id.get()] = true;
-                        }
-                    }
-                    Op::Permute { v_id, .. } => {
-                        keep[v_id.get()] = true;
-                    }
-                    Op::NoOp
-                    | Op::Fill { .. }
-                    | Op::Arange { .. }
-                    | Op::Rand
-                    | Op::Randn { .. } => (),
+                for id in old_ops[i].op.input_ids() {
+                    keep[id.get()] = true;
                 }
             }
         }
-        // Build new ops and map old indices to new indices
-        let mut index_map = std::collections::HashMap::new();
-        let mut new_ops = Vec::new();
-        for (old_idx, node) in old_ops.into_iter().enumerate() {
-            if keep[old_idx] {
-                let new_idx = new_ops.len();
-                index_map.insert(old_idx, new_idx);
-                new_ops.push(node);
-            }
-        }
-        // Update tensor IDs in remaining ops
-        for node in new_ops.iter_mut() {
-            match &mut node.op {
-                Op::BinaryOp { l_id, r_id, .. } => {
-                    let old_l = l_id.get();
-                    let old_r = r_id.get();
-                    l_id.set(*index_map.get(&old_l).unwrap());
-                    r_id.set(*index_map.get(&old_r).unwrap());
-                }
-                Op::UnaryOp { v_id, .. } => {
-                    let old_v = v_id.get();
-                    v_id.set(*index_map.get(&old_v).unwrap());
-                }
-                Op::FusedMulAdd {
-                    a_id, b_id, c_id, ..
-                } => {
-                    let old_a = a_id.get();
-                    let old_b = b_id.get();
-                    let old_c = c_id.get();
-                    a_id.set(*index_map.get(&old_a).unwrap());
-                    b_id.set(*index_map.get(&old_b).unwrap());
-                    c_id.set(*index_map.get(&old_c).unwrap());
-                }
-                Op::MatMul {
-                    l_id, r_id, o_id, ..
-                } => {
-                    let old_l = l_id.get();
-                    let old_r = r_id.get();
-                    l_id.set(*index_map.get(&old_l).unwrap());
-                    r_id.set(*index_map.get(&old_r).unwrap());
-                    if let Some(o_id) = o_id {
-                        let old_o = o_id.get();
-                        o_id.set(*index_map.get(&old_o).unwrap());
-                    }
-                }
-                _ => {}
-            }
-        }
         // Commit pruned graph
-        *self.data.write().unwrap() = new_ops;
+        *self.data.write().unwrap() = Self::retain_nodes(old_ops, &keep);
     }
 
     /// Optimize this graph.
     ///
     /// Apply the following optimizations:
-    /// - Constant folding of elementwise fills
+    /// - Constant folding of elementwise ops on fills, aranges and constants. Results involving
+    ///   only fills stay a fill, others are stored as a constant
+    /// - Equal fills of the same shape share one node
     /// - Fuse mul-add into FMA
-    /// - Inplace binary operations when safe
-    /// - Inplace fused multiply-add when safe
-    /// - Inplace matrix-multiplication when safe
     /// - Dead code removal
+    /// - Inplace binary operations, fused multiply-adds, matmul accumulators and permutes when
+    ///   the input has no other consumer
     pub fn optimize(&mut self) {
         // Constant folding first
         self.optimize_const();
+        // Share one node between equal fills, including those made by folding
+        self.optimize_dedup_fill();
         // Fuse mul-add into FMA
         self.optimize_fma();
-        self.optimize_inplace_bin();
-        self.optimize_inplace_fma();
-        self.optimize_inplace_matmul();
-        // Remove dead code
+        // Remove dead code, whose consumers would otherwise keep buffers from being reused
         self.optimize_dead_code();
+        self.optimize_inplace();
     }
 
     /// Compile this graph and insert device-specific optimizations such as CUDA streams.
     pub fn compile<S: Shape, D: Dev>(self) -> Result<CompiledGraph<S, T, D>> {
-        if self
-            .data
-            .read()
-            .unwrap()
-            .last()
-            .is_some_and(|last| last.shape != S::shape())
-        {
-            let read = self.data.read();
-            let last = read.as_ref().unwrap().last().unwrap();
+        let Some(last) = self.data.read().unwrap().last().cloned() else {
+            return Err(Error::EmptyGraph);
+        };
+        if last.shape != S::shape() {
+            return Err(Error::ShapeMismatch {
+                expected: S::shape(),
+                got: last.shape,
+            });
+        }
+
+        let device = D::resolve()?;
+
+        let compiled = device.compile(self.data.read().unwrap().clone())?;
+        let check_finite = self.check_finite.load(Ordering::Relaxed);
+        Ok(CpuDevice.with_check_finite(compiled, check_finite))
+    }
 
+    /// Make graphs compiled with [`Graph::compile`] fail with [`Error::NonFinite`] at the first
+    /// node that outputs a NaN or infinity, to find where a numerical blowup starts.
+    ///
+    /// Every buffer is scanned after it is computed, so this is off by default and costs nothing
+    /// then. Only the CPU backend checks, and integer graphs never fail.
+    pub fn check_finite(&mut self, enabled: bool) {
+        self.check_finite.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Compile this graph so that running it returns the tensors in `outputs`, in that order.
+    ///
+    /// Unlike [`Graph::compile`], outputs may be intermediate nodes. Their buffers are kept alive
+    /// rather than reused in place by later ops. Nodes removed by [`Graph::optimize`], such as a
+    /// mul fused into an FMA, cannot be requested.
+    pub fn compile_multi<D: Dev>(
+        self,
+        outputs: &[GraphTensorId],
+    ) -> Result<CompiledGraphMulti<T, D>> {
+        let mut graph = self.data.read().unwrap().clone();
+        if outputs.is_empty() {
+            crate::bail!("compile_multi requires at least one output");
+        }
+        let outputs = outputs.iter().map(|id| id.get()).collect::<Vec<_>>();
+        if outputs.iter().any(|o| *o >= graph.len()) {
             crate::bail!(
-                "Graph compiled shape is {:?} does not match the last node shape {:?}!",
-                &last.shape,
-                S::shape()
+                "compile_multi output is not a node of this graph, it may have been optimized away"
             );
         }
 
+        // Requested outputs must survive the run, so nothing may consume them in place.
+        for node in &mut graph {
+            for id in node.op.input_ids_mut() {
+                if id.is_inplace() && outputs.contains(&id.get()) {
+                    *id = GraphTensorId::out_of_place(id.get());
+                }
+            }
+        }
+
+        // Validate the graph for the target device.
         let device = D::resolve()?;
+        device.check_multi(&graph)?;
 
-        device.compile(self.data.read().unwrap().clone())
+        Ok(CompiledGraphMulti {
+            graph,
+            outputs,
+            ghost: PhantomData,
+        })
     }
 }
 
-/// A representation of the compiled graph. The shape is the output shape.
-pub enum CompiledGraph<S: Shape, T: DType, D: Dev> {
-    Cpu {
-        order: Vec<usize>,
-        graph: Vec<GraphNode<T>>,
-        ghost: PhantomData<(S, T, D)>,
-    },
-    #[cfg(feature = "cuda")]
-    Cuda {
-        kernels: Vec<crate::cuda_backend::CudaCompiledKernel<T>>,
-        ghost: PhantomData<(S, T, D)>,
-    },
+/// Builds a [`Graph`] without passing `&mut graph` to every constructor.
+///
+/// Tensors made by the builder record into the same graph as those made with an explicit
+/// `&mut Graph` from [`GraphBuilder::graph`], so the two styles can be mixed.
+#[derive(Clone)]
+pub struct GraphBuilder<T: DType> {
+    graph: Graph<T>,
 }
 
-impl<S: Shape, T: DType, D: Dev> CompiledGraph<S, T, D> {
-    /// Run the precompiled graph. This executes all nodes on the specified backend device and returns a concrete tensor.
-    pub fn run(&self) -> Result<Tensor<S, T, D>> {
-        let device = D::resolve()?;
-        let storage = device.run_graph(self)?;
-        Ok(from_storage(Arc::new(storage)))
+impl<T: DType> Default for GraphBuilder<T> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-#[derive(PartialEq, Debug, Clone, Copy)]
-pub enum BinaryOpType {
-    Add,
-    Div,
-    Sub,
-    Mul,
-}
+impl<T: DType> GraphBuilder<T> {
+    /// Start building an empty graph.
+    pub fn new() -> Self {
+        Self::from_graph(Graph::empty())
+    }
 
-impl BinaryOpType {
-    pub fn as_c_op(&self) -> &'static str {
-        match self {
-            Self::Add => "+",
-            Self::Div => "/",
-            Self::Sub => "-",
-            Self::Mul => "*",
-        }
+    /// Keep building `graph`.
+    pub fn from_graph(graph: Graph<T>) -> Self {
+        Self { graph }
     }
 
-    pub fn as_closure<T: DType>(&self) -> impl Fn(T, T) -> T {
-        match self {
-            Self::Add => |x, y| x + y,
-            Self::Div => |x, y| x / y,
-            Self::Sub => |x, y| x - y,
-            Self::Mul => |x, y| x * y,
-        }
+    /// A handle to the graph being built, which shares its nodes with this builder.
+    pub fn graph(&self) -> Graph<T> {
+        self.graph.clone()
     }
-}
 
-#[derive(PartialEq, Debug, Clone)]
-pub enum UnaryOpType {
-    Neg,
-    Sqrt,
-    Exp,
-    Exp2,
-    Log,
-    Log1p,
-}
+    /// Finish building, returning the graph.
+    pub fn into_graph(self) -> Graph<T> {
+        self.graph
+    }
 
-impl UnaryOpType {
-    pub fn fill_in_c_op(&self, val: impl Display) -> String {
-        match self {
-            Self::Neg => format!("-{val}"),
-            Self::Sqrt => format!("static_cast<T>( sqrt( static_cast<double>({val}) ) )"),
-            Self::Exp => format!("static_cast<T>( exp( static_cast<double>({val}) ) )"),
-            Self::Exp2 => format!("static_cast<T>( exp2( static_cast<double>({val}) ) )"),
-            Self::Log => format!("static_cast<T>( log( static_cast<double>({val}) ) )"),
+    #[must_use]
+    /// See [`GraphTensor::fill`].
+    pub fn fill<S: Shape, D: Dev>(&self, v: T) -> GraphTensor<S, T, D> {
+        GraphTensor::fill(&mut self.graph(), v)
+    }
+
+    #[must_use]
+    /// See [`GraphTensor::zeros`].
+    pub fn zeros<S: Shape, D: Dev>(&self) -> GraphTensor<S, T, D> {
+        GraphTensor::zeros(&mut self.graph())
+    }
+
+    #[must_use]
+    /// See [`GraphTensor::ones`].
+    pub fn ones<S: Shape, D: Dev>(&self) -> GraphTensor<S, T, D> {
+        GraphTensor::ones(&mut self.graph())
+    }
+
+    #[must_use]
+    /// See [`GraphTensor::rand`].
+    pub fn rand<S: Shape, D: Dev>(&self) -> GraphTensor<S, T, D> {
+        GraphTensor::rand(&mut self.graph())
+    }
+
+    #[must_use]
+    /// See [`GraphTensor::randn`].
+    pub fn randn<S: Shape, D: Dev>(&self, mean: T, std: T) -> GraphTensor<S, T, D> {
+        GraphTensor::randn(&mut self.graph(), mean, std)
+    }
+
+    #[must_use]
+    /// See [`GraphTensor::arange`].
+    pub fn arange<const A: usize, D: Dev>(&self, start: T, stop: T) -> GraphTensor<R1<A>, T, D> {
+        GraphTensor::arange(&mut self.graph(), start, stop)
+    }
+
+    #[must_use]
+    /// See [`GraphTensor::arange_step`].
+    pub fn arange_step<const A: usize, D: Dev>(
+        &self,
+        start: T,
+        step: T,
+    ) -> GraphTensor<R1<A>, T, D> {
+        GraphTensor::arange_step(&mut self.graph(), start, step)
+    }
+
+    #[must_use]
+    /// See [`GraphTensor::eye`].
+    pub fn eye<const N: usize, D: Dev>(&self) -> GraphTensor<R2<N, N>, T, D> {
+        GraphTensor::eye(&mut self.graph())
+    }
+}
+
+/// 64-bit FNV-1a, which unlike [`std::hash::DefaultHasher`] has a fixed, documented algorithm.
+pub(crate) struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+impl<T: DType> PartialEq for Graph<T> {
+    /// Graphs are equal when their nodes are, regardless of which `Graph` handle built them.
+    fn eq(&self, other: &Self) -> bool {
+        *self.data.read().unwrap() == *other.data.read().unwrap()
+    }
+}
+
+impl<T: DType> Hash for Graph<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.data.read().unwrap().hash(state);
+    }
+}
+
+impl<T: DType> Hash for GraphNode<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.op.hash(state);
+        self.shape.hash(state);
+        self.strides.hash(state);
+        self.id.hash(state);
+        self.id.is_inplace().hash(state);
+    }
+}
+
+/// The graphs sharing one structural hash, each with its compiled form.
+type CacheBucket<S, T, D> = Vec<(Graph<T>, Arc<CompiledGraph<S, T, D>>)>;
+
+/// A cache of compiled graphs keyed by [`Graph::structural_hash`], so that compiling a graph
+/// identical to one seen before reuses the existing artifact.
+pub struct CompiledGraphCache<S: Shape, T: DType, D: Dev> {
+    compiled: HashMap<u64, CacheBucket<S, T, D>>,
+}
+
+impl<S: Shape, T: DType, D: Dev> Default for CompiledGraphCache<S, T, D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Shape, T: DType, D: Dev> CompiledGraphCache<S, T, D> {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self {
+            compiled: HashMap::new(),
+        }
+    }
+
+    /// Return the compiled form of `graph`, compiling it only if no equal graph was compiled before.
+    pub fn get_or_compile(&mut self, graph: Graph<T>) -> Result<Arc<CompiledGraph<S, T, D>>> {
+        let entries = self.compiled.entry(graph.structural_hash()).or_default();
+        // Compare the graphs too, so that a hash collision never returns the wrong artifact.
+        if let Some((_, compiled)) = entries.iter().find(|(cached, _)| *cached == graph) {
+            return Ok(compiled.clone());
+        }
+        let compiled = Arc::new(graph.clone().compile()?);
+        entries.push((graph, compiled.clone()));
+        Ok(compiled)
+    }
+
+    /// The number of distinct graphs compiled so far.
+    pub fn len(&self) -> usize {
+        self.compiled.values().map(Vec::len).sum()
+    }
+
+    /// Whether no graph has been compiled yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drop every cached artifact.
+    pub fn clear(&mut self) {
+        self.compiled.clear();
+    }
+}
+
+/// A representation of the compiled graph. The shape is the output shape.
+pub enum CompiledGraph<S: Shape, T: DType, D: Dev> {
+    Cpu {
+        order: Vec<usize>,
+        graph: Vec<GraphNode<T>>,
+        pool: crate::cpu_storage::SharedPool<T>,
+        threads: Option<Arc<rayon::ThreadPool>>,
+        reduce_mode: ReduceMode,
+        check_finite: bool,
+        ghost: PhantomData<(S, T, D)>,
+    },
+    #[cfg(feature = "cuda")]
+    Cuda {
+        kernels: Vec<crate::cuda_backend::CudaCompiledKernel<T>>,
+        plan: Vec<KernelInfo>,
+        ghost: PhantomData<(S, T, D)>,
+    },
+}
+
+impl<S: Shape, T: DType, D: Dev> CompiledGraph<S, T, D> {
+    /// Run the precompiled graph. This executes all nodes on the specified backend device and returns a concrete tensor.
+    ///
+    /// Every run gets buffers of its own, so a compiled graph can be shared in an `Arc` and run
+    /// from several threads at once.
+    pub fn run(&self) -> Result<Tensor<S, T, D>> {
+        self.run_async()?.synchronize()
+    }
+
+    /// Run the precompiled graph like [`CompiledGraph::run`], writing the result into `out`'s
+    /// buffer instead of allocating a new tensor. Useful to reuse one output across runs in a loop.
+    ///
+    /// Returns an error if `out` shares its storage with another tensor, e.g. a clone or a view
+    /// from [`Tensor::t`].
+    pub fn run_into(&self, out: &mut Tensor<S, T, D>) -> Result<()> {
+        let device = D::resolve()?;
+        let Some((storage, strides)) = out.storage_mut() else {
+            return Err(Error::InvalidArgument(
+                "run_into requires a tensor that does not share its storage".to_string(),
+            ));
+        };
+        device.run_graph_into(self, storage)?;
+        *strides = self.output_strides();
+        Ok(())
+    }
+
+    /// Queue a run of the graph like [`CompiledGraph::run`], returning before the device has
+    /// finished so the host can do other work meanwhile. The CPU backend runs to completion.
+    pub fn run_async(&self) -> Result<PendingTensor<S, T, D>> {
+        let device = D::resolve()?;
+        let storage = device.run_graph(self)?;
+        Ok(PendingTensor {
+            tensor: from_storage_strided(Arc::new(storage), self.output_strides()),
+        })
+    }
+
+    /// Run the precompiled graph like [`CompiledGraph::run`], also returning how the backend's
+    /// buffer pool was used. Only supported on the CPU backend.
+    pub fn run_tracked(&self) -> Result<(Tensor<S, T, D>, RunStats)> {
+        let device = D::resolve()?;
+        let (storage, stats) = device.run_graph_tracked(self)?;
+        Ok((
+            from_storage_strided(Arc::new(storage), self.output_strides()),
+            stats,
+        ))
+    }
+
+    /// Run the precompiled graph like [`CompiledGraph::run`], computing `tile_elems` output
+    /// elements at a time so that intermediates only ever hold one tile each. Useful for
+    /// elementwise chains whose intermediates would not fit in memory together.
+    ///
+    /// Only graphs of fills, aranges, constants, random tensors, unary and binary ops, FMAs,
+    /// clamps and `nan_to_num` can be tiled; matmuls, reductions and views are rejected. Random
+    /// tensors draw fresh values per tile. Only supported on the CPU backend.
+    pub fn run_tiled(&self, tile_elems: usize) -> Result<Tensor<S, T, D>> {
+        let device = D::resolve()?;
+        let storage = device.run_graph_tiled(self, tile_elems)?;
+        Ok(from_storage_strided(
+            Arc::new(storage),
+            contiguous_strides(&S::shape()),
+        ))
+    }
+
+    /// The strides the output buffer is laid out with. On the CPU an output such as a transpose
+    /// is returned as a view of its input's buffer; CUDA kernels always write row-major.
+    fn output_strides(&self) -> Vec<usize> {
+        match self {
+            Self::Cpu { graph, .. } => graph.last().map_or_else(
+                || contiguous_strides(&S::shape()),
+                |node| node.strides.clone(),
+            ),
+            #[cfg(feature = "cuda")]
+            Self::Cuda { .. } => contiguous_strides(&S::shape()),
+        }
+    }
+
+    /// Run the precompiled graph like [`CompiledGraph::run`], also returning the time spent
+    /// computing each node. Only supported on the CPU backend.
+    pub fn run_profiled(&self) -> Result<(Tensor<S, T, D>, ProfileReport)> {
+        let device = D::resolve()?;
+        let (storage, report) = device.run_graph_profiled(self)?;
+        Ok((
+            from_storage_strided(Arc::new(storage), self.output_strides()),
+            report,
+        ))
+    }
+
+    /// Evaluate this graph on a dedicated pool of `n` threads instead of the global rayon pool,
+    /// e.g. to leave cores free for the rest of an application. Only supported on the CPU backend.
+    ///
+    /// With the `numa` feature, the threads are pinned to the CPUs the process may run on, so
+    /// the buffers they first touch stay on their NUMA node. Whether this helps depends on the
+    /// machine and the graph.
+    pub fn with_threads(self, n: usize) -> Result<Self> {
+        CpuDevice.with_threads(self, n)
+    }
+
+    /// Choose how float reductions accumulate, see [`ReduceMode`]. The CUDA backend has no
+    /// parallel reductions, so this only affects the CPU backend.
+    pub fn with_reduce_mode(self, mode: ReduceMode) -> Self {
+        CpuDevice.with_reduce_mode(self, mode)
+    }
+
+    /// The kernels a run launches, in launch order, with the nodes fused into each. Useful to
+    /// check how the CUDA backend grouped a graph. Empty on the CPU backend, which evaluates
+    /// node by node.
+    pub fn kernel_plan(&self) -> Vec<KernelInfo> {
+        match self {
+            Self::Cpu { .. } => Vec::new(),
+            #[cfg(feature = "cuda")]
+            Self::Cuda { plan, .. } => plan.clone(),
+        }
+    }
+}
+
+/// One kernel of a compiled CUDA graph, see [`CompiledGraph::kernel_plan`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelInfo {
+    /// The nodes computed by the kernel, in graph order.
+    pub nodes: Vec<usize>,
+    /// The node whose value the kernel writes to its output buffer.
+    pub output: usize,
+    /// Shape of the output.
+    pub shape: Vec<usize>,
+}
+
+/// The buffers needed to run a graph, estimated by [`Graph::memory_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// Buffers allocated by a run.
+    pub buffers: usize,
+    /// Size of all buffers allocated by a run.
+    pub total_bytes: usize,
+    /// The most buffers alive at the same node, if each is released after its last use.
+    pub peak_live_buffers: usize,
+    /// The most bytes alive at the same node, if each buffer is released after its last use.
+    pub peak_bytes: usize,
+    /// The output of every node, indexed like [`Graph::get_ops`].
+    pub nodes: Vec<NodeMemory>,
+}
+
+/// Where a node's output is stored and how long it is needed, see [`MemoryReport`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeMemory {
+    /// The buffer holding the output, shared with the input it was computed from in place.
+    pub buffer: usize,
+    /// Size of the output.
+    pub bytes: usize,
+    /// The nodes from this one up to its last consumer, or to the end for the graph output.
+    pub live: Range<usize>,
+}
+
+/// The output of a run that may still be executing, returned by [`CompiledGraph::run_async`].
+#[must_use]
+pub struct PendingTensor<S: Shape, T: DType, D: Dev> {
+    tensor: Tensor<S, T, D>,
+}
+
+impl<S: Shape, T: DType, D: Dev> PendingTensor<S, T, D> {
+    /// Block until the run has completed and return its output.
+    pub fn synchronize(self) -> Result<Tensor<S, T, D>> {
+        self.tensor.synchronize()?;
+        Ok(self.tensor)
+    }
+}
+
+/// Buffer pool usage of a single run, returned by [`CompiledGraph::run_tracked`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunStats {
+    /// Buffers newly allocated because the pool had none large enough.
+    pub allocated: usize,
+    /// Buffers taken from the pool instead of allocated.
+    pub reused: usize,
+    /// The most buffers handed out by the pool at the same time.
+    pub peak_live: usize,
+}
+
+/// Per-node timings of a single run, returned by [`CompiledGraph::run_profiled`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileReport {
+    /// One entry per node, in graph order.
+    pub nodes: Vec<NodeProfile>,
+}
+
+impl ProfileReport {
+    /// The time spent in all nodes. Nodes run concurrently, so this can exceed the wall-clock
+    /// time of the run.
+    pub fn total(&self) -> Duration {
+        self.nodes.iter().map(|n| n.elapsed).sum()
+    }
+}
+
+/// The time one node of a [`ProfileReport`] took to compute its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeProfile {
+    /// The node's index in the graph.
+    pub node: usize,
+    /// Wall-clock time spent computing the node, excluding time waiting on its inputs.
+    pub elapsed: Duration,
+}
+
+/// A graph compiled with [`Graph::compile_multi`], producing several output tensors.
+pub struct CompiledGraphMulti<T: DType, D: Dev> {
+    graph: Vec<GraphNode<T>>,
+    outputs: Vec<usize>,
+    ghost: PhantomData<D>,
+}
+
+impl<T: DType, D: Dev> CompiledGraphMulti<T, D> {
+    /// Run the precompiled graph, returning the requested outputs.
+    pub fn run(&self) -> Result<GraphOutputs<T, D>> {
+        let device = D::resolve()?;
+        let storages = device.run_nodes(&self.graph, &self.outputs)?;
+        Ok(GraphOutputs {
+            tensors: storages
+                .into_iter()
+                .zip(&self.outputs)
+                .map(|(storage, &o)| {
+                    let node = &self.graph[o];
+                    (Arc::new(storage), node.shape.clone(), node.strides.clone())
+                })
+                .collect(),
+            ghost: PhantomData,
+        })
+    }
+}
+
+/// An output buffer with its shape and strides.
+type OutputBuffer<T> = (Arc<Storage<T>>, Vec<usize>, Vec<usize>);
+
+/// The tensors produced by [`CompiledGraphMulti::run`], in the order they were requested.
+pub struct GraphOutputs<T: DType, D: Dev> {
+    tensors: Vec<OutputBuffer<T>>,
+    ghost: PhantomData<D>,
+}
+
+impl<T: DType, D: Dev> GraphOutputs<T, D> {
+    /// The number of outputs.
+    pub fn len(&self) -> usize {
+        self.tensors.len()
+    }
+
+    /// Whether there are no outputs.
+    pub fn is_empty(&self) -> bool {
+        self.tensors.is_empty()
+    }
+
+    /// Get output `i` as a tensor of shape `S`. Returns an error if the shape does not match.
+    pub fn get<S: Shape>(&self, i: usize) -> Result<Tensor<S, T, D>> {
+        let Some((storage, shape, strides)) = self.tensors.get(i) else {
+            return Err(Error::InvalidArgument(format!(
+                "output {i} is out of range for {} outputs",
+                self.tensors.len()
+            )));
+        };
+        if *shape != S::shape() {
+            return Err(Error::ShapeMismatch {
+                expected: S::shape(),
+                got: shape.clone(),
+            });
+        }
+        Ok(from_storage_strided(storage.clone(), strides.clone()))
+    }
+}
+
+/// How gather and scatter treat index values that fall outside the indexed axis.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum IndexMode {
+    /// Fail the run with an error.
+    #[default]
+    Error,
+    /// Clamp the index into the valid range. Cheaper, intended for trusted inputs.
+    Clamp,
+}
+
+/// How [`Op::Reduce`] combines the entries along its axis.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ReduceOpType {
+    Sum,
+    Max,
+}
+
+/// What [`Op::ArgReduce`] computes along its axis, as an exact `u32`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ArgReduceType {
+    /// The position of the largest entry. Ties go to the lowest position.
+    ArgMax,
+    /// The position of the smallest entry, like [`ArgReduceType::ArgMax`].
+    ArgMin,
+    /// The number of entries that are not zero. NaNs count as nonzero.
+    CountNonzero,
+}
+
+/// The nodes computing the input of an [`Op::ArgReduce`], copied out of a graph of any dtype.
+/// They are run again by every run of the graph holding the op.
+#[derive(Clone)]
+pub struct SourceGraph(Arc<dyn ErasedSource>);
+
+impl SourceGraph {
+    pub(crate) fn new<U: DType>(graph: Graph<U>) -> Self {
+        Self(Arc::new(graph))
+    }
+
+    /// The shape of the source tensor.
+    pub fn shape(&self) -> Vec<usize> {
+        self.0.shape()
+    }
+
+    /// Run the source on the CPU and reduce it along `axis` as `kind` says.
+    pub(crate) fn arg_reduce_cpu(&self, axis: usize, kind: ArgReduceType) -> Result<Vec<u32>> {
+        self.0.arg_reduce_cpu(axis, kind)
+    }
+}
+
+impl std::fmt::Debug for SourceGraph {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "SourceGraph({:?} {})",
+            self.0.shape(),
+            self.0.dtype_name()
+        )
+    }
+}
+
+impl PartialEq for SourceGraph {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0.eq_source(other.0.as_any())
+    }
+}
+
+impl Hash for SourceGraph {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.structural_hash());
+    }
+}
+
+/// A source [`Graph`] with its dtype erased.
+trait ErasedSource: Send + Sync {
+    fn shape(&self) -> Vec<usize>;
+    fn dtype_name(&self) -> &'static str;
+    fn arg_reduce_cpu(&self, axis: usize, kind: ArgReduceType) -> Result<Vec<u32>>;
+    /// [`Graph::structural_hash`], also covering the dtype.
+    fn structural_hash(&self) -> u64;
+    fn eq_source(&self, other: &dyn std::any::Any) -> bool;
+    fn as_any(&self) -> &dyn std::any::Any;
+}
+
+impl<U: DType> ErasedSource for Graph<U> {
+    fn shape(&self) -> Vec<usize> {
+        self.get_ops()
+            .last()
+            .map_or(vec![], |node| node.shape.clone())
+    }
+
+    fn dtype_name(&self) -> &'static str {
+        U::NAME
+    }
+
+    fn arg_reduce_cpu(&self, axis: usize, kind: ArgReduceType) -> Result<Vec<u32>> {
+        let nodes = self.get_ops();
+        let last = nodes.last().ok_or(Error::EmptyGraph)?;
+        let out = CpuDevice.run_nodes(&nodes, &[nodes.len() - 1])?.remove(0);
+        crate::cpu_storage::arg_reduce((&out.0, &last.shape, &last.strides), axis, kind)
+    }
+
+    fn structural_hash(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        U::NAME.hash(&mut hasher);
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn eq_source(&self, other: &dyn std::any::Any) -> bool {
+        other
+            .downcast_ref::<Self>()
+            .is_some_and(|other| self == other)
+    }
+
+    fn as_any(&self) -> &dyn std::any::Any {
+        self
+    }
+}
+
+/// How [`Op::Reduce`] sums are accumulated. Matmuls, and so [`GraphTensor::dot`], always use an
+/// order that does not depend on the number of threads, and are compensated only in
+/// [`ReduceMode::Compensated`].
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ReduceMode {
+    /// Long axes are split into one partial sum per thread, so float results can change in the
+    /// last bits with the size of the thread pool, e.g. with `RAYON_NUM_THREADS`.
+    Fast,
+    /// Every sum is accumulated left to right, giving bit-for-bit identical results on any
+    /// machine and thread count. This is the default.
+    #[default]
+    Deterministic,
+    /// Like [`ReduceMode::Deterministic`], but float sums and matmul dot products carry a Kahan
+    /// compensation term, so the rounding error stays near one ulp instead of growing with the
+    /// length. This is several times slower and skips the optimized CPU GEMM.
+    Compensated,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BinaryOpType {
+    Add,
+    Div,
+    Sub,
+    Mul,
+    /// The remainder of `l / r` with the sign of `l`, like Rust's `%`. Floats give NaN for a zero
+    /// divisor; for integers it panics on the CPU and is undefined on CUDA, as for division.
+    Rem,
+    /// The larger of `l` and `r`. If one of them is NaN the other is returned, like C's `fmax`.
+    Max,
+    /// The smaller of `l` and `r`. If one of them is NaN the other is returned, like C's `fmin`.
+    Min,
+    /// One where `l == r` and zero elsewhere, like the other comparisons below. Comparisons with
+    /// NaN are false, except for `Ne`.
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// Every backend implements a binary operator through the methods here, so adding an operator
+/// only means extending this block.
+impl BinaryOpType {
+    pub const ALL: [Self; 13] = [
+        Self::Add,
+        Self::Div,
+        Self::Sub,
+        Self::Mul,
+        Self::Rem,
+        Self::Max,
+        Self::Min,
+        Self::Eq,
+        Self::Ne,
+        Self::Lt,
+        Self::Le,
+        Self::Gt,
+        Self::Ge,
+    ];
+
+    /// Whether the operator compares its operands, giving one or zero.
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            Self::Eq | Self::Ne | Self::Lt | Self::Le | Self::Gt | Self::Ge
+        )
+    }
+
+    /// The C operator used by the CUDA kernels, or the function name for `Max` and `Min`.
+    pub fn as_c_op(&self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Div => "/",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Rem => "%",
+            Self::Max => "max",
+            Self::Min => "min",
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+        }
+    }
+
+    /// The C expression applying the operator to `l` and `r` of type `T`, used by the CUDA kernels.
+    pub fn fill_in_c_op<T: DType>(&self, l: impl Display, r: impl Display) -> String {
+        match self {
+            // C's `%` only takes integers
+            Self::Rem if !T::INTEGRAL => format!(
+                "static_cast<T>( fmod( static_cast<double>({l}), static_cast<double>({r}) ) )"
+            ),
+            Self::Max if !T::INTEGRAL => format!(
+                "static_cast<T>( fmax( static_cast<double>({l}), static_cast<double>({r}) ) )"
+            ),
+            Self::Min if !T::INTEGRAL => format!(
+                "static_cast<T>( fmin( static_cast<double>({l}), static_cast<double>({r}) ) )"
+            ),
+            Self::Max => format!("(({l}) > ({r}) ? ({l}) : ({r}))"),
+            Self::Min => format!("(({l}) < ({r}) ? ({l}) : ({r}))"),
+            op if op.is_comparison() => format!(
+                "(({l}) {} ({r}) ? static_cast<T>(1) : static_cast<T>(0))",
+                op.as_c_op()
+            ),
+            _ => format!("({l} {} {r})", self.as_c_op()),
+        }
+    }
+
+    /// Apply the operator to two scalars, as the CPU backend and constant folding do.
+    pub fn apply<T: DType>(&self, l: T, r: T) -> T {
+        match self {
+            Self::Add => l + r,
+            Self::Div => l / r,
+            Self::Sub => l - r,
+            Self::Mul => l * r,
+            Self::Rem => l % r,
+            // `l` is only NaN if it is unordered with itself
+            Self::Max if r > l || l.partial_cmp(&l).is_none() => r,
+            Self::Min if r < l || l.partial_cmp(&l).is_none() => r,
+            Self::Max | Self::Min => l,
+            Self::Eq => Self::indicator(l == r),
+            Self::Ne => Self::indicator(l != r),
+            Self::Lt => Self::indicator(l < r),
+            Self::Le => Self::indicator(l <= r),
+            Self::Gt => Self::indicator(l > r),
+            Self::Ge => Self::indicator(l >= r),
+        }
+    }
+
+    fn indicator<T: DType>(holds: bool) -> T {
+        if holds {
+            T::ONE
+        } else {
+            T::ZERO
+        }
+    }
+
+    /// Apply the operator lane-wise, as the CPU SIMD kernels do.
+    pub(crate) fn apply_simd<T, const N: usize>(&self, l: Simd<T, N>, r: Simd<T, N>) -> Simd<T, N>
+    where
+        T: SimdElement + DType,
+        Simd<T, N>: Add<Output = Simd<T, N>>
+            + Div<Output = Simd<T, N>>
+            + Sub<Output = Simd<T, N>>
+            + Mul<Output = Simd<T, N>>
+            + Rem<Output = Simd<T, N>>
+            + SimdPartialOrd<Mask = Mask<T::Mask, N>>,
+    {
+        let indicator =
+            |holds: Mask<T::Mask, N>| holds.select(Simd::splat(T::ONE), Simd::splat(T::ZERO));
+        match self {
+            Self::Add => l + r,
+            Self::Div => l / r,
+            Self::Sub => l - r,
+            Self::Mul => l * r,
+            Self::Rem => l % r,
+            Self::Max => (r.simd_gt(l) | l.simd_ne(l)).select(r, l),
+            Self::Min => (r.simd_lt(l) | l.simd_ne(l)).select(r, l),
+            Self::Eq => indicator(l.simd_eq(r)),
+            Self::Ne => indicator(l.simd_ne(r)),
+            Self::Lt => indicator(l.simd_lt(r)),
+            Self::Le => indicator(l.simd_le(r)),
+            Self::Gt => indicator(l.simd_gt(r)),
+            Self::Ge => indicator(l.simd_ge(r)),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum UnaryOpType {
+    Neg,
+    Sqrt,
+    Exp,
+    Exp2,
+    Log,
+    Log1p,
+}
+
+/// Every backend implements a unary operator through the methods here, so adding an operator
+/// only means extending this block.
+impl UnaryOpType {
+    pub const ALL: [Self; 6] = [
+        Self::Neg,
+        Self::Sqrt,
+        Self::Exp,
+        Self::Exp2,
+        Self::Log,
+        Self::Log1p,
+    ];
+
+    /// The C expression applying the operator to `val`, used by the CUDA kernels.
+    pub fn fill_in_c_op(&self, val: impl Display) -> String {
+        match self {
+            Self::Neg => format!("-{val}"),
+            Self::Sqrt => format!("static_cast<T>( sqrt( static_cast<double>({val}) ) )"),
+            Self::Exp => format!("static_cast<T>( exp( static_cast<double>({val}) ) )"),
+            Self::Exp2 => format!("static_cast<T>( exp2( static_cast<double>({val}) ) )"),
+            Self::Log => format!("static_cast<T>( log( static_cast<double>({val}) ) )"),
             Self::Log1p => format!("static_cast<T>( log1p( static_cast<double>({val}) ) )"),
         }
     }
 
+    /// Apply the operator to a scalar.
+    pub fn apply<T: DType>(&self, v: T) -> T {
+        self.to_closure()(v)
+    }
+
+    /// The function applying the operator, resolved once for use over a whole buffer.
     pub fn to_closure<T: DType>(&self) -> impl Fn(T) -> T {
         match self {
             Self::Neg => T::maybe_neg,
@@ -741,14 +1687,17 @@ impl UnaryOpType {
     }
 }
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub enum Op<T: DType> {
     Fill {
         v: T,
     },
+    /// `start + i * step` for each index `i`, evaluated in `f64` and rounded once to `T`; `stop`
+    /// is the exclusive end, kept for display. The step is kept in `f64` so that it does not
+    /// lose precision in half-precision types.
     Arange {
         start: T,
-        step: T,
+        step: f64,
         stop: T,
     },
     BinaryOp {
@@ -760,6 +1709,14 @@ pub enum Op<T: DType> {
         v_id: GraphTensorId,
         operator: UnaryOpType,
     },
+    /// Apply a Rust function to every entry of `v`. Only the CPU backend can run it.
+    ///
+    /// `name` identifies the function in structural hashes and graph dumps.
+    CustomUnary {
+        v_id: GraphTensorId,
+        name: &'static str,
+        f: fn(T) -> T,
+    },
     /// a * b + c
     FusedMulAdd {
         a_id: GraphTensorId,
@@ -768,6 +1725,9 @@ pub enum Op<T: DType> {
     },
     /// (B x M x K) * (B x K x N) = (B x M x N)
     /// out = out * alpha + beta * lhs * rhs
+    ///
+    /// `out` may be larger in its last two dims, in which case the product is accumulated
+    /// into the leading `M x N` block of each batch through `out`'s strides.
     MatMul {
         l_id: GraphTensorId,
         r_id: GraphTensorId,
@@ -776,6 +1736,12 @@ pub enum Op<T: DType> {
         alpha: T,
         beta: T,
     },
+    /// An identity matrix: one on the diagonal, zero elsewhere.
+    Eye,
+    /// Precomputed data in row-major order, e.g. from [`crate::GraphTensor::eval_to_const`].
+    Const {
+        data: Arc<Vec<T>>,
+    },
     /// Fill with uniform random values in [0, 1).
     Rand,
     /// Fill with normally distributed random values (mean, std).
@@ -787,9 +1753,306 @@ pub enum Op<T: DType> {
     Permute {
         v_id: GraphTensorId,
     },
+    /// Copy `v` into row-major order. A copy of an already contiguous `v` is a plain memcpy.
+    Contiguous {
+        v_id: GraphTensorId,
+    },
+    /// Stack same-shaped tensors along a new leading dimension.
+    Stack {
+        ids: Vec<GraphTensorId>,
+    },
+    /// Concatenate tensors along an existing `axis`; all other dimensions must match.
+    Concat {
+        ids: Vec<GraphTensorId>,
+        axis: usize,
+    },
+    /// Take `len` consecutive entries along `axis`, starting at `offset`.
+    Slice {
+        v_id: GraphTensorId,
+        offset: usize,
+        len: usize,
+        axis: usize,
+    },
+    /// Repeat `v` along its length-one axes up to the node's shape, which has the same rank.
+    Broadcast {
+        v_id: GraphTensorId,
+    },
+    /// The diagonal of the matrix `v` starting at row `max(0, -offset)` and column
+    /// `max(0, offset)`, so a positive `offset` picks a diagonal above the main one.
+    Diagonal {
+        v_id: GraphTensorId,
+        offset: isize,
+    },
+    /// Circularly shift the entries of `v` by `shift` positions along `axis`, so position `i`
+    /// of the output holds position `(i - shift) mod len` of `v`.
+    Roll {
+        v_id: GraphTensorId,
+        shift: isize,
+        axis: usize,
+    },
+    /// Reverse the entries of `v` along `axis`, so position `i` of the output holds position
+    /// `len - 1 - i` of `v`.
+    Flip {
+        v_id: GraphTensorId,
+        axis: usize,
+    },
+    /// Replace NaN, positive infinity and negative infinity in `v` with the given values.
+    NanToNum {
+        v_id: GraphTensorId,
+        nan: T,
+        posinf: T,
+        neginf: T,
+    },
+    /// Replace the entries of `v` where `mask` is nonzero with `value`.
+    MaskedFill {
+        v_id: GraphTensorId,
+        mask_id: GraphTensorId,
+        value: T,
+    },
+    /// Limit every entry of `v` to `[min, max]`.
+    Clamp {
+        v_id: GraphTensorId,
+        min: T,
+        max: T,
+    },
+    /// Zero the entries of each matrix in `v` below (`upper`) or above the `diagonal`-th
+    /// diagonal. Offset 0 is the main diagonal, positive offsets lie above it.
+    Triangular {
+        v_id: GraphTensorId,
+        upper: bool,
+        diagonal: isize,
+    },
+    /// Combine the entries of `v` along `axis`, which is kept with length one.
+    Reduce {
+        v_id: GraphTensorId,
+        axis: usize,
+        operator: ReduceOpType,
+    },
+    /// The position or count picked by `kind` along `axis` of `v`, which is kept with length one.
+    /// `v` is computed by a graph of its own, so that it may have another dtype than the result,
+    /// which is `u32` in the graphs built by [`GraphTensor::argmax_axis`] and
+    /// [`GraphTensor::count_nonzero`].
+    ArgReduce {
+        v: SourceGraph,
+        axis: usize,
+        kind: ArgReduceType,
+    },
+    /// Surround `v` with `before[i]` and `after[i]` entries of `value` along each axis `i`.
+    Pad {
+        v_id: GraphTensorId,
+        before: Vec<usize>,
+        after: Vec<usize>,
+        value: T,
+    },
+    /// Read `v` along `axis` at the positions given by `idx`, which has the output shape.
+    Gather {
+        v_id: GraphTensorId,
+        idx_id: GraphTensorId,
+        axis: usize,
+        mode: IndexMode,
+    },
+    /// Copy `v`, then write `src` into it along `axis` at the positions given by `idx`.
+    Scatter {
+        v_id: GraphTensorId,
+        idx_id: GraphTensorId,
+        src_id: GraphTensorId,
+        axis: usize,
+        mode: IndexMode,
+    },
     NoOp,
 }
 
+/// Check that `(B x M x K) * (B x K x N)`, accumulated into an optional `(B x M x N)` output,
+/// lines up, returning `[B, M, N, K]`.
+pub(crate) fn matmul_dims(l: &[usize], r: &[usize], o: Option<&[usize]>) -> Result<[usize; 4]> {
+    let (&[b, m, k], &[_, _, n]) = (l, r) else {
+        return Err(Error::InvalidArgument(format!(
+            "matmul operands must be (B x M x K) and (B x K x N), got {l:?} and {r:?}"
+        )));
+    };
+    if r != [b, k, n] {
+        return Err(Error::ShapeMismatch {
+            expected: vec![b, k, n],
+            got: r.to_vec(),
+        });
+    }
+    if let Some(o) = o.filter(|o| *o != [b, m, n]) {
+        return Err(Error::ShapeMismatch {
+            expected: vec![b, m, n],
+            got: o.to_vec(),
+        });
+    }
+    Ok([b, m, n, k])
+}
+
+impl<T: DType> Op<T> {
+    /// The name of this op's variant, for error messages.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Op::Fill { .. } => "Fill",
+            Op::Arange { .. } => "Arange",
+            Op::BinaryOp { .. } => "BinaryOp",
+            Op::UnaryOp { .. } => "UnaryOp",
+            Op::CustomUnary { .. } => "CustomUnary",
+            Op::FusedMulAdd { .. } => "FusedMulAdd",
+            Op::MatMul { .. } => "MatMul",
+            Op::Eye => "Eye",
+            Op::Const { .. } => "Const",
+            Op::Rand => "Rand",
+            Op::Randn { .. } => "Randn",
+            Op::Permute { .. } => "Permute",
+            Op::Contiguous { .. } => "Contiguous",
+            Op::Stack { .. } => "Stack",
+            Op::Concat { .. } => "Concat",
+            Op::Slice { .. } => "Slice",
+            Op::Roll { .. } => "Roll",
+            Op::Flip { .. } => "Flip",
+            Op::Broadcast { .. } => "Broadcast",
+            Op::Diagonal { .. } => "Diagonal",
+            Op::NanToNum { .. } => "NanToNum",
+            Op::Clamp { .. } => "Clamp",
+            Op::MaskedFill { .. } => "MaskedFill",
+            Op::Triangular { .. } => "Triangular",
+            Op::Reduce { .. } => "Reduce",
+            Op::ArgReduce { .. } => "ArgReduce",
+            Op::Pad { .. } => "Pad",
+            Op::Gather { .. } => "Gather",
+            Op::Scatter { .. } => "Scatter",
+            Op::NoOp => "NoOp",
+        }
+    }
+
+    /// The ids of the tensors this op reads, in operand order.
+    pub(crate) fn input_ids(&self) -> Vec<&GraphTensorId> {
+        match self {
+            Op::BinaryOp { l_id, r_id, .. } => vec![l_id, r_id],
+            Op::UnaryOp { v_id, .. }
+            | Op::CustomUnary { v_id, .. }
+            | Op::Permute { v_id }
+            | Op::Contiguous { v_id }
+            | Op::Slice { v_id, .. }
+            | Op::Roll { v_id, .. }
+            | Op::Flip { v_id, .. }
+            | Op::Broadcast { v_id }
+            | Op::Diagonal { v_id, .. }
+            | Op::Pad { v_id, .. }
+            | Op::NanToNum { v_id, .. }
+            | Op::Clamp { v_id, .. }
+            | Op::Triangular { v_id, .. }
+            | Op::Reduce { v_id, .. } => vec![v_id],
+            Op::FusedMulAdd { a_id, b_id, c_id } => vec![a_id, b_id, c_id],
+            Op::MatMul {
+                l_id, r_id, o_id, ..
+            } => match o_id {
+                Some(o_id) => vec![l_id, r_id, o_id],
+                None => vec![l_id, r_id],
+            },
+            Op::Gather { v_id, idx_id, .. } => vec![v_id, idx_id],
+            Op::MaskedFill { v_id, mask_id, .. } => vec![v_id, mask_id],
+            Op::Scatter {
+                v_id,
+                idx_id,
+                src_id,
+                ..
+            } => vec![v_id, idx_id, src_id],
+            Op::Stack { ids } | Op::Concat { ids, .. } => ids.iter().collect(),
+            Op::NoOp
+            | Op::Fill { .. }
+            | Op::Arange { .. }
+            | Op::Eye
+            | Op::Const { .. }
+            | Op::Rand
+            | Op::Randn { .. }
+            | Op::ArgReduce { .. } => vec![],
+        }
+    }
+
+    /// Add the bits of `v` to `state`, agreeing with how `==` compares values.
+    fn hash_value<H: Hasher>(v: &T, state: &mut H) {
+        v.to_bits().hash(state);
+    }
+
+    /// The fill color of this op when rendered with [`Graph::to_dot`].
+    fn dot_color(&self) -> &'static str {
+        match self {
+            Op::Fill { .. }
+            | Op::Arange { .. }
+            | Op::Eye
+            | Op::Const { .. }
+            | Op::Rand
+            | Op::Randn { .. } => "lightgray",
+            Op::BinaryOp { .. }
+            | Op::UnaryOp { .. }
+            | Op::CustomUnary { .. }
+            | Op::FusedMulAdd { .. }
+            | Op::NanToNum { .. }
+            | Op::Clamp { .. }
+            | Op::MaskedFill { .. }
+            | Op::Triangular { .. } => "lightblue",
+            Op::MatMul { .. } => "orange",
+            Op::Reduce { .. } | Op::ArgReduce { .. } => "khaki",
+            Op::Permute { .. }
+            | Op::Contiguous { .. }
+            | Op::Stack { .. }
+            | Op::Concat { .. }
+            | Op::Slice { .. }
+            | Op::Roll { .. }
+            | Op::Flip { .. }
+            | Op::Broadcast { .. }
+            | Op::Diagonal { .. }
+            | Op::Pad { .. }
+            | Op::Gather { .. }
+            | Op::Scatter { .. } => "palegreen",
+            Op::NoOp => "white",
+        }
+    }
+
+    /// Mutable access to the ids of the tensors this op reads, in operand order.
+    pub(crate) fn input_ids_mut(&mut self) -> Vec<&mut GraphTensorId> {
+        match self {
+            Op::BinaryOp { l_id, r_id, .. } => vec![l_id, r_id],
+            Op::UnaryOp { v_id, .. }
+            | Op::CustomUnary { v_id, .. }
+            | Op::Permute { v_id }
+            | Op::Contiguous { v_id }
+            | Op::Slice { v_id, .. }
+            | Op::Roll { v_id, .. }
+            | Op::Flip { v_id, .. }
+            | Op::Broadcast { v_id }
+            | Op::Diagonal { v_id, .. }
+            | Op::Pad { v_id, .. }
+            | Op::NanToNum { v_id, .. }
+            | Op::Clamp { v_id, .. }
+            | Op::Triangular { v_id, .. }
+            | Op::Reduce { v_id, .. } => vec![v_id],
+            Op::FusedMulAdd { a_id, b_id, c_id } => vec![a_id, b_id, c_id],
+            Op::MatMul {
+                l_id, r_id, o_id, ..
+            } => match o_id {
+                Some(o_id) => vec![l_id, r_id, o_id],
+                None => vec![l_id, r_id],
+            },
+            Op::Gather { v_id, idx_id, .. } => vec![v_id, idx_id],
+            Op::MaskedFill { v_id, mask_id, .. } => vec![v_id, mask_id],
+            Op::Scatter {
+                v_id,
+                idx_id,
+                src_id,
+                ..
+            } => vec![v_id, idx_id, src_id],
+            Op::Stack { ids } | Op::Concat { ids, .. } => ids.iter_mut().collect(),
+            Op::NoOp
+            | Op::Fill { .. }
+            | Op::Arange { .. }
+            | Op::Eye
+            | Op::Const { .. }
+            | Op::Rand
+            | Op::Randn { .. }
+            | Op::ArgReduce { .. } => vec![],
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 /// Graph tensor IDs can be cloned.
 pub enum GraphTensorId {
@@ -797,6 +2060,361 @@ pub enum GraphTensorId {
     InPlace(Arc<AtomicUsize>),
 }
 
+/// Ops compare their values by bits, see [`DType::to_bits`], so that a graph holding NaN
+/// equals itself and `-0.0` is told from `0.0`, matching the [`Hash`] impl.
+impl<T: DType> PartialEq for Op<T> {
+    // `CustomUnary` functions compare by address, which codegen may duplicate or merge.
+    #[allow(unpredictable_function_pointer_comparisons)]
+    fn eq(&self, other: &Self) -> bool {
+        let same = |a: &T, b: &T| a.to_bits() == b.to_bits();
+        match (self, other) {
+            (Op::Fill { v: a }, Op::Fill { v: b }) => same(a, b),
+            (
+                Op::Arange { start, step, stop },
+                Op::Arange {
+                    start: start2,
+                    step: step2,
+                    stop: stop2,
+                },
+            ) => same(start, start2) && step.to_bits() == step2.to_bits() && same(stop, stop2),
+            (
+                Op::BinaryOp {
+                    l_id,
+                    r_id,
+                    operator,
+                },
+                Op::BinaryOp {
+                    l_id: l_id2,
+                    r_id: r_id2,
+                    operator: operator2,
+                },
+            ) => l_id == l_id2 && r_id == r_id2 && operator == operator2,
+            (
+                Op::UnaryOp { v_id, operator },
+                Op::UnaryOp {
+                    v_id: v_id2,
+                    operator: operator2,
+                },
+            ) => v_id == v_id2 && operator == operator2,
+            (
+                Op::CustomUnary { v_id, name, f },
+                Op::CustomUnary {
+                    v_id: v_id2,
+                    name: name2,
+                    f: f2,
+                },
+            ) => v_id == v_id2 && name == name2 && f == f2,
+            (
+                Op::FusedMulAdd { a_id, b_id, c_id },
+                Op::FusedMulAdd {
+                    a_id: a_id2,
+                    b_id: b_id2,
+                    c_id: c_id2,
+                },
+            ) => a_id == a_id2 && b_id == b_id2 && c_id == c_id2,
+            (
+                Op::MatMul {
+                    l_id,
+                    r_id,
+                    o_id,
+                    k,
+                    alpha,
+                    beta,
+                },
+                Op::MatMul {
+                    l_id: l_id2,
+                    r_id: r_id2,
+                    o_id: o_id2,
+                    k: k2,
+                    alpha: alpha2,
+                    beta: beta2,
+                },
+            ) => {
+                l_id == l_id2
+                    && r_id == r_id2
+                    && o_id == o_id2
+                    && k == k2
+                    && same(alpha, alpha2)
+                    && same(beta, beta2)
+            }
+            (Op::Const { data }, Op::Const { data: data2 }) => {
+                data.len() == data2.len() && data.iter().zip(data2.iter()).all(|(a, b)| same(a, b))
+            }
+            (
+                Op::Randn { mean, std },
+                Op::Randn {
+                    mean: mean2,
+                    std: std2,
+                },
+            ) => same(mean, mean2) && same(std, std2),
+            (Op::Permute { v_id }, Op::Permute { v_id: v_id2 })
+            | (Op::Contiguous { v_id }, Op::Contiguous { v_id: v_id2 })
+            | (Op::Broadcast { v_id }, Op::Broadcast { v_id: v_id2 }) => v_id == v_id2,
+            (Op::Stack { ids }, Op::Stack { ids: ids2 }) => ids == ids2,
+            (
+                Op::Concat { ids, axis },
+                Op::Concat {
+                    ids: ids2,
+                    axis: axis2,
+                },
+            ) => ids == ids2 && axis == axis2,
+            (
+                Op::Slice {
+                    v_id,
+                    offset,
+                    len,
+                    axis,
+                },
+                Op::Slice {
+                    v_id: v_id2,
+                    offset: offset2,
+                    len: len2,
+                    axis: axis2,
+                },
+            ) => v_id == v_id2 && offset == offset2 && len == len2 && axis == axis2,
+            (
+                Op::Diagonal { v_id, offset },
+                Op::Diagonal {
+                    v_id: v_id2,
+                    offset: offset2,
+                },
+            ) => v_id == v_id2 && offset == offset2,
+            (
+                Op::Roll { v_id, shift, axis },
+                Op::Roll {
+                    v_id: v_id2,
+                    shift: shift2,
+                    axis: axis2,
+                },
+            ) => v_id == v_id2 && shift == shift2 && axis == axis2,
+            (
+                Op::Flip { v_id, axis },
+                Op::Flip {
+                    v_id: v_id2,
+                    axis: axis2,
+                },
+            ) => v_id == v_id2 && axis == axis2,
+            (
+                Op::NanToNum {
+                    v_id,
+                    nan,
+                    posinf,
+                    neginf,
+                },
+                Op::NanToNum {
+                    v_id: v_id2,
+                    nan: nan2,
+                    posinf: posinf2,
+                    neginf: neginf2,
+                },
+            ) => v_id == v_id2 && same(nan, nan2) && same(posinf, posinf2) && same(neginf, neginf2),
+            (
+                Op::MaskedFill {
+                    v_id,
+                    mask_id,
+                    value,
+                },
+                Op::MaskedFill {
+                    v_id: v_id2,
+                    mask_id: mask_id2,
+                    value: value2,
+                },
+            ) => v_id == v_id2 && mask_id == mask_id2 && same(value, value2),
+            (
+                Op::Clamp { v_id, min, max },
+                Op::Clamp {
+                    v_id: v_id2,
+                    min: min2,
+                    max: max2,
+                },
+            ) => v_id == v_id2 && same(min, min2) && same(max, max2),
+            (
+                Op::Triangular {
+                    v_id,
+                    upper,
+                    diagonal,
+                },
+                Op::Triangular {
+                    v_id: v_id2,
+                    upper: upper2,
+                    diagonal: diagonal2,
+                },
+            ) => v_id == v_id2 && upper == upper2 && diagonal == diagonal2,
+            (
+                Op::Reduce {
+                    v_id,
+                    axis,
+                    operator,
+                },
+                Op::Reduce {
+                    v_id: v_id2,
+                    axis: axis2,
+                    operator: operator2,
+                },
+            ) => v_id == v_id2 && axis == axis2 && operator == operator2,
+            (
+                Op::ArgReduce { v, axis, kind },
+                Op::ArgReduce {
+                    v: v2,
+                    axis: axis2,
+                    kind: kind2,
+                },
+            ) => v == v2 && axis == axis2 && kind == kind2,
+            (
+                Op::Pad {
+                    v_id,
+                    before,
+                    after,
+                    value,
+                },
+                Op::Pad {
+                    v_id: v_id2,
+                    before: before2,
+                    after: after2,
+                    value: value2,
+                },
+            ) => v_id == v_id2 && before == before2 && after == after2 && same(value, value2),
+            (
+                Op::Gather {
+                    v_id,
+                    idx_id,
+                    axis,
+                    mode,
+                },
+                Op::Gather {
+                    v_id: v_id2,
+                    idx_id: idx_id2,
+                    axis: axis2,
+                    mode: mode2,
+                },
+            ) => v_id == v_id2 && idx_id == idx_id2 && axis == axis2 && mode == mode2,
+            (
+                Op::Scatter {
+                    v_id,
+                    idx_id,
+                    src_id,
+                    axis,
+                    mode,
+                },
+                Op::Scatter {
+                    v_id: v_id2,
+                    idx_id: idx_id2,
+                    src_id: src_id2,
+                    axis: axis2,
+                    mode: mode2,
+                },
+            ) => {
+                v_id == v_id2
+                    && idx_id == idx_id2
+                    && src_id == src_id2
+                    && axis == axis2
+                    && mode == mode2
+            }
+            (Op::Eye, Op::Eye) | (Op::Rand, Op::Rand) | (Op::NoOp, Op::NoOp) => true,
+            _ => false,
+        }
+    }
+}
+
+impl<T: DType> Hash for Op<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        for id in self.input_ids() {
+            id.hash(state);
+            id.is_inplace().hash(state);
+        }
+        match self {
+            Op::Fill { v } => Self::hash_value(v, state),
+            Op::Const { data } => {
+                for v in data.iter() {
+                    Self::hash_value(v, state);
+                }
+            }
+            Op::Arange { start, step, stop } => {
+                Self::hash_value(start, state);
+                step.to_bits().hash(state);
+                Self::hash_value(stop, state);
+            }
+            Op::BinaryOp { operator, .. } => std::mem::discriminant(operator).hash(state),
+            Op::UnaryOp { operator, .. } => std::mem::discriminant(operator).hash(state),
+            // Function addresses change between builds, so the name stands in for `f`.
+            Op::CustomUnary { name, .. } => name.hash(state),
+            Op::MatMul {
+                o_id,
+                k,
+                alpha,
+                beta,
+                ..
+            } => {
+                o_id.is_some().hash(state);
+                k.hash(state);
+                Self::hash_value(alpha, state);
+                Self::hash_value(beta, state);
+            }
+            Op::Randn { mean, std } => {
+                Self::hash_value(mean, state);
+                Self::hash_value(std, state);
+            }
+            Op::Concat { axis, .. } => axis.hash(state),
+            Op::Slice {
+                offset, len, axis, ..
+            } => (offset, len, axis).hash(state),
+            Op::Roll { shift, axis, .. } => (shift, axis).hash(state),
+            Op::Flip { axis, .. } => axis.hash(state),
+            Op::Diagonal { offset, .. } => offset.hash(state),
+            Op::NanToNum {
+                nan,
+                posinf,
+                neginf,
+                ..
+            } => {
+                for v in [nan, posinf, neginf] {
+                    Self::hash_value(v, state);
+                }
+            }
+            Op::Clamp { min, max, .. } => {
+                Self::hash_value(min, state);
+                Self::hash_value(max, state);
+            }
+            Op::MaskedFill { value, .. } => Self::hash_value(value, state),
+            Op::Triangular {
+                upper, diagonal, ..
+            } => (upper, diagonal).hash(state),
+            Op::Reduce { axis, operator, .. } => {
+                axis.hash(state);
+                std::mem::discriminant(operator).hash(state);
+            }
+            Op::ArgReduce { v, axis, kind } => {
+                v.hash(state);
+                axis.hash(state);
+                std::mem::discriminant(kind).hash(state);
+            }
+            Op::Pad {
+                before,
+                after,
+                value,
+                ..
+            } => {
+                before.hash(state);
+                after.hash(state);
+                Self::hash_value(value, state);
+            }
+            Op::Gather { axis, mode, .. } | Op::Scatter { axis, mode, .. } => {
+                axis.hash(state);
+                std::mem::discriminant(mode).hash(state);
+            }
+            Op::FusedMulAdd { .. }
+            | Op::Eye
+            | Op::Rand
+            | Op::Permute { .. }
+            | Op::Contiguous { .. }
+            | Op::Broadcast { .. }
+            | Op::Stack { .. }
+            | Op::NoOp => {}
+        }
+    }
+}
+
 impl Hash for GraphTensorId {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         state.write_usize(self.get());
@@ -844,6 +2462,21 @@ impl GraphTensorId {
     pub fn is_inplace(&self) -> bool {
         matches!(self, Self::InPlace(_))
     }
+
+    /// The id of this node after [`Graph::append`] copied it `offset` nodes further on.
+    pub fn shifted(&self, offset: usize) -> Self {
+        let id = self.detached();
+        id.set(id.get() + offset);
+        id
+    }
+
+    /// A copy of this id with its own storage, so that renumbering one does not affect the other.
+    pub(crate) fn detached(&self) -> Self {
+        match self {
+            Self::OutOfPlace(_) => Self::out_of_place(self.get()),
+            Self::InPlace(_) => Self::inplace(self.get()),
+        }
+    }
 }
 
 // Manually implement equality by comparing the numeric IDs and in‐place flag:
@@ -43,6 +43,7 @@
 //! assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![9.0; 4]; 3],);
 //! ```
 
+mod autograd;
 mod cpu_storage;
 #[cfg(feature = "cuda")]
 mod cuda_backend;
@@ -54,11 +55,17 @@ mod shape;
 mod storage;
 mod tensor;
 
+pub use autograd::Gradients;
 #[cfg(feature = "cuda")]
 pub use device::Cuda;
-pub use device::{BestDevice, Cpu};
+pub use device::{BestDevice, Cpu, Dev};
 pub use dtype::DType;
 pub use error::{Context, Error, Result};
-pub use graph::{CompiledGraph, Graph, GraphNode, Op};
+pub use graph::{
+    ArgReduceType, BinaryOpType, CompiledGraph, CompiledGraphCache, CompiledGraphMulti, Graph,
+    GraphBuilder, GraphNode, GraphOutputs, IndexMode, KernelInfo, MemoryReport, NodeMemory,
+    NodeProfile, Op, PendingTensor, ProfileReport, ReduceMode, ReduceOpType, RunStats, SourceGraph,
+    UnaryOpType,
+};
 pub use shape::{Shape, R1, R2, R3, R4, R5, R6};
-pub use tensor::{GraphTensor, Tensor};
+pub use tensor::{DynGraphTensor, GraphTensor, Mask, Tensor};
@@ -1,6 +1,12 @@
 /// Marker trait for shapes
-pub trait Shape: Clone {
+pub trait Shape: Clone + Send + Sync {
+    /// The number of dimensions.
+    const RANK: usize;
+
+    #[doc(alias = "dims")]
     fn shape() -> Vec<usize>;
+
+    #[doc(alias = "numel")]
     fn element_count() -> usize {
         Self::shape().iter().product()
     }
@@ -12,6 +18,8 @@ macro_rules! shape {
         pub struct $name<$($C $N: usize, )*>;
 
         impl<$($C $N: usize, )*> Shape for $name<$({ $N }, )*> {
+            const RANK: usize = [$(stringify!($N), )*].len();
+
             fn shape() -> Vec<usize> {
                 vec![$($N, )*]
             }
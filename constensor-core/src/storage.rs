@@ -26,6 +26,15 @@ impl<T: DType> Storage<T> {
             Self::Cuda(cuda) => cuda.cast::<U>(),
         }
     }
+
+    /// Block until this storage has been computed. CPU storage always is.
+    pub(crate) fn synchronize(&self) -> Result<()> {
+        match self {
+            Self::Cpu(_) => Ok(()),
+            #[cfg(feature = "cuda")]
+            Self::Cuda(cuda) => cuda.synchronize(),
+        }
+    }
 }
 
 pub trait BackendStorage<T: DType> {
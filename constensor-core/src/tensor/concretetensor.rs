@@ -1,7 +1,7 @@
 use crate::{
     device::{Cpu, Dev},
     storage::Storage,
-    DType, Result, Shape, R1, R2, R3,
+    DType, Error, Result, Shape, R1, R2, R3,
 };
 
 #[cfg(feature = "cuda")]
@@ -9,7 +9,8 @@ use crate::device::Cuda;
 
 use std::{borrow::Cow, marker::PhantomData, ops::Deref, sync::Arc};
 
-use super::contiguous_strides;
+use super::{contiguous_strides, is_contiguous_strides};
+use crate::cpu_storage::{extend_contiguous, CpuStorage};
 
 #[derive(Clone)]
 pub struct Tensor_<S: Shape, T: DType, D: Dev> {
@@ -45,7 +46,7 @@ pub(crate) fn from_storage<S: Shape, T: DType, D: Dev>(
 }
 
 /// Create a Tensor from storage with explicit strides (for views/transposes).
-fn from_storage_strided<S: Shape, T: DType, D: Dev>(
+pub(crate) fn from_storage_strided<S: Shape, T: DType, D: Dev>(
     storage: Arc<Storage<T>>,
     strides: Vec<usize>,
 ) -> Tensor<S, T, D> {
@@ -59,10 +60,20 @@ fn from_storage_strided<S: Shape, T: DType, D: Dev>(
 macro_rules! tensor_api {
     ($device:ty) => {
         impl<T: DType, const A: usize> Tensor<R1<A>, T, $device> {
-            /// Get data for a vector.
+            /// Get data for a vector, respecting strides (supports views).
+            ///
+            /// A contiguous CPU tensor borrows its storage; anything else is copied out.
             pub fn data(&self) -> Result<Cow<'_, Vec<T>>> {
                 let data = self.storage.to_cpu_storage()?;
-                Ok(Cow::Owned(data.into_owned().0))
+                let contiguous = self.strides[0] == 1;
+                Ok(match data {
+                    Cow::Borrowed(cpu) if contiguous && cpu.0.len() == A => Cow::Borrowed(&cpu.0),
+                    Cow::Owned(mut cpu) if contiguous => {
+                        cpu.0.truncate(A);
+                        Cow::Owned(cpu.0)
+                    }
+                    data => Cow::Owned((0..A).map(|i| data.0[i * self.strides[0]]).collect()),
+                })
             }
         }
 
@@ -111,6 +122,59 @@ tensor_api!(Cpu);
 #[cfg(feature = "cuda")]
 tensor_api!(Cuda<0>);
 
+impl<T: DType, const A: usize, const B: usize> Tensor<R2<A, B>, T, Cpu> {
+    /// Iterate over the rows of this matrix as slices into the underlying storage, without copying.
+    ///
+    /// Fails if the rows are not contiguous in memory, e.g. for a transposed view.
+    pub fn rows(&self) -> Result<impl Iterator<Item = &[T]>> {
+        if self.strides[1] != 1 {
+            return Err(Error::InvalidArgument(format!(
+                "rows requires contiguous rows, got strides {:?}",
+                self.strides
+            )));
+        }
+        let data = match self.storage.as_ref() {
+            Storage::Cpu(cpu) => &cpu.0,
+            #[cfg(feature = "cuda")]
+            Storage::Cuda(_) => {
+                return Err(Error::UnsupportedOp {
+                    op: "rows",
+                    backend: "CUDA",
+                })
+            }
+        };
+        let row_stride = self.strides[0];
+        Ok((0..A).map(move |i| &data[i * row_stride..i * row_stride + B]))
+    }
+}
+
+impl<S: Shape, T: DType> Tensor<S, T, Cpu> {
+    /// Build a tensor from the row-major little-endian bytes written by [`Tensor::as_bytes`].
+    ///
+    /// `bytes` must hold exactly `S::element_count()` elements of `T`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let size = std::mem::size_of::<T>();
+        if bytes.len() != S::element_count() * size {
+            return Err(Error::InvalidArgument(format!(
+                "expected {} bytes for shape {:?} of {}, got {}",
+                S::element_count() * size,
+                S::shape(),
+                T::NAME,
+                bytes.len()
+            )));
+        }
+        let mut data = vec![T::ZERO; S::element_count()];
+        // SAFETY: the lengths match and every `DType` is a plain scalar valid for any bytes.
+        let dst =
+            unsafe { std::slice::from_raw_parts_mut(data.as_mut_ptr() as *mut u8, bytes.len()) };
+        dst.copy_from_slice(bytes);
+        if cfg!(target_endian = "big") {
+            dst.chunks_exact_mut(size).for_each(<[u8]>::reverse);
+        }
+        Ok(from_storage(Arc::new(Storage::Cpu(CpuStorage(data)))))
+    }
+}
+
 impl<S: Shape, T: DType, D: Dev> Tensor<S, T, D> {
     /// Cast this tensor to a different dtype `U` on the CPU.
     pub fn cast<U: DType>(&self) -> Result<Tensor<S, U, D>> {
@@ -118,6 +182,151 @@ impl<S: Shape, T: DType, D: Dev> Tensor<S, T, D> {
         let storage = self.storage.cast::<U>()?;
         Ok(from_storage::<S, U, D>(Arc::new(storage)))
     }
+
+    /// Copy this tensor to the device `D2`. Copies between CUDA devices go directly from GPU to
+    /// GPU and keep the layout; everything else goes through the host and comes out row-major.
+    pub fn to_device<D2: Dev>(&self) -> Result<Tensor<S, T, D2>> {
+        match (self.storage.as_ref(), D2::resolve()?) {
+            #[cfg(feature = "cuda")]
+            (Storage::Cuda(cuda), crate::device::Device::Cuda(device)) => Ok(from_storage_strided(
+                Arc::new(Storage::Cuda(cuda.copy_to(&device)?)),
+                self.strides.clone(),
+            )),
+            (_, device) => Ok(from_storage(Arc::new(device.upload(self.to_flat_vec()?)?))),
+        }
+    }
+
+    /// Block until the device has computed this tensor.
+    pub(crate) fn synchronize(&self) -> Result<()> {
+        self.storage.synchronize()
+    }
+
+    /// Mutable access to the storage and strides, if no other tensor shares them.
+    pub(crate) fn storage_mut(&mut self) -> Option<(&mut Storage<T>, &mut Vec<usize>)> {
+        let inner = Arc::get_mut(&mut self.0)?;
+        let storage = Arc::get_mut(&mut inner.storage)?;
+        Some((storage, &mut inner.strides))
+    }
+
+    /// The dimensions of this tensor.
+    pub fn dims(&self) -> Vec<usize> {
+        S::shape()
+    }
+
+    /// Whether the elements are laid out in row-major order in storage. Views such as
+    /// [`Tensor::t`] are not.
+    pub fn is_contiguous(&self) -> bool {
+        is_contiguous_strides(&S::shape(), &self.strides)
+    }
+
+    /// Copy the data out in row-major order, respecting strides (supports views/transposes).
+    ///
+    /// Together with [`Tensor::dims`] this is the layout expected by n-dimensional array crates,
+    /// e.g. `ndarray::ArrayD::from_shape_vec(t.dims(), t.to_flat_vec()?)`. Contiguous tensors are
+    /// copied out of storage in one piece, with a single device-to-host copy on CUDA.
+    #[doc(alias = "to_vec_flat")]
+    pub fn to_flat_vec(&self) -> Result<Vec<T>> {
+        let data = self.storage.to_cpu_storage()?;
+        if self.is_contiguous() {
+            return Ok(match data {
+                Cow::Borrowed(cpu) => cpu.0[..S::element_count()].to_vec(),
+                Cow::Owned(mut cpu) => {
+                    cpu.0.truncate(S::element_count());
+                    cpu.0
+                }
+            });
+        }
+        let mut out = Vec::with_capacity(S::element_count());
+        extend_contiguous(&mut out, &data.as_ref().0, 0, &S::shape(), &self.strides);
+        Ok(out)
+    }
+
+    /// Copy the data into an owned `ndarray` array with this tensor's dimensions, respecting
+    /// strides like [`Tensor::to_flat_vec`].
+    #[cfg(feature = "ndarray")]
+    pub fn to_ndarray(&self) -> Result<ndarray::ArrayD<T>> {
+        let array = ndarray::ArrayD::from_shape_vec(S::shape(), self.to_flat_vec()?);
+        Ok(array.expect("the flat data has one element per index of the shape"))
+    }
+
+    /// The elements as little-endian bytes in row-major order, the inverse of
+    /// [`Tensor::from_bytes`].
+    pub fn as_bytes(&self) -> Result<Vec<u8>> {
+        let data = self.to_flat_vec()?;
+        // SAFETY: every `DType` is a plain scalar without padding bytes.
+        let mut bytes = unsafe {
+            std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(&*data))
+        }
+        .to_vec();
+        if cfg!(target_endian = "big") {
+            bytes
+                .chunks_exact_mut(std::mem::size_of::<T>())
+                .for_each(<[u8]>::reverse);
+        }
+        Ok(bytes)
+    }
+
+    /// Whether every element is within `atol + rtol * |other|` of the one in `other`, like NumPy's
+    /// `allclose` whose defaults are `rtol = 1e-5` and `atol = 1e-8`. Both tensors are copied to
+    /// the host first.
+    ///
+    /// NaNs only compare equal to each other if `equal_nan` is set. Infinities are close only to
+    /// an infinity of the same sign.
+    pub fn allclose<D2: Dev>(
+        &self,
+        other: &Tensor<S, T, D2>,
+        rtol: f64,
+        atol: f64,
+        equal_nan: bool,
+    ) -> Result<bool> {
+        let (lhs, rhs) = (self.to_flat_vec()?, other.to_flat_vec()?);
+        Ok(lhs.iter().zip(&rhs).all(|(a, b)| {
+            let (a, b) = (a.to_f64(), b.to_f64());
+            if a.is_nan() || b.is_nan() {
+                equal_nan && a.is_nan() && b.is_nan()
+            } else if a.is_infinite() || b.is_infinite() {
+                a == b
+            } else {
+                (a - b).abs() <= atol + rtol * b.abs()
+            }
+        }))
+    }
+
+    /// [`Tensor::allclose`] with NumPy's defaults: `rtol = 1e-5`, `atol = 1e-8` and NaNs never
+    /// close.
+    pub fn allclose_default<D2: Dev>(&self, other: &Tensor<S, T, D2>) -> Result<bool> {
+        self.allclose(other, 1e-5, 1e-8, false)
+    }
+
+    /// The lone element of a tensor with a single element, e.g. a loss of shape `R1<1>`.
+    pub fn item(&self) -> Result<T> {
+        if S::element_count() != 1 {
+            return Err(Error::InvalidArgument(format!(
+                "item requires a tensor with one element, got shape {:?}",
+                S::shape()
+            )));
+        }
+        Ok(self.storage.to_cpu_storage()?.0[0])
+    }
+
+    /// Reinterpret this tensor as shape `S2` with the same element count, sharing its storage.
+    ///
+    /// Views such as [`Tensor::t`] are not laid out row-major and cannot be reshaped.
+    pub fn reshape<S2: Shape>(&self) -> Result<Tensor<S2, T, D>> {
+        if S2::element_count() != S::element_count() {
+            return Err(Error::ShapeMismatch {
+                expected: S2::shape(),
+                got: S::shape(),
+            });
+        }
+        if !self.is_contiguous() {
+            crate::bail!(
+                "reshape requires a contiguous tensor, got strides {:?}",
+                self.strides
+            );
+        }
+        Ok(from_storage::<S2, T, D>(Arc::clone(&self.storage)))
+    }
 }
 
 impl<T: DType, const A: usize, const B: usize, D: Dev> Tensor<R2<A, B>, T, D> {
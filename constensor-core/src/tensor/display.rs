@@ -0,0 +1,75 @@
+use std::fmt;
+
+use crate::{device::Dev, DType, Shape, Tensor};
+
+/// Tensors with more elements than this are summarized, like NumPy's default `threshold`.
+const SUMMARIZE_THRESHOLD: usize = 1000;
+/// How many entries are shown at each end of a summarized axis.
+const EDGE_ITEMS: usize = 3;
+
+/// Write the block of `data` for `dims` as nested brackets, starting at column `indent`.
+fn write_nested<T: DType>(
+    f: &mut fmt::Formatter<'_>,
+    data: &[T],
+    dims: &[usize],
+    indent: usize,
+    summarize: bool,
+) -> fmt::Result {
+    let Some((&len, inner)) = dims.split_first() else {
+        return write!(f, "{:?}", data[0]);
+    };
+    let block = inner.iter().product::<usize>();
+    let shown = if summarize && len > 2 * EDGE_ITEMS {
+        (0..EDGE_ITEMS)
+            .map(Some)
+            .chain([None])
+            .chain((len - EDGE_ITEMS..len).map(Some))
+            .collect::<Vec<_>>()
+    } else {
+        (0..len).map(Some).collect()
+    };
+    // Rows of a matrix go on separate lines, with a blank line between higher-rank blocks.
+    let separator = match inner.len() {
+        0 => ", ".to_string(),
+        n => format!(",{}{:indent$}", "\n".repeat(n), "", indent = indent + 1),
+    };
+    write!(f, "[")?;
+    for (i, entry) in shown.into_iter().enumerate() {
+        if i > 0 {
+            write!(f, "{separator}")?;
+        }
+        match entry {
+            Some(k) => {
+                let entries = &data[k * block..(k + 1) * block];
+                write_nested(f, entries, inner, indent + 1, summarize)?;
+            }
+            None => write!(f, "...")?,
+        }
+    }
+    write!(f, "]")
+}
+
+/// Prints the shape and dtype, then the entries like NumPy, summarizing large tensors with
+/// `...`. CUDA tensors are copied to the host first.
+impl<S: Shape, T: DType, D: Dev> fmt::Display for Tensor<S, T, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dims = S::shape();
+        writeln!(
+            f,
+            "Tensor[{}] {}",
+            dims.iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+            T::NAME
+        )?;
+        let data = self.to_flat_vec().map_err(|_| fmt::Error)?;
+        write_nested(f, &data, &dims, 0, data.len() > SUMMARIZE_THRESHOLD)
+    }
+}
+
+impl<S: Shape, T: DType, D: Dev> fmt::Debug for Tensor<S, T, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
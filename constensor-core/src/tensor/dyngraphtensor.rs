@@ -0,0 +1,200 @@
+use std::{
+    marker::PhantomData,
+    ops::{Add, Div, Mul, Rem, Sub},
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    device::Dev,
+    graph::{matmul_dims, BinaryOpType, Graph, GraphTensorId, Op},
+    DType, Error, GraphTensor, Result, Shape,
+};
+
+use super::contiguous_strides;
+
+/// A [`GraphTensor`] whose dimensions are only known at runtime, e.g. a sequence length read
+/// from the input data.
+///
+/// It records into the same graph as `GraphTensor`, but operations check shapes when they are
+/// added rather than at compile time. Convert it into a `GraphTensor` with `TryFrom` once the
+/// shape is fixed, e.g. to compile the graph.
+#[derive(Clone)]
+pub struct DynGraphTensor<T: DType, D: Dev> {
+    id: GraphTensorId,
+    graph: Arc<RwLock<Graph<T>>>,
+    dims: Vec<usize>,
+    strides: Vec<usize>,
+    _ghost: PhantomData<(T, D)>,
+}
+
+impl<T: DType, D: Dev> DynGraphTensor<T, D> {
+    #[must_use]
+    /// Create a tensor of shape `dims` filled with some value.
+    pub fn fill(graph: &mut Graph<T>, dims: &[usize], v: T) -> Self {
+        let id = graph.next_id();
+        let strides = contiguous_strides(dims);
+        graph.add_op_with_shape(Op::Fill { v }, dims.to_vec(), &strides, &id);
+        Self {
+            id,
+            graph: Arc::new(RwLock::new(graph.clone())),
+            dims: dims.to_vec(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    #[must_use]
+    /// Create a tensor of shape `dims` filled with zeros.
+    pub fn zeros(graph: &mut Graph<T>, dims: &[usize]) -> Self {
+        Self::fill(graph, dims, T::ZERO)
+    }
+
+    #[must_use]
+    /// Create a tensor of shape `dims` filled with ones.
+    pub fn ones(graph: &mut Graph<T>, dims: &[usize]) -> Self {
+        Self::fill(graph, dims, T::ONE)
+    }
+
+    /// The dimensions of this tensor.
+    pub fn dims(&self) -> &[usize] {
+        &self.dims
+    }
+
+    /// Get the graph tensor ID.
+    pub fn id(&self) -> GraphTensorId {
+        self.id.clone()
+    }
+
+    /// Elementwise `max(self, rhs)`, see [`GraphTensor::maximum`].
+    pub fn maximum(self, rhs: Self) -> Result<Self> {
+        self.binary(rhs, BinaryOpType::Max)
+    }
+
+    /// Elementwise `min(self, rhs)`, see [`GraphTensor::minimum`].
+    pub fn minimum(self, rhs: Self) -> Result<Self> {
+        self.binary(rhs, BinaryOpType::Min)
+    }
+
+    /// Batched matrix multiplication `(B x M x K) * (B x K x N) = (B x M x N)`, see
+    /// [`GraphTensor::matmul`]. Fails with [`Error::ShapeMismatch`] if the batch or inner
+    /// dimensions differ.
+    pub fn matmul(self, rhs: Self) -> Result<Self> {
+        let [b, m, n, k] = matmul_dims(&self.dims, &rhs.dims, None)?;
+        let strides = contiguous_strides(&[b, m, n]);
+        let op = Op::MatMul {
+            l_id: self.id(),
+            r_id: rhs.id(),
+            o_id: None,
+            k,
+            alpha: T::ZERO,
+            beta: T::ONE,
+        };
+        Ok(self.matmul_node(op, vec![b, m, n], strides))
+    }
+
+    /// Accumulate a matrix product into `out`: `out * alpha + beta * self @ rhs`, see
+    /// [`GraphTensor::matmul_axpby`]. Fails with [`Error::ShapeMismatch`] if the batch or inner
+    /// dimensions differ, or `out` is not `B x M x N`.
+    pub fn matmul_axpby(self, rhs: Self, out: Self, alpha: T, beta: T) -> Result<Self> {
+        let [b, m, n, k] = matmul_dims(&self.dims, &rhs.dims, Some(&out.dims))?;
+        let op = Op::MatMul {
+            l_id: self.id(),
+            r_id: rhs.id(),
+            o_id: Some(out.id()),
+            k,
+            alpha,
+            beta,
+        };
+        Ok(self.matmul_node(op, vec![b, m, n], out.strides))
+    }
+
+    fn matmul_node(self, op: Op<T>, dims: Vec<usize>, strides: Vec<usize>) -> Self {
+        let id = self.graph.write().unwrap().next_id();
+        self.graph
+            .write()
+            .unwrap()
+            .add_op_with_shape(op, dims.clone(), &strides, &id);
+        Self {
+            id,
+            dims,
+            strides,
+            ..self
+        }
+    }
+
+    fn binary(self, rhs: Self, operator: BinaryOpType) -> Result<Self> {
+        if self.dims != rhs.dims {
+            return Err(Error::ShapeMismatch {
+                expected: self.dims,
+                got: rhs.dims,
+            });
+        }
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&self.dims);
+        self.graph.write().unwrap().add_op_with_shape(
+            Op::BinaryOp {
+                l_id: self.id(),
+                r_id: rhs.id(),
+                operator,
+            },
+            self.dims.clone(),
+            &strides,
+            &id,
+        );
+        Ok(Self {
+            id,
+            strides,
+            ..self
+        })
+    }
+}
+
+macro_rules! dyngraphtensor_binop {
+    ($trait:ident, $fn_name:ident) => {
+        impl<T: DType, D: Dev> $trait for DynGraphTensor<T, D> {
+            type Output = Result<DynGraphTensor<T, D>>;
+            /// Add an elementwise operation to the graph, failing if the shapes differ.
+            fn $fn_name(self, rhs: Self) -> Self::Output {
+                self.binary(rhs, BinaryOpType::$trait)
+            }
+        }
+    };
+}
+
+dyngraphtensor_binop!(Add, add);
+dyngraphtensor_binop!(Div, div);
+dyngraphtensor_binop!(Mul, mul);
+dyngraphtensor_binop!(Sub, sub);
+dyngraphtensor_binop!(Rem, rem);
+
+impl<S: Shape, T: DType, D: Dev> From<GraphTensor<S, T, D>> for DynGraphTensor<T, D> {
+    fn from(tensor: GraphTensor<S, T, D>) -> Self {
+        let (id, graph, strides) = tensor.into_parts();
+        Self {
+            id,
+            graph,
+            dims: S::shape(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+}
+
+impl<S: Shape, T: DType, D: Dev> TryFrom<DynGraphTensor<T, D>> for GraphTensor<S, T, D> {
+    type Error = Error;
+
+    /// Fails with [`Error::ShapeMismatch`] unless the dimensions are exactly `S`.
+    fn try_from(tensor: DynGraphTensor<T, D>) -> Result<Self> {
+        if tensor.dims != S::shape() {
+            return Err(Error::ShapeMismatch {
+                expected: S::shape(),
+                got: tensor.dims,
+            });
+        }
+        Ok(GraphTensor::from_parts(
+            tensor.id,
+            tensor.graph,
+            tensor.strides,
+        ))
+    }
+}
@@ -1,16 +1,19 @@
 use std::{
     marker::PhantomData,
-    ops::{Add, Div, Mul, Neg, Sub},
+    ops::{Add, Div, Mul, Neg, Rem, Sub},
     sync::{Arc, RwLock, RwLockReadGuard},
 };
 
 use crate::{
     device::Dev,
-    graph::{BinaryOpType, Graph, GraphTensorId, Op, UnaryOpType},
-    DType, Shape, R1, R2, R3,
+    graph::{
+        ArgReduceType, BinaryOpType, Graph, GraphTensorId, IndexMode, Op, ReduceOpType,
+        SourceGraph, UnaryOpType,
+    },
+    DType, Error, Result, Shape, Tensor, R1, R2, R3,
 };
 
-use super::contiguous_strides;
+use super::{contiguous_strides, is_contiguous_strides, normalize_axis};
 
 /// A tensor representing an intermediary result of a graph. Performing operations
 /// on this tensor will not cause any computations.
@@ -22,6 +25,18 @@ pub struct GraphTensor<S: Shape, T: DType, D: Dev> {
     _ghost: PhantomData<(S, T, D)>,
 }
 
+/// Where an elementwise comparison such as [`GraphTensor::lt`] holds, stored as ones and zeros
+/// of the compared dtype. It picks the entries replaced by [`GraphTensor::masked_fill`].
+#[derive(Clone)]
+pub struct Mask<S: Shape, T: DType, D: Dev>(GraphTensor<S, T, D>);
+
+impl<S: Shape, T: DType, D: Dev> Mask<S, T, D> {
+    /// The mask as a tensor of ones and zeros, e.g. to count its entries.
+    pub fn into_tensor(self) -> GraphTensor<S, T, D> {
+        self.0
+    }
+}
+
 impl<const B: usize, const M: usize, const K: usize, T: DType, D: Dev>
     GraphTensor<R3<B, M, K>, T, D>
 {
@@ -32,6 +47,7 @@ impl<const B: usize, const M: usize, const K: usize, T: DType, D: Dev>
         rhs: GraphTensor<R3<B, K, N>, T, D>,
     ) -> GraphTensor<R3<B, M, N>, T, D> {
         let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&[B, M, N]);
         self.graph.write().unwrap().add_op::<R3<B, M, N>>(
             Op::MatMul {
                 l_id: self.id(),
@@ -41,20 +57,22 @@ impl<const B: usize, const M: usize, const K: usize, T: DType, D: Dev>
                 alpha: T::ZERO,
                 beta: T::ONE,
             },
-            &self.strides,
+            &strides,
             &id,
         );
         GraphTensor {
             id,
             graph: self.graph.clone(),
-            strides: self.strides.clone(),
+            strides,
             _ghost: PhantomData,
         }
     }
 
     #[must_use]
     // Matrix multiplication: (B x M x K) * (B x K x N) = (B x M x N)
-    /// out = out * alpha + beta * lhs * rhs
+    /// Accumulate a matrix product into `out`: `out * alpha + beta * self @ rhs`.
+    ///
+    /// The result reuses `out`'s buffer when nothing else reads `out`, and keeps its layout.
     pub fn matmul_axpby<const N: usize>(
         self,
         rhs: GraphTensor<R3<B, K, N>, T, D>,
@@ -63,6 +81,8 @@ impl<const B: usize, const M: usize, const K: usize, T: DType, D: Dev>
         beta: T,
     ) -> GraphTensor<R3<B, M, N>, T, D> {
         let id = self.graph.write().unwrap().next_id();
+        // The product is accumulated into `out`'s buffer, so it keeps that layout.
+        let strides = out.strides.clone();
         self.graph.write().unwrap().add_op::<R3<B, M, N>>(
             Op::MatMul {
                 l_id: self.id(),
@@ -72,13 +92,68 @@ impl<const B: usize, const M: usize, const K: usize, T: DType, D: Dev>
                 alpha,
                 beta,
             },
-            &self.strides,
+            &strides,
+            &id,
+        );
+        GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    #[must_use]
+    /// Accumulate a matrix product into the leading `M x N` block of each batch of a larger
+    /// `out`, like [`GraphTensor::matmul_axpby`]. The rest of `out` is kept, so several products
+    /// can be written into disjoint parts of one buffer.
+    pub fn matmul_axpby_into<const N: usize, const MO: usize, const NO: usize>(
+        self,
+        rhs: GraphTensor<R3<B, K, N>, T, D>,
+        out: GraphTensor<R3<B, MO, NO>, T, D>,
+        alpha: T,
+        beta: T,
+    ) -> GraphTensor<R3<B, MO, NO>, T, D> {
+        const { assert!(M <= MO && N <= NO, "the product must fit into the output") };
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = out.strides.clone();
+        self.graph.write().unwrap().add_op::<R3<B, MO, NO>>(
+            Op::MatMul {
+                l_id: self.id(),
+                r_id: rhs.id(),
+                o_id: Some(out.id()),
+                k: K,
+                alpha,
+                beta,
+            },
+            &strides,
+            &id,
+        );
+        GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    /// View a single batch as a matrix, keeping its strides.
+    fn unbatched(&self) -> GraphTensor<R2<M, K>, T, D> {
+        const { assert!(B == 1, "only a single batch can be viewed as a matrix") };
+        let strides = self.strides[1..].to_vec();
+        let id = self.graph.write().unwrap().next_id();
+        self.graph.write().unwrap().add_op::<R2<M, K>>(
+            Op::Permute {
+                v_id: self.id.clone(),
+            },
+            &strides,
             &id,
         );
         GraphTensor {
             id,
             graph: self.graph.clone(),
-            strides: self.strides.clone(),
+            strides,
             _ghost: PhantomData,
         }
     }
@@ -111,22 +186,292 @@ impl<S: Shape, T: DType, D: Dev> GraphTensor<S, T, D> {
         Self::fill(graph, T::ONE)
     }
 
+    #[must_use]
+    /// Replace NaN with `nan`, positive infinity with `posinf` and negative infinity with `neginf`.
+    /// Only available for floating point dtypes.
+    pub fn nan_to_num(self, nan: T, posinf: T, neginf: T) -> GraphTensor<S, T, D> {
+        const {
+            assert!(
+                !T::INTEGRAL,
+                "nan_to_num is only supported for float dtypes"
+            )
+        };
+
+        let id = self.graph.write().unwrap().next_id();
+
+        let strides = contiguous_strides(&S::shape());
+        self.graph.write().unwrap().add_op::<S>(
+            Op::NanToNum {
+                v_id: self.id(),
+                nan,
+                posinf,
+                neginf,
+            },
+            &strides,
+            &id,
+        );
+        Self {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    #[must_use]
+    /// Limit every element to the range `[min, max]`.
+    pub fn clamp(self, min: T, max: T) -> GraphTensor<S, T, D> {
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
+        self.graph.write().unwrap().add_op::<S>(
+            Op::Clamp {
+                v_id: self.id(),
+                min,
+                max,
+            },
+            &strides,
+            &id,
+        );
+        Self {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    #[must_use]
+    /// Raise every element below `min` to `min`, e.g. `clamp_min(0.0)` for a ReLU. Unlike
+    /// [`GraphTensor::maximum`] with a fill, no buffer of `min` values is allocated. NaNs pass
+    /// through.
+    pub fn clamp_min(self, min: T) -> GraphTensor<S, T, D> {
+        self.clamp(min, T::MAX)
+    }
+
+    #[must_use]
+    /// Lower every element above `max` to `max`, without allocating a buffer of `max` values.
+    /// NaNs pass through.
+    pub fn clamp_max(self, max: T) -> GraphTensor<S, T, D> {
+        self.clamp(T::MIN, max)
+    }
+
+    #[must_use]
+    /// Set the elements where `mask` is true to `value` and keep the others, e.g. to hide
+    /// attention scores with a large negative value. No buffer of `value`s is allocated.
+    pub fn masked_fill(self, mask: Mask<S, T, D>, value: T) -> GraphTensor<S, T, D> {
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
+        self.graph.write().unwrap().add_op::<S>(
+            Op::MaskedFill {
+                v_id: self.id(),
+                mask_id: mask.0.id(),
+                value,
+            },
+            &strides,
+            &id,
+        );
+        Self {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    #[must_use]
+    /// Where `self == rhs`, as a mask for [`GraphTensor::masked_fill`].
+    pub fn eq(self, rhs: Self) -> Mask<S, T, D> {
+        self.compare(rhs, BinaryOpType::Eq)
+    }
+
+    #[must_use]
+    /// Where `self != rhs`, which includes every NaN, like [`GraphTensor::eq`].
+    pub fn ne(self, rhs: Self) -> Mask<S, T, D> {
+        self.compare(rhs, BinaryOpType::Ne)
+    }
+
+    #[must_use]
+    /// Where `self < rhs`, like [`GraphTensor::eq`].
+    pub fn lt(self, rhs: Self) -> Mask<S, T, D> {
+        self.compare(rhs, BinaryOpType::Lt)
+    }
+
+    #[must_use]
+    /// Where `self <= rhs`, like [`GraphTensor::eq`].
+    pub fn le(self, rhs: Self) -> Mask<S, T, D> {
+        self.compare(rhs, BinaryOpType::Le)
+    }
+
+    #[must_use]
+    /// Where `self > rhs`, like [`GraphTensor::eq`].
+    pub fn gt(self, rhs: Self) -> Mask<S, T, D> {
+        self.compare(rhs, BinaryOpType::Gt)
+    }
+
+    #[must_use]
+    /// Where `self >= rhs`, like [`GraphTensor::eq`].
+    pub fn ge(self, rhs: Self) -> Mask<S, T, D> {
+        self.compare(rhs, BinaryOpType::Ge)
+    }
+
+    fn compare(self, rhs: Self, operator: BinaryOpType) -> Mask<S, T, D> {
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
+        self.graph.write().unwrap().add_op::<S>(
+            Op::BinaryOp {
+                l_id: self.id(),
+                r_id: rhs.id(),
+                operator,
+            },
+            &strides,
+            &id,
+        );
+        Mask(Self {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        })
+    }
+
+    #[must_use]
+    /// Elementwise maximum of `self` and `rhs`. A NaN is ignored in favour of the other operand.
+    pub fn maximum(self, rhs: Self) -> GraphTensor<S, T, D> {
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
+        self.graph.write().unwrap().add_op::<S>(
+            Op::BinaryOp {
+                l_id: self.id(),
+                r_id: rhs.id(),
+                operator: BinaryOpType::Max,
+            },
+            &strides,
+            &id,
+        );
+        Self {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    #[must_use]
+    /// Elementwise minimum of `self` and `rhs`. A NaN is ignored in favour of the other operand.
+    pub fn minimum(self, rhs: Self) -> GraphTensor<S, T, D> {
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
+        self.graph.write().unwrap().add_op::<S>(
+            Op::BinaryOp {
+                l_id: self.id(),
+                r_id: rhs.id(),
+                operator: BinaryOpType::Min,
+            },
+            &strides,
+            &id,
+        );
+        Self {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    #[must_use]
+    /// Keep the entries on and above the `diagonal`-th diagonal of each matrix in the last two
+    /// dimensions, zeroing the rest. Offset 0 is the main diagonal, positive offsets lie above it.
+    pub fn triu(self, diagonal: isize) -> GraphTensor<S, T, D> {
+        self.triangular(true, diagonal)
+    }
+
+    #[must_use]
+    /// Keep the entries on and below the `diagonal`-th diagonal of each matrix in the last two
+    /// dimensions, zeroing the rest. Offset 0 is the main diagonal, positive offsets lie above it.
+    pub fn tril(self, diagonal: isize) -> GraphTensor<S, T, D> {
+        self.triangular(false, diagonal)
+    }
+
+    fn triangular(self, upper: bool, diagonal: isize) -> GraphTensor<S, T, D> {
+        const { assert!(S::RANK >= 2, "triu and tril need at least two dimensions") };
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
+        self.graph.write().unwrap().add_op::<S>(
+            Op::Triangular {
+                v_id: self.id(),
+                upper,
+                diagonal,
+            },
+            &strides,
+            &id,
+        );
+        Self {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    #[must_use]
+    /// Clamp probabilities into `[T::EPSILON, 1 - T::EPSILON]`, e.g. before taking their log in a
+    /// BCE loss. Use [`GraphTensor::clamp_prob_eps`] for another margin.
+    pub fn clamp_prob(self) -> GraphTensor<S, T, D> {
+        self.clamp_prob_eps(T::EPSILON)
+    }
+
+    #[must_use]
+    /// Clamp probabilities into `[eps, 1 - eps]`.
+    pub fn clamp_prob_eps(self, eps: T) -> GraphTensor<S, T, D> {
+        self.clamp(eps, T::ONE - eps)
+    }
+
     #[must_use]
     /// Elementwise unary square root.
     pub fn sqrt(self) -> GraphTensor<S, T, D> {
         let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
         self.graph.write().unwrap().add_op::<S>(
             Op::UnaryOp {
                 v_id: self.id(),
                 operator: UnaryOpType::Sqrt,
             },
-            &self.strides,
+            &strides,
+            &id,
+        );
+        Self {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    #[must_use]
+    /// Apply `f` to every element, for functions without a built-in op.
+    ///
+    /// `name` stands in for `f` in [`Graph::structural_hash`], so give different functions
+    /// different names.
+    ///
+    /// Only the CPU backend can call a Rust function, so compiling for CUDA fails with
+    /// [`Error::UnsupportedOp`].
+    pub fn map_cpu(self, name: &'static str, f: fn(T) -> T) -> GraphTensor<S, T, D> {
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
+        self.graph.write().unwrap().add_op::<S>(
+            Op::CustomUnary {
+                v_id: self.id(),
+                name,
+                f,
+            },
+            &strides,
             &id,
         );
         Self {
             id,
             graph: self.graph.clone(),
-            strides: self.strides.clone(),
+            strides,
             _ghost: PhantomData,
         }
     }
@@ -135,18 +480,19 @@ impl<S: Shape, T: DType, D: Dev> GraphTensor<S, T, D> {
     /// Elementwise unary exponential function.  
     pub fn exp(self) -> GraphTensor<S, T, D> {
         let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
         self.graph.write().unwrap().add_op::<S>(
             Op::UnaryOp {
                 v_id: self.id(),
                 operator: UnaryOpType::Exp,
             },
-            &self.strides,
+            &strides,
             &id,
         );
         Self {
             id,
             graph: self.graph.clone(),
-            strides: self.strides.clone(),
+            strides,
             _ghost: PhantomData,
         }
     }
@@ -155,18 +501,19 @@ impl<S: Shape, T: DType, D: Dev> GraphTensor<S, T, D> {
     /// Elementwise unary base-2 exponential function.  
     pub fn exp2(self) -> GraphTensor<S, T, D> {
         let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
         self.graph.write().unwrap().add_op::<S>(
             Op::UnaryOp {
                 v_id: self.id(),
                 operator: UnaryOpType::Exp2,
             },
-            &self.strides,
+            &strides,
             &id,
         );
         Self {
             id,
             graph: self.graph.clone(),
-            strides: self.strides.clone(),
+            strides,
             _ghost: PhantomData,
         }
     }
@@ -175,18 +522,19 @@ impl<S: Shape, T: DType, D: Dev> GraphTensor<S, T, D> {
     /// Elementwise unary natural logarithm function.  
     pub fn log(self) -> GraphTensor<S, T, D> {
         let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
         self.graph.write().unwrap().add_op::<S>(
             Op::UnaryOp {
                 v_id: self.id(),
                 operator: UnaryOpType::Log,
             },
-            &self.strides,
+            &strides,
             &id,
         );
         Self {
             id,
             graph: self.graph.clone(),
-            strides: self.strides.clone(),
+            strides,
             _ghost: PhantomData,
         }
     }
@@ -195,18 +543,19 @@ impl<S: Shape, T: DType, D: Dev> GraphTensor<S, T, D> {
     /// Elementwise unary natural logarithm of (1+x) function.  
     pub fn log1p(self) -> GraphTensor<S, T, D> {
         let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
         self.graph.write().unwrap().add_op::<S>(
             Op::UnaryOp {
                 v_id: self.id(),
                 operator: UnaryOpType::Log1p,
             },
-            &self.strides,
+            &strides,
             &id,
         );
         Self {
             id,
             graph: self.graph.clone(),
-            strides: self.strides.clone(),
+            strides,
             _ghost: PhantomData,
         }
     }
@@ -241,6 +590,25 @@ impl<S: Shape, T: DType, D: Dev> GraphTensor<S, T, D> {
 }
 
 impl<S: Shape, T: DType, D: Dev> GraphTensor<S, T, D> {
+    /// Wrap a node of `graph` whose shape is `S`.
+    pub(crate) fn from_parts(
+        id: GraphTensorId,
+        graph: Arc<RwLock<Graph<T>>>,
+        strides: Vec<usize>,
+    ) -> Self {
+        Self {
+            id,
+            graph,
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    /// The node id, graph and strides of this tensor.
+    pub(crate) fn into_parts(self) -> (GraphTensorId, Arc<RwLock<Graph<T>>>, Vec<usize>) {
+        (self.id, self.graph, self.strides)
+    }
+
     /// Retrieve the graph for this `GraphTensor`.
     pub fn graph(&self) -> RwLockReadGuard<'_, Graph<T>> {
         self.graph.read().unwrap()
@@ -250,20 +618,136 @@ impl<S: Shape, T: DType, D: Dev> GraphTensor<S, T, D> {
     pub fn id(&self) -> GraphTensorId {
         self.id.clone()
     }
-}
 
-impl<const A: usize, T: DType, D: Dev> GraphTensor<R1<A>, T, D> {
-    #[must_use]
-    /// A GraphTensor representing a vector ranging from `start` to `stop` with `step` computed using A.
-    pub fn arange(graph: &mut Graph<T>, start: T, stop: T) -> Self {
-        let id = graph.next_id();
-        let step = (stop.to_f64() - start.to_f64()) / (A as f64);
-        let strides = contiguous_strides(&[A]);
-        graph.add_op::<R1<A>>(
+    /// The dimensions recorded on this tensor's graph node, read without running the graph.
+    pub fn shape_dims(&self) -> Vec<usize> {
+        self.graph().get_ops()[self.id.get()].shape.clone()
+    }
+
+    /// Compute this tensor now, leaving the graph untouched so it can still be built upon.
+    ///
+    /// Only the nodes this tensor depends on are run, in a separate copy of the graph.
+    pub fn eval(&self) -> Result<Tensor<S, T, D>> {
+        let subgraph = self.graph.read().unwrap().detached_subgraph(&self.id);
+        subgraph.compile::<S, D>()?.run()
+    }
+
+    /// Compute this tensor now and return it as a constant in the same graph.
+    ///
+    /// Only the nodes this tensor depends on are run. Ops built on the result read the constant, so
+    /// once nothing else uses them, [`Graph::optimize`] prunes the upstream ops from later compiles.
+    pub fn eval_to_const(self) -> Result<GraphTensor<S, T, D>> {
+        let data = self.eval()?.to_flat_vec()?;
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
+        self.graph.write().unwrap().add_op::<S>(
+            Op::Const {
+                data: Arc::new(data),
+            },
+            &strides,
+            &id,
+        );
+        Ok(GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        })
+    }
+
+    /// Copy this tensor into row-major order, e.g. to materialize a view such as
+    /// [`GraphTensor::t`] before [`GraphTensor::flatten_to`].
+    ///
+    /// On CUDA, only already contiguous tensors are supported.
+    #[must_use]
+    pub fn contiguous(self) -> Self {
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
+        self.graph
+            .write()
+            .unwrap()
+            .add_op::<S>(Op::Contiguous { v_id: self.id() }, &strides, &id);
+        GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    /// View the same elements as shape `O`, which must have the same element count.
+    fn view<O: Shape>(&self) -> GraphTensor<O, T, D> {
+        let strides = contiguous_strides(&O::shape());
+        let id = self.graph.write().unwrap().next_id();
+        self.graph.write().unwrap().add_op::<O>(
+            Op::Permute {
+                v_id: self.id.clone(),
+            },
+            &strides,
+            &id,
+        );
+        GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    /// Flatten into a vector of the `N` elements in row-major order. This only changes the shape,
+    /// no data is copied.
+    ///
+    /// `N` must be the element count of `S`. Views such as [`GraphTensor::t`] are not laid out
+    /// row-major and cannot be flattened.
+    pub fn flatten_to<const N: usize>(&self) -> Result<GraphTensor<R1<N>, T, D>> {
+        if N != S::element_count() {
+            return Err(Error::ShapeMismatch {
+                expected: vec![N],
+                got: S::shape(),
+            });
+        }
+        if !is_contiguous_strides(&S::shape(), &self.strides) {
+            crate::bail!(
+                "flatten requires a contiguous tensor, got strides {:?}",
+                self.strides
+            );
+        }
+        Ok(self.view())
+    }
+}
+
+impl<const A: usize, T: DType, D: Dev> GraphTensor<R1<A>, T, D> {
+    #[must_use]
+    /// A GraphTensor representing a vector ranging from `start` to `stop` with `step` computed using A.
+    pub fn arange(graph: &mut Graph<T>, start: T, stop: T) -> Self {
+        let id = graph.next_id();
+        let mut step = (stop.to_f64() - start.to_f64()) / (A as f64);
+        if T::INTEGRAL {
+            // Integer aranges step by a whole number.
+            step = T::from_f64(step).to_f64();
+        }
+        let strides = contiguous_strides(&[A]);
+        graph.add_op::<R1<A>>(Op::Arange { start, step, stop }, &strides, &id);
+        Self {
+            id,
+            graph: Arc::new(RwLock::new(graph.clone())),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    #[must_use]
+    /// A GraphTensor representing the A values `start, start + step, ..., start + (A - 1) * step`.
+    pub fn arange_step(graph: &mut Graph<T>, start: T, step: T) -> Self {
+        let id = graph.next_id();
+        let stop = start.to_f64() + step.to_f64() * (A as f64);
+        let strides = contiguous_strides(&[A]);
+        graph.add_op::<R1<A>>(
             Op::Arange {
                 start,
-                step: T::from_f64(step),
-                stop,
+                step: step.to_f64(),
+                stop: T::from_f64(stop),
             },
             &strides,
             &id,
@@ -275,9 +759,150 @@ impl<const A: usize, T: DType, D: Dev> GraphTensor<R1<A>, T, D> {
             _ghost: PhantomData,
         }
     }
+
+    #[must_use]
+    /// Dot product of two vectors, `sum(a * b)`, computed as a (1 x A) * (A x 1) matrix
+    /// multiplication so the products are never materialized.
+    ///
+    /// Products are accumulated in `T`. Integer, `f16` and `bf16` kernels sum in index order, while
+    /// `f32` and `f64` use blocked `gemm`/cuBLAS kernels whose summation order is unspecified, so
+    /// results may differ from a sequential sum in the last bits.
+    pub fn dot(self, rhs: GraphTensor<R1<A>, T, D>) -> GraphTensor<R1<1>, T, D> {
+        let lhs = self.view::<R3<1, 1, A>>();
+        let rhs = rhs.view::<R3<1, A, 1>>();
+        lhs.matmul(rhs).view::<R1<1>>()
+    }
+
+    #[must_use]
+    /// Outer product of two vectors, computed as an (A x 1) * (1 x B) matrix multiplication.
+    pub fn outer<const B: usize>(
+        self,
+        rhs: GraphTensor<R1<B>, T, D>,
+    ) -> GraphTensor<R2<A, B>, T, D> {
+        let lhs = self.view::<R3<1, A, 1>>();
+        let rhs = rhs.view::<R3<1, 1, B>>();
+        lhs.matmul(rhs).view::<R2<A, B>>()
+    }
+}
+
+impl<T: DType, const N: usize, D: Dev> GraphTensor<R2<N, N>, T, D> {
+    #[must_use]
+    /// A GraphTensor representing the N x N identity matrix.
+    pub fn eye(graph: &mut Graph<T>) -> Self {
+        let id = graph.next_id();
+        let strides = contiguous_strides(&[N, N]);
+        graph.add_op::<R2<N, N>>(Op::Eye, &strides, &id);
+        Self {
+            id,
+            graph: Arc::new(RwLock::new(graph.clone())),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    #[must_use]
+    /// The main diagonal, `out[i] = self[i][i]`.
+    pub fn diagonal(self) -> GraphTensor<R1<N>, T, D> {
+        self.diagonal_offset(0)
+            .expect("the main diagonal of a square matrix has its length")
+    }
 }
 
 impl<T: DType, const A: usize, const B: usize, D: Dev> GraphTensor<R2<A, B>, T, D> {
+    #[must_use]
+    // Matrix multiplication: (A x B) * (B x N) = (A x N), as a batched matmul of one batch.
+    pub fn matmul<const N: usize>(
+        self,
+        rhs: GraphTensor<R2<B, N>, T, D>,
+    ) -> GraphTensor<R2<A, N>, T, D> {
+        self.batched().matmul(rhs.batched()).unbatched()
+    }
+
+    #[must_use]
+    /// Accumulate a matrix product into `out`: `out * alpha + beta * self @ rhs`, see
+    /// [`GraphTensor::matmul_axpby`] for batches.
+    pub fn matmul_axpby<const N: usize>(
+        self,
+        rhs: GraphTensor<R2<B, N>, T, D>,
+        out: GraphTensor<R2<A, N>, T, D>,
+        alpha: T,
+        beta: T,
+    ) -> GraphTensor<R2<A, N>, T, D> {
+        self.batched()
+            .matmul_axpby(rhs.batched(), out.batched(), alpha, beta)
+            .unbatched()
+    }
+
+    #[must_use]
+    /// Accumulate a matrix product into the leading `A x N` block of a larger `out`, see
+    /// [`GraphTensor::matmul_axpby_into`] for batches.
+    pub fn matmul_axpby_into<const N: usize, const AO: usize, const NO: usize>(
+        self,
+        rhs: GraphTensor<R2<B, N>, T, D>,
+        out: GraphTensor<R2<AO, NO>, T, D>,
+        alpha: T,
+        beta: T,
+    ) -> GraphTensor<R2<AO, NO>, T, D> {
+        self.batched()
+            .matmul_axpby_into(rhs.batched(), out.batched(), alpha, beta)
+            .unbatched()
+    }
+
+    /// The diagonal `offset` places above the main one, or below it if `offset` is negative:
+    /// `out[i] = self[i][i + offset]`. `O` is `R1<L>` with `L` the length of that diagonal.
+    pub fn diagonal_offset<O: Shape>(self, offset: isize) -> Result<GraphTensor<O, T, D>> {
+        let (rows, cols) = (A as isize - (-offset).max(0), B as isize - offset.max(0));
+        let len = rows.min(cols);
+        if len <= 0 {
+            return Err(Error::InvalidArgument(format!(
+                "diagonal offset {offset} is out of range for shape {:?}",
+                [A, B]
+            )));
+        }
+        if O::shape() != [len as usize] {
+            return Err(Error::ShapeMismatch {
+                expected: vec![len as usize],
+                got: O::shape(),
+            });
+        }
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&O::shape());
+        self.graph.write().unwrap().add_op::<O>(
+            Op::Diagonal {
+                v_id: self.id(),
+                offset,
+            },
+            &strides,
+            &id,
+        );
+        Ok(GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        })
+    }
+
+    /// View this matrix as a single batch, keeping its strides so transposed views still work.
+    fn batched(&self) -> GraphTensor<R3<1, A, B>, T, D> {
+        let strides = vec![A * B, self.strides[0], self.strides[1]];
+        let id = self.graph.write().unwrap().next_id();
+        self.graph.write().unwrap().add_op::<R3<1, A, B>>(
+            Op::Permute {
+                v_id: self.id.clone(),
+            },
+            &strides,
+            &id,
+        );
+        GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
     /// Return a view of this matrix with dimensions transposed (A x B -> B x A).
     pub fn t(&self) -> GraphTensor<R2<B, A>, T, D> {
         // swap strides for first two dimensions
@@ -300,6 +925,144 @@ impl<T: DType, const A: usize, const B: usize, D: Dev> GraphTensor<R2<A, B>, T,
             _ghost: PhantomData,
         }
     }
+
+    /// Take rows `START..START + LEN` of this matrix.
+    ///
+    /// Returns an error if the range does not fit within the `A` rows.
+    pub fn slice_rows<const START: usize, const LEN: usize>(
+        &self,
+    ) -> Result<GraphTensor<R2<LEN, B>, T, D>> {
+        if START + LEN > A {
+            return Err(Error::InvalidArgument(format!(
+                "row slice {START}..{} is out of range for a matrix with {A} rows",
+                START + LEN
+            )));
+        }
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&[LEN, B]);
+        self.graph.write().unwrap().add_op::<R2<LEN, B>>(
+            Op::Slice {
+                v_id: self.id.clone(),
+                offset: START,
+                len: LEN,
+                axis: 0,
+            },
+            &strides,
+            &id,
+        );
+        Ok(GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        })
+    }
+}
+
+impl<T: DType, const N: usize, const C: usize, D: Dev> GraphTensor<R2<N, C>, T, D> {
+    #[must_use]
+    /// The log of the softmax over each row, with the row maximum subtracted first for stability.
+    pub fn log_softmax(self) -> Self {
+        let max = self.clone().max_axis::<R2<N, 1>>(1).unwrap();
+        let shifted = self - Self::expand_axis(max, 1);
+        let log_sum = shifted.clone().exp().sum_axis::<R2<N, 1>>(1).unwrap().log();
+        shifted - Self::expand_axis(log_sum, 1)
+    }
+
+    #[must_use]
+    /// Scale each row to unit L2 norm, `x / max(|x|, T::EPSILON)`, so that zero rows stay zero.
+    pub fn normalize(self) -> Self {
+        const { assert!(!T::INTEGRAL, "normalize is only supported for float dtypes") };
+
+        let norm = (self.clone() * self.clone())
+            .sum_axis::<R2<N, 1>>(1)
+            .unwrap()
+            .sqrt()
+            .clamp_min(T::EPSILON);
+        self / Self::expand_axis(norm, 1)
+    }
+
+    #[must_use]
+    /// Normalize each row to zero mean and unit variance, `(x - mean) / sqrt(var + T::EPSILON)`,
+    /// as layer norm does before its affine transform. The variance is the biased one.
+    pub fn layer_norm(self) -> Self {
+        const {
+            assert!(
+                !T::INTEGRAL,
+                "layer_norm is only supported for float dtypes"
+            )
+        };
+
+        let inv_len = GraphTensor::<R2<N, 1>, T, D>::fill(
+            &mut self.graph.write().unwrap(),
+            T::from_f64(1.0 / C as f64),
+        );
+        let mean = self.clone().sum_axis::<R2<N, 1>>(1).unwrap() * inv_len.clone();
+        let centered = self - Self::expand_axis(mean, 1);
+        let var = (centered.clone() * centered.clone())
+            .sum_axis::<R2<N, 1>>(1)
+            .unwrap()
+            * inv_len;
+        let eps = GraphTensor::<R2<N, 1>, T, D>::fill(&mut var.graph.write().unwrap(), T::EPSILON);
+        centered / Self::expand_axis((var + eps).sqrt(), 1)
+    }
+
+    #[must_use]
+    /// The mean negative log-likelihood of the class `targets[i]` under the logits of row `i`.
+    ///
+    /// Targets are class indices stored as values of `T`, as for [`GraphTensor::gather`], since a graph
+    /// holds a single dtype. Out-of-range targets fail the run.
+    pub fn cross_entropy(self, targets: GraphTensor<R1<N>, T, D>) -> GraphTensor<R1<1>, T, D> {
+        const {
+            assert!(
+                !T::INTEGRAL,
+                "cross_entropy is only supported for float dtypes"
+            )
+        };
+
+        let picked = self
+            .log_softmax()
+            .gather(1, targets.view::<R2<N, 1>>(), IndexMode::Error)
+            .expect("an N x 1 index always fits an N x C tensor along axis 1");
+        let total = picked.sum_axis::<R2<1, 1>>(0).unwrap();
+        let scale = GraphTensor::<R2<1, 1>, T, D>::fill(
+            &mut total.graph.write().unwrap(),
+            T::from_f64(-1.0 / N as f64),
+        );
+        (total * scale).view::<R1<1>>()
+    }
+}
+
+impl<T: DType, const A: usize, const N: usize, D: Dev> GraphTensor<R2<A, N>, T, D> {
+    /// Concatenate `rhs` below this matrix, yielding `A + B` rows.
+    ///
+    /// The output row count `O` is checked at compile time: instantiating this with `O != A + B`
+    /// fails to build.
+    #[must_use]
+    pub fn cat0<const B: usize, const O: usize>(
+        self,
+        rhs: GraphTensor<R2<B, N>, T, D>,
+    ) -> GraphTensor<R2<O, N>, T, D> {
+        const { assert!(A + B == O, "cat0 output must have A + B rows") };
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&[O, N]);
+        self.graph.write().unwrap().add_op::<R2<O, N>>(
+            Op::Concat {
+                ids: vec![self.id.clone(), rhs.id.clone()],
+                axis: 0,
+            },
+            &strides,
+            &id,
+        );
+        GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
 }
 
 impl<T: DType, const A: usize, const B: usize, const C: usize, D: Dev>
@@ -329,6 +1092,522 @@ impl<T: DType, const A: usize, const B: usize, const C: usize, D: Dev>
     }
 }
 
+impl<S: Shape, T: DType, D: Dev> GraphTensor<S, T, D> {
+    /// Stack `N` tensors of shape `S` along a new leading dimension of length `N`.
+    ///
+    /// The output shape `O` must be `S` with `N` prepended, e.g. stacking three `R1<4>` yields `R2<3, 4>`.
+    pub fn stack<const N: usize, O: Shape>(tensors: [Self; N]) -> Result<GraphTensor<O, T, D>> {
+        let Some(first) = tensors.first() else {
+            return Err(Error::InvalidArgument(
+                "stack requires at least one tensor".to_string(),
+            ));
+        };
+        let mut expected = vec![N];
+        expected.extend(S::shape());
+        if O::shape() != expected {
+            return Err(Error::ShapeMismatch {
+                expected,
+                got: O::shape(),
+            });
+        }
+
+        let id = first.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&expected);
+        first.graph.write().unwrap().add_op::<O>(
+            Op::Stack {
+                ids: tensors.iter().map(|t| t.id()).collect(),
+            },
+            &strides,
+            &id,
+        );
+        Ok(GraphTensor {
+            id,
+            graph: first.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        })
+    }
+
+    /// Pad each axis `i` with `before[i]` leading and `after[i]` trailing entries of `value`.
+    ///
+    /// `N` must be the rank of `S` and the output shape `O` must grow each axis by `before[i] + after[i]`.
+    pub fn pad<const N: usize, O: Shape>(
+        self,
+        before: [usize; N],
+        after: [usize; N],
+        value: T,
+    ) -> Result<GraphTensor<O, T, D>> {
+        let shape = S::shape();
+        if N != shape.len() {
+            return Err(Error::InvalidArgument(format!(
+                "pad needs one width per axis, got {N} for shape {shape:?}"
+            )));
+        }
+        let expected = (0..N)
+            .map(|i| shape[i] + before[i] + after[i])
+            .collect::<Vec<_>>();
+        if O::shape() != expected {
+            return Err(Error::ShapeMismatch {
+                expected,
+                got: O::shape(),
+            });
+        }
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&expected);
+        self.graph.write().unwrap().add_op::<O>(
+            Op::Pad {
+                v_id: self.id(),
+                before: before.to_vec(),
+                after: after.to_vec(),
+                value,
+            },
+            &strides,
+            &id,
+        );
+        Ok(GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        })
+    }
+
+    /// Split into `CHUNKS` equal consecutive pieces along `axis`, the inverse of concatenation:
+    /// `O` is `S` with `axis` divided by `CHUNKS`. Each piece is copied out with a slice.
+    ///
+    /// Returns an error if the axis is out of range or its length is not divisible by `CHUNKS`.
+    pub fn split<const CHUNKS: usize, O: Shape>(
+        self,
+        axis: isize,
+    ) -> Result<[GraphTensor<O, T, D>; CHUNKS]> {
+        let mut expected = S::shape();
+        let axis = normalize_axis(axis, expected.len())?;
+        if CHUNKS == 0 || expected[axis] % CHUNKS != 0 {
+            return Err(Error::InvalidArgument(format!(
+                "cannot split axis {axis} of length {} into {CHUNKS} equal chunks",
+                expected[axis]
+            )));
+        }
+        expected[axis] /= CHUNKS;
+        if O::shape() != expected {
+            return Err(Error::ShapeMismatch {
+                expected,
+                got: O::shape(),
+            });
+        }
+
+        let len = expected[axis];
+        let strides = contiguous_strides(&expected);
+        Ok(std::array::from_fn(|i| {
+            let id = self.graph.write().unwrap().next_id();
+            self.graph.write().unwrap().add_op::<O>(
+                Op::Slice {
+                    v_id: self.id(),
+                    offset: i * len,
+                    len,
+                    axis,
+                },
+                &strides,
+                &id,
+            );
+            GraphTensor {
+                id,
+                graph: self.graph.clone(),
+                strides: strides.clone(),
+                _ghost: PhantomData,
+            }
+        }))
+    }
+
+    /// Repeat the length-one axes of this tensor up to the shape `O`, which has the same rank,
+    /// e.g. a `R2<1, 4>` row to the rows of a `R2<3, 4>`. The result is copied out, so it can be
+    /// consumed in place like any other tensor.
+    pub fn broadcast_to<O: Shape>(self) -> Result<GraphTensor<O, T, D>> {
+        let (from, to) = (S::shape(), O::shape());
+        if from.len() != to.len() || from.iter().zip(&to).any(|(&f, &t)| f != t && f != 1) {
+            return Err(Error::InvalidArgument(format!(
+                "cannot broadcast {from:?} to {to:?}, only length-one axes can be repeated"
+            )));
+        }
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&to);
+        self.graph
+            .write()
+            .unwrap()
+            .add_op::<O>(Op::Broadcast { v_id: self.id() }, &strides, &id);
+        Ok(GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        })
+    }
+
+    /// Insert a length-one axis at position `axis` of the output shape `O`, e.g. a `R1<4>` to a
+    /// `R2<1, 4>` row for axis 0. Only the shape changes, no data is copied.
+    pub fn unsqueeze<O: Shape>(self, axis: isize) -> Result<GraphTensor<O, T, D>> {
+        let shape = S::shape();
+        let axis = normalize_axis(axis, shape.len() + 1)?;
+        let mut expected = shape.clone();
+        expected.insert(axis, 1);
+        if O::shape() != expected {
+            return Err(Error::ShapeMismatch {
+                expected,
+                got: O::shape(),
+            });
+        }
+
+        // Stepping along a length-one axis never happens, so any stride keeps the layout valid.
+        let mut strides = self.strides.clone();
+        let stride = shape.get(axis).map_or(1, |&dim| dim * self.strides[axis]);
+        strides.insert(axis, stride);
+        Ok(self.strided_view(strides))
+    }
+
+    /// Remove the length-one axis `axis`, giving the shape `O`, e.g. a `R2<1, 4>` row back to a
+    /// `R1<4>`. Only the shape changes, no data is copied.
+    pub fn squeeze<O: Shape>(self, axis: isize) -> Result<GraphTensor<O, T, D>> {
+        let mut shape = S::shape();
+        let axis = normalize_axis(axis, shape.len())?;
+        if shape[axis] != 1 {
+            return Err(Error::InvalidArgument(format!(
+                "cannot squeeze axis {axis} of {shape:?}, it has length {}",
+                shape[axis]
+            )));
+        }
+        shape.remove(axis);
+        if O::shape() != shape {
+            return Err(Error::ShapeMismatch {
+                expected: shape,
+                got: O::shape(),
+            });
+        }
+
+        let mut strides = self.strides.clone();
+        strides.remove(axis);
+        Ok(self.strided_view(strides))
+    }
+
+    /// View the same buffer as shape `O` through `strides`.
+    fn strided_view<O: Shape>(&self, strides: Vec<usize>) -> GraphTensor<O, T, D> {
+        let id = self.graph.write().unwrap().next_id();
+        self.graph.write().unwrap().add_op::<O>(
+            Op::Permute {
+                v_id: self.id.clone(),
+            },
+            &strides,
+            &id,
+        );
+        GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    /// Circularly shift the elements along `axis` by `shift` positions, so that those shifted
+    /// past the end wrap around to the start. A negative `shift` rolls the other way.
+    pub fn roll(self, shift: isize, axis: isize) -> Result<Self> {
+        let axis = normalize_axis(axis, S::shape().len())?;
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
+        self.graph.write().unwrap().add_op::<S>(
+            Op::Roll {
+                v_id: self.id(),
+                shift,
+                axis,
+            },
+            &strides,
+            &id,
+        );
+        Ok(GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        })
+    }
+
+    /// Reverse the order of the elements along `axis`, e.g. to flip a convolution kernel.
+    pub fn flip(self, axis: isize) -> Result<Self> {
+        let axis = normalize_axis(axis, S::shape().len())?;
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
+        self.graph.write().unwrap().add_op::<S>(
+            Op::Flip {
+                v_id: self.id(),
+                axis,
+            },
+            &strides,
+            &id,
+        );
+        Ok(GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        })
+    }
+
+    /// Sum along `axis`, keeping it with length one: `O` is `S` with `axis` set to 1.
+    pub fn sum_axis<O: Shape>(self, axis: isize) -> Result<GraphTensor<O, T, D>> {
+        self.reduce(normalize_axis(axis, S::shape().len())?, ReduceOpType::Sum)
+    }
+
+    /// Sum over every axis into a single element, e.g. for a scalar loss.
+    pub fn sum_all(self) -> GraphTensor<R1<1>, T, D> {
+        self.reduce_all(ReduceOpType::Sum)
+    }
+
+    /// The number of nonzero elements, e.g. the true entries of a mask, recorded in a new `u32`
+    /// graph like [`GraphTensor::argmax_axis`].
+    pub fn count_nonzero(self) -> GraphTensor<R1<1>, u32, D> {
+        let (graph, flat_id) = self.flatten();
+        let source = graph.read().unwrap().detached_subgraph(&flat_id);
+        Self::record_arg_reduce(source, 0, ArgReduceType::CountNonzero)
+    }
+
+    fn reduce_all(self, operator: ReduceOpType) -> GraphTensor<R1<1>, T, D> {
+        let (graph_handle, flat_id) = self.flatten();
+        let mut graph = graph_handle.write().unwrap();
+        let id = graph.next_id();
+        let strides = contiguous_strides(&[1]);
+        graph.add_op::<R1<1>>(
+            Op::Reduce {
+                v_id: flat_id,
+                axis: 0,
+                operator,
+            },
+            &strides,
+            &id,
+        );
+        drop(graph);
+        GraphTensor {
+            id,
+            graph: graph_handle,
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    /// Add a flat view of all the elements, copying views that are not row-major first.
+    fn flatten(self) -> (Arc<RwLock<Graph<T>>>, GraphTensorId) {
+        let flat = if is_contiguous_strides(&S::shape(), &self.strides) {
+            self
+        } else {
+            self.contiguous()
+        };
+        let flat_id = flat.graph.write().unwrap().next_id();
+        flat.graph.write().unwrap().add_op_with_shape(
+            Op::Permute { v_id: flat.id() },
+            vec![S::element_count()],
+            &[1],
+            &flat_id,
+        );
+        (flat.graph, flat_id)
+    }
+
+    /// Maximum along `axis`, keeping it with length one: `O` is `S` with `axis` set to 1.
+    pub fn max_axis<O: Shape>(self, axis: isize) -> Result<GraphTensor<O, T, D>> {
+        self.reduce(normalize_axis(axis, S::shape().len())?, ReduceOpType::Max)
+    }
+
+    /// The index of the maximum along `axis`, keeping it with length one: `O` is `S` with `axis`
+    /// set to 1. Ties go to the lowest index.
+    ///
+    /// The indices are recorded in a new `u32` graph, which runs the nodes this tensor depends on
+    /// as part of its own runs. Only the CPU backend supports it.
+    pub fn argmax_axis<O: Shape>(self, axis: isize) -> Result<GraphTensor<O, u32, D>> {
+        self.arg_reduce(
+            normalize_axis(axis, S::shape().len())?,
+            ArgReduceType::ArgMax,
+        )
+    }
+
+    /// The index of the minimum along `axis`, like [`GraphTensor::argmax_axis`].
+    pub fn argmin_axis<O: Shape>(self, axis: isize) -> Result<GraphTensor<O, u32, D>> {
+        self.arg_reduce(
+            normalize_axis(axis, S::shape().len())?,
+            ArgReduceType::ArgMin,
+        )
+    }
+
+    /// Record an [`Op::ArgReduce`] of this tensor along `axis` in a new `u32` graph.
+    fn arg_reduce<O: Shape>(
+        self,
+        axis: usize,
+        kind: ArgReduceType,
+    ) -> Result<GraphTensor<O, u32, D>> {
+        let mut expected = S::shape();
+        expected[axis] = 1;
+        if O::shape() != expected {
+            return Err(Error::ShapeMismatch {
+                expected,
+                got: O::shape(),
+            });
+        }
+
+        let source = self.graph().detached_subgraph(&self.id);
+        Ok(Self::record_arg_reduce(source, axis, kind))
+    }
+
+    /// Record an [`Op::ArgReduce`] of the last node of `source` in a new `u32` graph.
+    fn record_arg_reduce<O: Shape>(
+        source: Graph<T>,
+        axis: usize,
+        kind: ArgReduceType,
+    ) -> GraphTensor<O, u32, D> {
+        let v = SourceGraph::new(source);
+        let mut graph = Graph::empty();
+        let id = graph.next_id();
+        let strides = contiguous_strides(&O::shape());
+        graph.add_op::<O>(Op::ArgReduce { v, axis, kind }, &strides, &id);
+        GraphTensor {
+            id,
+            graph: Arc::new(RwLock::new(graph)),
+            strides,
+            _ghost: PhantomData,
+        }
+    }
+
+    /// Softmax along `axis`, `exp(x - max) / sum(exp(x - max))`: `R` is `S` with `axis` set to 1.
+    ///
+    /// The maximum is subtracted before `exp` so that large inputs do not overflow.
+    pub fn softmax<R: Shape>(self, axis: isize) -> Result<Self> {
+        const { assert!(!T::INTEGRAL, "softmax is only supported for float dtypes") };
+
+        let axis = normalize_axis(axis, S::shape().len())?;
+        let max = self.clone().reduce::<R>(axis, ReduceOpType::Max)?;
+        let exp = (self - Self::expand_axis(max, axis)).exp();
+        let sum = exp.clone().reduce::<R>(axis, ReduceOpType::Sum)?;
+        Ok(exp / Self::expand_axis(sum, axis))
+    }
+
+    /// Repeat `reduced`, which has length one along `axis`, up to this shape by gathering index 0.
+    fn expand_axis<R: Shape>(reduced: GraphTensor<R, T, D>, axis: usize) -> Self {
+        let zeros = Self::zeros(&mut reduced.graph.write().unwrap());
+        reduced
+            .gather(axis as isize, zeros, IndexMode::Clamp)
+            .expect("a reduced tensor always expands back along its axis")
+    }
+
+    fn reduce<O: Shape>(self, axis: usize, operator: ReduceOpType) -> Result<GraphTensor<O, T, D>> {
+        let mut expected = S::shape();
+        expected[axis] = 1;
+        if O::shape() != expected {
+            return Err(Error::ShapeMismatch {
+                expected,
+                got: O::shape(),
+            });
+        }
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&O::shape());
+        self.graph.write().unwrap().add_op::<O>(
+            Op::Reduce {
+                v_id: self.id(),
+                axis,
+                operator,
+            },
+            &strides,
+            &id,
+        );
+        Ok(GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        })
+    }
+
+    /// Gather values along `axis` at the positions in `idx`, e.g. `out[i][j] = self[i][idx[i][j]]` for axis 1.
+    ///
+    /// `idx` stores the indices as values of `T` and has the output shape `O`, which must have the rank of `S`
+    /// and be no larger than it outside of `axis`. Out-of-range indices are handled according to `mode`.
+    pub fn gather<O: Shape>(
+        self,
+        axis: isize,
+        idx: GraphTensor<O, T, D>,
+        mode: IndexMode,
+    ) -> Result<GraphTensor<O, T, D>> {
+        let axis = check_index_shape::<S, O>("Gather", axis)?;
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&O::shape());
+        self.graph.write().unwrap().add_op::<O>(
+            Op::Gather {
+                v_id: self.id(),
+                idx_id: idx.id(),
+                axis,
+                mode,
+            },
+            &strides,
+            &id,
+        );
+        Ok(GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        })
+    }
+
+    /// Copy this tensor and write `src` into it along `axis` at the positions in `idx`, e.g.
+    /// `out[i][idx[i][j]] = src[i][j]` for axis 1.
+    ///
+    /// `idx` and `src` follow the same shape rules as in [`GraphTensor::gather`]. Out-of-range indices are
+    /// handled according to `mode`.
+    pub fn scatter<O: Shape>(
+        self,
+        axis: isize,
+        idx: GraphTensor<O, T, D>,
+        src: GraphTensor<O, T, D>,
+        mode: IndexMode,
+    ) -> Result<Self> {
+        let axis = check_index_shape::<S, O>("Scatter", axis)?;
+
+        let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
+        self.graph.write().unwrap().add_op::<S>(
+            Op::Scatter {
+                v_id: self.id(),
+                idx_id: idx.id(),
+                src_id: src.id(),
+                axis,
+                mode,
+            },
+            &strides,
+            &id,
+        );
+        Ok(GraphTensor {
+            id,
+            graph: self.graph.clone(),
+            strides,
+            _ghost: PhantomData,
+        })
+    }
+}
+
+/// Check that an index tensor of shape `O` can address a tensor of shape `S` along `axis`, and
+/// resolve the axis.
+fn check_index_shape<S: Shape, O: Shape>(op: &str, axis: isize) -> Result<usize> {
+    let (shape, idx_shape) = (S::shape(), O::shape());
+    let axis = normalize_axis(axis, shape.len())?;
+    let fits = idx_shape.len() == shape.len()
+        && (0..shape.len()).all(|d| d == axis || idx_shape[d] <= shape[d]);
+    if !fits {
+        crate::bail!(
+            "{op} index shape {idx_shape:?} does not fit shape {shape:?} along axis {axis}"
+        );
+    }
+    Ok(axis)
+}
+
 macro_rules! graphtensor_binop {
     ($trait:ident, $fn_name:ident) => {
         impl<S: Shape, T: DType, D: Dev> $trait for GraphTensor<S, T, D> {
@@ -336,19 +1615,20 @@ macro_rules! graphtensor_binop {
             /// Add an elementwise operation to the graph.
             fn $fn_name(self, rhs: Self) -> Self::Output {
                 let id = self.graph.write().unwrap().next_id();
+                let strides = contiguous_strides(&S::shape());
                 self.graph.write().unwrap().add_op::<S>(
                     Op::BinaryOp {
                         l_id: self.id(),
                         r_id: rhs.id(),
                         operator: BinaryOpType::$trait,
                     },
-                    &self.strides,
+                    &strides,
                     &id,
                 );
                 Self {
                     id,
                     graph: self.graph.clone(),
-                    strides: self.strides.clone(),
+                    strides,
                     _ghost: PhantomData,
                 }
             }
@@ -360,24 +1640,26 @@ graphtensor_binop!(Add, add);
 graphtensor_binop!(Div, div);
 graphtensor_binop!(Mul, mul);
 graphtensor_binop!(Sub, sub);
+graphtensor_binop!(Rem, rem);
 
 impl<S: Shape, T: DType + Neg<Output = T>, D: Dev> Neg for GraphTensor<S, T, D> {
     type Output = GraphTensor<S, T, D>;
     /// Add an elementwise addition operation to the graph.
     fn neg(self) -> Self::Output {
         let id = self.graph.write().unwrap().next_id();
+        let strides = contiguous_strides(&S::shape());
         self.graph.write().unwrap().add_op::<S>(
             Op::UnaryOp {
                 v_id: self.id(),
                 operator: UnaryOpType::Neg,
             },
-            &self.strides,
+            &strides,
             &id,
         );
         Self {
             id,
             graph: self.graph.clone(),
-            strides: self.strides.clone(),
+            strides,
             _ghost: PhantomData,
         }
     }
@@ -1,8 +1,12 @@
 pub mod concretetensor;
+mod display;
+pub mod dyngraphtensor;
 pub mod graphtensor;
+mod npy;
 
 pub use concretetensor::Tensor;
-pub use graphtensor::GraphTensor;
+pub use dyngraphtensor::DynGraphTensor;
+pub use graphtensor::{GraphTensor, Mask};
 
 /// Compute default (contiguous) strides for a tensor of given shape.
 pub(crate) fn contiguous_strides(shape: &[usize]) -> Vec<usize> {
@@ -16,3 +20,31 @@ pub(crate) fn contiguous_strides(shape: &[usize]) -> Vec<usize> {
     strides.reverse();
     strides
 }
+
+/// Resolve a possibly negative `axis` of a tensor with `rank` axes, where `-1` is the last one
+/// like in NumPy.
+pub(crate) fn normalize_axis(axis: isize, rank: usize) -> crate::Result<usize> {
+    let resolved = if axis < 0 {
+        rank.checked_sub(axis.unsigned_abs())
+    } else {
+        Some(axis as usize).filter(|&axis| axis < rank)
+    };
+    resolved.ok_or_else(|| {
+        crate::Error::InvalidArgument(format!(
+            "axis {axis} is out of range for a tensor with {rank} axes"
+        ))
+    })
+}
+
+/// Whether `strides` lay out `shape` in row-major order without gaps. Strides of size-1 dimensions
+/// are ignored, since they never advance the offset.
+pub(crate) fn is_contiguous_strides(shape: &[usize], strides: &[usize]) -> bool {
+    let mut expected = 1;
+    for (dim, stride) in shape.iter().zip(strides).rev() {
+        if *dim != 1 && *stride != expected {
+            return false;
+        }
+        expected *= dim;
+    }
+    true
+}
@@ -0,0 +1,90 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{device::Dev, Context, DType, Result, Shape, Tensor};
+
+/// The NumPy `descr` string for `T`, e.g. `<f4` for a little-endian `f32`.
+fn npy_descr<T: DType>() -> Result<String> {
+    let kind = match T::NAME {
+        "u8" => return Ok("|u1".to_string()),
+        "u32" => "u4",
+        "i32" => "i4",
+        "i64" => "i8",
+        "f16" => "f2",
+        "f32" => "f4",
+        "f64" => "f8",
+        name => crate::bail!("{name} has no NumPy equivalent and cannot be saved as npy"),
+    };
+    let endian = if cfg!(target_endian = "little") {
+        '<'
+    } else {
+        '>'
+    };
+    Ok(format!("{endian}{kind}"))
+}
+
+impl<S: Shape, T: DType, D: Dev> Tensor<S, T, D> {
+    /// Write this tensor to `path` in the NumPy `.npy` format.
+    pub fn save_npy<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_npy(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Write several tensors to `path` as a NumPy `.npz` archive, with one `<name>.npy` member
+    /// per entry.
+    pub fn save_npz<P: AsRef<Path>>(entries: &[(&str, &Self)], path: P) -> Result<()> {
+        let mut zip = ZipWriter::new(BufWriter::new(File::create(path)?));
+        // `np.savez` stores its members uncompressed as well.
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        for (name, tensor) in entries {
+            zip.start_file(format!("{name}.npy"), options)
+                .with_context(|| format!("failed to add {name}.npy to the npz archive"))?;
+            tensor.write_npy(&mut zip)?;
+        }
+        zip.finish()
+            .context("failed to finish the npz archive")?
+            .flush()?;
+        Ok(())
+    }
+
+    /// Serialize this tensor as npy version 1.0: magic, header dictionary, then row-major data.
+    fn write_npy<W: Write>(&self, w: &mut W) -> Result<()> {
+        let shape = match S::shape().as_slice() {
+            [dim] => format!("({dim},)"),
+            dims => format!(
+                "({})",
+                dims.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {shape}, }}",
+            npy_descr::<T>()?
+        );
+        // The magic, version and header length take 10 bytes; pad so the data is 64-byte aligned.
+        let total = (10 + header.len() + 1).next_multiple_of(64);
+        header.extend(std::iter::repeat_n(' ', total - 10 - header.len() - 1));
+        header.push('\n');
+
+        w.write_all(b"\x93NUMPY\x01\x00")?;
+        w.write_all(&(header.len() as u16).to_le_bytes())?;
+        w.write_all(header.as_bytes())?;
+
+        let data = self.to_flat_vec()?;
+        // SAFETY: every `DType` is a plain scalar without padding bytes.
+        let bytes = unsafe {
+            std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(&*data))
+        };
+        w.write_all(bytes)?;
+        Ok(())
+    }
+}
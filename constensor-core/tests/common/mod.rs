@@ -0,0 +1,26 @@
+//! Fixtures shared by the integration tests. Each test crate uses a subset of them.
+#![allow(dead_code)]
+
+use constensor_core::{Dev, Graph, GraphTensor, R1, R2};
+
+/// `[[0, 1, 2], [3, 4, 5]]`
+pub fn matrix_2x3<D: Dev>(graph: &mut Graph<f32>) -> GraphTensor<R2<2, 3>, f32, D> {
+    matrix_2x3_from(graph, 0.0)
+}
+
+/// `[[start, start + 1, start + 2], [start + 3, start + 4, start + 5]]`
+pub fn matrix_2x3_from<D: Dev>(
+    graph: &mut Graph<f32>,
+    start: f32,
+) -> GraphTensor<R2<2, 3>, f32, D> {
+    let rows = [start, start + 3.0]
+        .map(|start| GraphTensor::<R1<3>, f32, D>::arange(graph, start, start + 3.0));
+    GraphTensor::stack(rows).unwrap()
+}
+
+/// `[[0, 1, 2], [5, 3, 1]]`, whose rows run in opposite directions.
+pub fn matrix_2x3_mixed<D: Dev>(graph: &mut Graph<f32>) -> GraphTensor<R2<2, 3>, f32, D> {
+    let r0 = GraphTensor::<R1<3>, f32, D>::arange(graph, 0.0, 3.0);
+    let r1 = GraphTensor::<R1<3>, f32, D>::arange_step(graph, 5.0, -2.0);
+    GraphTensor::stack([r0, r1]).unwrap()
+}
@@ -0,0 +1,26 @@
+#![cfg(feature = "cuda")]
+
+use constensor_core::{CompiledGraph, Cuda, Graph, GraphTensor, R3};
+
+fn compile(scale: f32) -> CompiledGraph<R3<1, 64, 64>, f32, Cuda<0>> {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R3<1, 64, 64>, f32, Cuda<0>>::ones(&mut graph);
+    let y = GraphTensor::<R3<1, 64, 64>, f32, Cuda<0>>::fill(&mut graph, scale);
+    let _z = x.matmul(y) + GraphTensor::<R3<1, 64, 64>, f32, Cuda<0>>::ones(&mut graph);
+    graph.compile().unwrap()
+}
+
+#[test]
+fn run_async_matches_run() {
+    let (a, b) = (compile(2.0), compile(3.0));
+    let pending_a = a.run_async().unwrap();
+    let pending_b = b.run_async().unwrap();
+    Cuda::<0>::synchronize().unwrap();
+    let (out_a, out_b) = (
+        pending_a.synchronize().unwrap(),
+        pending_b.synchronize().unwrap(),
+    );
+    assert_eq!(out_a.data().unwrap(), a.run().unwrap().data().unwrap());
+    assert_eq!(out_b.data().unwrap(), b.run().unwrap().data().unwrap());
+    assert_eq!(out_b.data().unwrap()[0][0][0], 64.0 * 3.0 + 1.0);
+}
@@ -0,0 +1,47 @@
+#![cfg(feature = "cuda")]
+
+use constensor_core::{CompiledGraph, Cpu, Cuda, Graph, GraphTensor, R1, R2};
+
+mod common;
+
+#[test]
+fn memory_info() {
+    let (free, total) = Cuda::<0>::memory_info().unwrap();
+    assert!(total > 0);
+    assert!(free <= total, "{free} free of {total}");
+}
+
+#[test]
+fn to_device_round_trip() {
+    let mut graph = Graph::empty();
+    let _x = common::matrix_2x3::<Cpu>(&mut graph);
+    let compiled: CompiledGraph<R2<2, 3>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+
+    let gpu = tensor.to_device::<Cuda<0>>().unwrap();
+    // Views are copied between CUDA devices with their strides.
+    let gpu_t = gpu.t().to_device::<Cuda<0>>().unwrap();
+    let back = gpu.to_device::<Cpu>().unwrap();
+    assert_eq!(back.data().unwrap(), tensor.data().unwrap());
+    assert_eq!(gpu_t.data().unwrap(), tensor.t().data().unwrap());
+}
+
+#[test]
+fn elementwise_chain_is_one_kernel() {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<4>, f32, Cuda<0>>::arange(&mut graph, 0.0, 4.0);
+    let y = GraphTensor::<R1<4>, f32, Cuda<0>>::fill(&mut graph, 2.0);
+    let _out = (x * y).exp().sqrt();
+    let compiled: CompiledGraph<R1<4>, f32, Cuda<0>> = graph.compile().unwrap();
+    let plan = compiled.kernel_plan();
+    assert_eq!(plan.len(), 1, "{plan:?}");
+    assert_eq!(plan[0].nodes, [0, 1, 2, 3, 4]);
+    assert_eq!(plan[0].output, 4);
+    assert_eq!(plan[0].shape, [4]);
+
+    // The CPU backend has no kernels.
+    let mut graph = Graph::empty();
+    let _x = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 2.0);
+    let compiled: CompiledGraph<R1<4>, f32, Cpu> = graph.compile().unwrap();
+    assert!(compiled.kernel_plan().is_empty());
+}
@@ -0,0 +1,70 @@
+use constensor_core::DType;
+#[cfg(feature = "bfloat")]
+use half::bf16;
+#[cfg(feature = "half")]
+use half::f16;
+
+fn assert_epsilon_distinguishable<T: DType + PartialEq>() {
+    assert_ne!(T::ONE + T::EPSILON, T::ONE);
+}
+
+#[test]
+fn epsilon_float() {
+    assert_eq!(f32::EPSILON, <f32 as DType>::EPSILON);
+    assert_eq!(f64::EPSILON, <f64 as DType>::EPSILON);
+    assert_epsilon_distinguishable::<f32>();
+    assert_epsilon_distinguishable::<f64>();
+}
+
+#[cfg(feature = "half")]
+#[test]
+fn epsilon_half() {
+    assert_epsilon_distinguishable::<f16>();
+}
+
+#[cfg(feature = "bfloat")]
+#[test]
+fn epsilon_bfloat() {
+    assert_epsilon_distinguishable::<bf16>();
+}
+
+#[test]
+fn epsilon_int() {
+    assert_eq!(<u8 as DType>::EPSILON, 0);
+    assert_eq!(<u32 as DType>::EPSILON, 0);
+    assert_eq!(<i32 as DType>::EPSILON, 0);
+    assert_eq!(<i64 as DType>::EPSILON, 0);
+}
+
+fn round_trip<T: DType>(x: f64) -> f64 {
+    T::from_f64(x).to_f64()
+}
+
+#[test]
+fn f64_conversions() {
+    assert_eq!(round_trip::<f64>(0.1), 0.1);
+    assert_eq!(round_trip::<f32>(0.1), 0.1f32 as f64);
+    assert_eq!(round_trip::<f32>(1e300), f64::INFINITY);
+
+    // Integers truncate toward zero and saturate.
+    assert_eq!(round_trip::<i32>(-2.9), -2.0);
+    assert_eq!(round_trip::<i64>(2.9), 2.0);
+    assert_eq!(round_trip::<u8>(300.0), 255.0);
+    assert_eq!(round_trip::<u8>(-1.0), 0.0);
+    assert_eq!(round_trip::<u32>(f64::NAN), 0.0);
+    assert_eq!(<i64 as DType>::from_f64(1e19), i64::MAX);
+}
+
+#[cfg(feature = "half")]
+#[test]
+fn f64_conversions_half() {
+    assert_eq!(round_trip::<f16>(0.1), f16::from_f32(0.1).to_f64());
+    assert_eq!(round_trip::<f16>(1e6), f64::INFINITY);
+}
+
+#[cfg(feature = "bfloat")]
+#[test]
+fn f64_conversions_bfloat() {
+    assert_eq!(round_trip::<bf16>(0.1), bf16::from_f32(0.1).to_f64());
+    assert_eq!(round_trip::<bf16>(1e300), f64::INFINITY);
+}
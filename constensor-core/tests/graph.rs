@@ -0,0 +1,1046 @@
+use constensor_core::{
+    BinaryOpType, CompiledGraph, CompiledGraphCache, Cpu, DynGraphTensor, Error, Graph,
+    GraphBuilder, GraphTensor, MemoryReport, NodeMemory, Op, RunStats, UnaryOpType, R1, R2, R3,
+};
+use std::{sync::Arc, time::Duration};
+
+#[cfg(feature = "cuda")]
+use constensor_core::Cuda;
+
+#[test]
+fn compile_multi_intermediates() {
+    let mut graph = Graph::empty();
+    // Random, so that constant folding leaves the intermediates in place.
+    let x = GraphTensor::<R1<4>, f32, Cpu>::rand(&mut graph);
+    let y = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 2.0);
+    let sum = x.clone() + y;
+    let z = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 4.0);
+    // Would reuse the buffer of `sum` in place if it were not requested.
+    let prod = sum.clone() * z;
+    let _out = prod.clone().sqrt();
+    graph.optimize();
+    let compiled = graph
+        .compile_multi::<Cpu>(&[x.id(), sum.id(), prod.id()])
+        .unwrap();
+    let outputs = compiled.run().unwrap();
+    assert_eq!(outputs.len(), 3);
+    let x = outputs.get::<R1<4>>(0).unwrap().to_flat_vec().unwrap();
+    let sum = outputs.get::<R1<4>>(1).unwrap();
+    let prod = outputs.get::<R1<4>>(2).unwrap();
+    let expected = x.iter().map(|x| x + 2.0).collect::<Vec<_>>();
+    assert_eq!(sum.data().unwrap().to_vec(), expected);
+    let expected = expected.iter().map(|s| s * 4.0).collect::<Vec<_>>();
+    assert_eq!(prod.data().unwrap().to_vec(), expected);
+}
+
+#[test]
+fn compile_multi_optimized_away() {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R2<3, 4>, f32, Cpu>::fill(&mut graph, 1.0);
+    let y = GraphTensor::<R2<3, 4>, f32, Cpu>::fill(&mut graph, 2.0);
+    let sum = x + y;
+    let _out = sum.clone().sqrt();
+    // Constant folding turns the output into a fill, so `sum` is dead code.
+    graph.optimize();
+    assert!(graph.compile_multi::<Cpu>(&[sum.id()]).is_err());
+}
+
+#[test]
+fn compile_multi_output_shape_mismatch() {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 1.0);
+    let compiled = graph.compile_multi::<Cpu>(&[x.id()]).unwrap();
+    let outputs = compiled.run().unwrap();
+    assert!(outputs.get::<R1<3>>(0).is_err());
+    assert!(outputs.get::<R1<4>>(1).is_err());
+}
+
+#[test]
+fn to_dot_annotations() {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R2<3, 4>, f32, Cpu>::fill(&mut graph, 1.5);
+    let y = GraphTensor::<R2<3, 4>, f32, Cpu>::ones(&mut graph);
+    let _z = x + y;
+    let dot = graph.to_dot();
+    assert!(dot.contains("Fill(1.5)"));
+    assert!(dot.contains("[3, 4] f32"));
+    assert!(dot.contains("BinOp(+)"));
+    assert!(dot.contains("fillcolor=lightgray"));
+    assert!(dot.contains("fillcolor=lightblue"));
+}
+
+fn build_graph(scale: f32) -> Graph<f32> {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 0.0, 4.0);
+    let y = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, scale);
+    let _out = (x * y).exp();
+    graph
+}
+
+#[test]
+fn structural_hash_matches_identical_graphs() {
+    let a = build_graph(2.0);
+    let b = build_graph(2.0);
+    let c = build_graph(3.0);
+    assert!(a == b);
+    assert_eq!(a.structural_hash(), b.structural_hash());
+    assert!(a != c);
+    assert_ne!(a.structural_hash(), c.structural_hash());
+}
+
+#[test]
+fn compiled_graph_cache_reuses_artifacts() {
+    let mut cache = CompiledGraphCache::<R1<4>, f32, Cpu>::new();
+    assert!(cache.is_empty());
+    let first = cache.get_or_compile(build_graph(2.0)).unwrap();
+    let second = cache.get_or_compile(build_graph(2.0)).unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+    let third = cache.get_or_compile(build_graph(3.0)).unwrap();
+    assert!(!Arc::ptr_eq(&first, &third));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(
+        second.run().unwrap().data().unwrap().to_vec(),
+        (0..4).map(|x| (x as f32 * 2.0).exp()).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn compiled_graph_cache_reuses_nan_graphs() {
+    assert!(build_graph(f32::NAN) == build_graph(f32::NAN));
+    assert!(build_graph(0.0) != build_graph(-0.0));
+    let mut cache = CompiledGraphCache::<R1<4>, f32, Cpu>::new();
+    let first = cache.get_or_compile(build_graph(f32::NAN)).unwrap();
+    let second = cache.get_or_compile(build_graph(f32::NAN)).unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+    assert_eq!(cache.len(), 1);
+}
+
+fn build_mul_add(graph: &mut Graph<f32>) {
+    let a = GraphTensor::<R1<4>, f32, Cpu>::arange(graph, 0.0, 4.0);
+    let b = GraphTensor::<R1<4>, f32, Cpu>::arange(graph, 1.0, 5.0);
+    let c = GraphTensor::<R1<4>, f32, Cpu>::arange(graph, 2.0, 6.0);
+    let _out = a * b + c;
+}
+
+#[test]
+fn run_tracked_mul_add() {
+    let mut graph = Graph::empty();
+    build_mul_add(&mut graph);
+    let compiled: CompiledGraph<R1<4>, f32, Cpu> = graph.compile().unwrap();
+    let (tensor, stats) = compiled.run_tracked().unwrap();
+    assert_eq!(tensor.data().unwrap().to_vec(), vec![2.0, 5.0, 10.0, 17.0]);
+    // Three inputs, the product and the sum, with nothing freed before the end of the run.
+    assert_eq!(
+        stats,
+        RunStats {
+            allocated: 5,
+            reused: 0,
+            peak_live: 5,
+        }
+    );
+
+    // The output of the first run no longer counts as live, and the other buffers are reused.
+    let (_tensor, stats) = compiled.run_tracked().unwrap();
+    assert_eq!(
+        stats,
+        RunStats {
+            allocated: 1,
+            reused: 4,
+            peak_live: 5,
+        }
+    );
+}
+
+#[test]
+fn run_tracked_mul_add_optimized() {
+    let mut graph = Graph::empty();
+    // Random, as constant folding would reduce `build_mul_add` to a single constant.
+    let [a, b, c] = [(); 3].map(|_| GraphTensor::<R1<4>, f32, Cpu>::rand(&mut graph));
+    let out = a.clone() * b.clone() + c.clone();
+    graph.optimize();
+    let outputs = graph
+        .clone()
+        .compile_multi::<Cpu>(&[a.id(), b.id(), c.id(), out.id()])
+        .unwrap()
+        .run()
+        .unwrap();
+    let [a, b, c, out] =
+        [0, 1, 2, 3].map(|i| outputs.get::<R1<4>>(i).unwrap().to_flat_vec().unwrap());
+    let expected = (0..4).map(|i| a[i].mul_add(b[i], c[i])).collect::<Vec<_>>();
+    assert_eq!(out, expected);
+
+    let compiled: CompiledGraph<R1<4>, f32, Cpu> = graph.compile().unwrap();
+    let (_tensor, stats) = compiled.run_tracked().unwrap();
+    // The fused multiply-add writes into the buffer of `c`, so only the inputs are allocated.
+    assert_eq!(
+        stats,
+        RunStats {
+            allocated: 3,
+            reused: 0,
+            peak_live: 3,
+        }
+    );
+}
+
+#[test]
+fn run_into_reuses_output() {
+    let mut graph = Graph::empty();
+    build_mul_add(&mut graph);
+    let mul_add: CompiledGraph<R1<4>, f32, Cpu> = graph.compile().unwrap();
+    let mut graph = Graph::empty();
+    let _x = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 3.0);
+    let fill: CompiledGraph<R1<4>, f32, Cpu> = graph.compile().unwrap();
+
+    let mut out = fill.run().unwrap();
+    mul_add.run_into(&mut out).unwrap();
+    assert_eq!(out.data().unwrap().to_vec(), vec![2.0, 5.0, 10.0, 17.0]);
+    fill.run_into(&mut out).unwrap();
+    assert_eq!(out.data().unwrap().to_vec(), vec![3.0; 4]);
+
+    // A tensor sharing its storage cannot be overwritten.
+    let shared = out.clone();
+    assert!(matches!(
+        mul_add.run_into(&mut out),
+        Err(Error::InvalidArgument(_))
+    ));
+    assert_eq!(shared.data().unwrap().to_vec(), vec![3.0; 4]);
+}
+
+#[test]
+fn deep_clone_is_independent() {
+    let mut graph = Graph::empty();
+    build_mul_add(&mut graph);
+
+    // A derived clone shares the ops.
+    let shared = graph.clone();
+    let _x = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut shared.clone(), 1.0);
+    assert_eq!(graph.get_ops().len(), 6);
+
+    let mut copy = graph.deep_clone();
+    copy.optimize();
+    let _y = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut copy, 2.0);
+    assert_ne!(copy.get_ops().len(), 6);
+    assert_eq!(graph.get_ops().len(), 6);
+    assert_eq!(graph.get_ops()[4].id.get(), 4);
+    assert!(graph
+        .get_ops()
+        .iter()
+        .all(|node| !matches!(node.op, Op::FusedMulAdd { .. })));
+
+    let compiled: CompiledGraph<R1<4>, f32, Cpu> = copy.compile().unwrap();
+    assert_eq!(
+        compiled.run().unwrap().data().unwrap().to_vec(),
+        vec![2.0; 4]
+    );
+}
+
+#[test]
+fn check_finite_names_node() {
+    let mut graph = Graph::empty();
+    let a = GraphTensor::<R1<3>, f32, Cpu>::arange(&mut graph, 0.0, 3.0);
+    let b = GraphTensor::<R1<3>, f32, Cpu>::zeros(&mut graph);
+    let c = GraphTensor::<R1<3>, f32, Cpu>::ones(&mut graph);
+    let _out = a / b + c;
+
+    let compiled: CompiledGraph<R1<3>, f32, Cpu> = graph.clone().compile().unwrap();
+    assert!(compiled.run().unwrap().data().unwrap()[0].is_nan());
+
+    graph.check_finite(true);
+    let compiled: CompiledGraph<R1<3>, f32, Cpu> = graph.compile().unwrap();
+    assert!(matches!(compiled.run(), Err(Error::NonFinite { node: 3 })));
+}
+
+#[test]
+fn run_profiled_times_each_node() {
+    let mut graph = Graph::empty();
+    build_mul_add(&mut graph);
+    let compiled: CompiledGraph<R1<4>, f32, Cpu> = graph.compile().unwrap();
+    let (tensor, report) = compiled.run_profiled().unwrap();
+    assert_eq!(tensor.data().unwrap().to_vec(), vec![2.0, 5.0, 10.0, 17.0]);
+    assert_eq!(
+        report.nodes.iter().map(|n| n.node).collect::<Vec<_>>(),
+        (0..5).collect::<Vec<_>>()
+    );
+    assert!(report.nodes.iter().all(|n| n.elapsed >= Duration::ZERO));
+    assert_eq!(
+        report.total(),
+        report.nodes.iter().map(|n| n.elapsed).sum::<Duration>()
+    );
+}
+
+#[test]
+fn compile_output_shape_mismatch() {
+    let mut graph = Graph::empty();
+    let _x = GraphTensor::<R2<3, 4>, f32, Cpu>::ones(&mut graph);
+    let compiled = graph.compile::<R2<4, 3>, Cpu>();
+    match compiled {
+        Err(Error::ShapeMismatch { expected, got }) => {
+            assert_eq!(expected, vec![4, 3]);
+            assert_eq!(got, vec![3, 4]);
+        }
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("compiled a graph with the wrong output shape"),
+    }
+}
+
+#[test]
+fn structural_hash_after_optimize() {
+    let mut a = build_graph(2.0);
+    let mut b = build_graph(2.0);
+    let before = a.structural_hash();
+    a.optimize();
+    b.optimize();
+    assert_eq!(a.structural_hash(), b.structural_hash());
+    // Optimizing rewrites the op list, e.g. marking in-place operands, which the hash reflects.
+    assert_ne!(a.structural_hash(), before);
+
+    // Same ops and values, different shape.
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<5>, f32, Cpu>::arange(&mut graph, 0.0, 5.0);
+    let y = GraphTensor::<R1<5>, f32, Cpu>::fill(&mut graph, 2.0);
+    let _out = (x * y).exp();
+    assert_ne!(graph.structural_hash(), build_graph(2.0).structural_hash());
+}
+
+#[test]
+fn eval_keeps_building() {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 0.0, 4.0);
+    let y = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 2.0);
+    let prod = x * y;
+    let before = graph.get_ops().clone();
+    assert_eq!(
+        prod.eval().unwrap().to_flat_vec().unwrap(),
+        vec![0.0, 2.0, 4.0, 6.0]
+    );
+    assert_eq!(*graph.get_ops(), before);
+
+    let one = GraphTensor::<R1<4>, f32, Cpu>::ones(&mut graph);
+    let sum = prod.clone() + one;
+    assert_eq!(
+        sum.eval().unwrap().to_flat_vec().unwrap(),
+        vec![1.0, 3.0, 5.0, 7.0]
+    );
+    assert_eq!(
+        prod.eval().unwrap().to_flat_vec().unwrap(),
+        vec![0.0, 2.0, 4.0, 6.0]
+    );
+}
+
+#[test]
+fn eval_to_const() {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 0.0, 4.0);
+    let y = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 2.0);
+    let prod = x * y;
+    let expected = graph
+        .clone()
+        .compile::<R1<4>, Cpu>()
+        .unwrap()
+        .run()
+        .unwrap();
+
+    let c = prod.eval_to_const().unwrap();
+    let z = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 1.0);
+    let _out = c.clone() + z;
+    assert!(matches!(graph.get_ops()[c.id().get()].op, Op::Const { .. }));
+
+    graph.optimize();
+    // The upstream arange, fill and mul are no longer reachable from the output, which then folds
+    // into a single constant.
+    assert_eq!(graph.get_ops().len(), 1);
+    let compiled: CompiledGraph<R1<4>, f32, Cpu> = graph.compile().unwrap();
+    assert_eq!(
+        compiled.run().unwrap().data().unwrap().to_vec(),
+        expected
+            .data()
+            .unwrap()
+            .iter()
+            .map(|v| v + 1.0)
+            .collect::<Vec<_>>()
+    );
+}
+
+fn build_matmul() -> Graph<f32> {
+    let mut graph = Graph::empty();
+    let rows = [0.0, 8.0, 16.0, 24.0]
+        .map(|start| GraphTensor::<R1<8>, f32, Cpu>::arange(&mut graph, start, start + 8.0));
+    let a: GraphTensor<R2<4, 8>, f32, Cpu> = GraphTensor::stack(rows).unwrap();
+    let a: GraphTensor<R3<1, 4, 8>, f32, Cpu> = GraphTensor::stack([a]).unwrap();
+    let b = GraphTensor::<R3<1, 8, 4>, f32, Cpu>::fill(&mut graph, 0.5);
+    let _c = a.matmul(b);
+    graph
+}
+
+#[test]
+fn with_threads_matches_default() {
+    let default: CompiledGraph<R3<1, 4, 4>, f32, Cpu> = build_matmul().compile().unwrap();
+    let single: CompiledGraph<R3<1, 4, 4>, f32, Cpu> =
+        build_matmul().compile().unwrap().with_threads(1).unwrap();
+    let expected = default.run().unwrap().to_flat_vec().unwrap();
+    assert_eq!(expected[0], 14.0);
+    assert_eq!(single.run().unwrap().to_flat_vec().unwrap(), expected);
+    let (tensor, _) = single.run_tracked().unwrap();
+    assert_eq!(tensor.to_flat_vec().unwrap(), expected);
+}
+
+#[test]
+fn builder_matches_explicit_graph() {
+    let builder = GraphBuilder::<f32>::new();
+    let x = builder.arange::<4, Cpu>(0.0, 4.0);
+    let y = builder.fill::<R1<4>, Cpu>(2.0);
+    let _out = (x * y).exp();
+    let built = builder.into_graph();
+
+    assert!(built == build_graph(2.0));
+    assert_eq!(built.structural_hash(), build_graph(2.0).structural_hash());
+    let built: CompiledGraph<R1<4>, f32, Cpu> = built.compile().unwrap();
+    let explicit: CompiledGraph<R1<4>, f32, Cpu> = build_graph(2.0).compile().unwrap();
+    assert_eq!(
+        built.run().unwrap().data().unwrap(),
+        explicit.run().unwrap().data().unwrap()
+    );
+}
+
+#[test]
+fn builder_mixes_with_explicit_graph() {
+    let builder = GraphBuilder::<f32>::new();
+    let x = builder.ones::<R1<4>, Cpu>();
+    let mut graph = builder.graph();
+    let y = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 2.0);
+    let _z = x + y;
+    let compiled: CompiledGraph<R1<4>, f32, Cpu> = builder.into_graph().compile().unwrap();
+    assert_eq!(
+        compiled.run().unwrap().data().unwrap().to_vec(),
+        vec![3.0; 4]
+    );
+}
+
+#[test]
+fn arange_constructors_agree() {
+    let mut graph = Graph::empty();
+    let _a = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 1.0, 3.0);
+    let builder = GraphBuilder::from_graph(graph.clone());
+    let _b = builder.arange_step::<4, Cpu>(1.0, 0.5);
+    let ops = graph.get_ops();
+    for node in ops.iter() {
+        match node.op {
+            Op::Arange { start, step, stop } => {
+                assert_eq!((start, step, stop), (1.0, 0.5, 3.0));
+            }
+            _ => panic!("expected only arange ops"),
+        }
+    }
+    assert_eq!(ops.len(), 2);
+}
+
+/// Names every `Op` variant without a wildcard, so adding or renaming one fails to build here.
+fn op_kind(op: &Op<f32>) -> &'static str {
+    match op {
+        Op::Fill { .. } => "fill",
+        Op::Arange { .. } => "arange",
+        Op::BinaryOp { .. } => "binary",
+        Op::UnaryOp { .. } => "unary",
+        Op::CustomUnary { .. } => "custom_unary",
+        Op::FusedMulAdd { .. } => "fma",
+        Op::MatMul { .. } => "matmul",
+        Op::Eye => "eye",
+        Op::Const { .. } => "const",
+        Op::Rand => "rand",
+        Op::Randn { .. } => "randn",
+        Op::Permute { .. } => "permute",
+        Op::Stack { .. } => "stack",
+        Op::Concat { .. } => "concat",
+        Op::Contiguous { .. } => "contiguous",
+        Op::Slice { .. } => "slice",
+        Op::Roll { .. } => "roll",
+        Op::Flip { .. } => "flip",
+        Op::Broadcast { .. } => "broadcast",
+        Op::Diagonal { .. } => "diagonal",
+        Op::NanToNum { .. } => "nan_to_num",
+        Op::Clamp { .. } => "clamp",
+        Op::MaskedFill { .. } => "masked_fill",
+        Op::Triangular { .. } => "triangular",
+        Op::Reduce { .. } => "reduce",
+        Op::ArgReduce { .. } => "arg_reduce",
+        Op::Pad { .. } => "pad",
+        Op::Gather { .. } => "gather",
+        Op::Scatter { .. } => "scatter",
+        Op::NoOp => "noop",
+    }
+}
+
+#[test]
+fn op_variants_match_builders() {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R2<2, 2>, f32, Cpu>::fill(&mut graph, 2.0);
+    let y = GraphTensor::<R2<2, 2>, f32, Cpu>::eye(&mut graph);
+    let z = (x * y).exp().clamp(0.0, 4.0).nan_to_num(0.0, 1.0, -1.0);
+    let _s = z.t().sum_axis::<R2<1, 2>>(0).unwrap();
+
+    let ops = graph.get_ops();
+    let kinds = ops.iter().map(|node| op_kind(&node.op)).collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![
+            "fill",
+            "eye",
+            "binary",
+            "unary",
+            "clamp",
+            "nan_to_num",
+            "permute",
+            "reduce"
+        ]
+    );
+    assert!(matches!(
+        ops[2].op,
+        Op::BinaryOp {
+            operator: BinaryOpType::Mul,
+            ..
+        }
+    ));
+    assert!(matches!(
+        ops[3].op,
+        Op::UnaryOp {
+            operator: UnaryOpType::Exp,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn operators_have_c_ops_and_closures() {
+    for op in BinaryOpType::ALL {
+        let expected = match op {
+            BinaryOpType::Add => 8.0,
+            BinaryOpType::Div => 3.0,
+            BinaryOpType::Sub => 4.0,
+            BinaryOpType::Mul => 12.0,
+            BinaryOpType::Rem => 0.0,
+            BinaryOpType::Max => 6.0,
+            BinaryOpType::Min => 2.0,
+            BinaryOpType::Ne | BinaryOpType::Gt | BinaryOpType::Ge => 1.0,
+            BinaryOpType::Eq | BinaryOpType::Lt | BinaryOpType::Le => 0.0,
+        };
+        assert!(!op.as_c_op().is_empty());
+        assert_eq!(op.apply(6.0f32, 2.0), expected, "{op:?}");
+    }
+    assert_eq!(BinaryOpType::Max.apply(f32::NAN, 1.0), 1.0);
+    assert_eq!(BinaryOpType::Min.apply(1.0, f32::NAN), 1.0);
+    for op in UnaryOpType::ALL {
+        let expected = match op {
+            UnaryOpType::Neg => -4.0f32,
+            UnaryOpType::Sqrt => 2.0,
+            UnaryOpType::Exp => 4.0f32.exp(),
+            UnaryOpType::Exp2 => 16.0,
+            UnaryOpType::Log => 4.0f32.ln(),
+            UnaryOpType::Log1p => 4.0f32.ln_1p(),
+        };
+        assert!(op.fill_in_c_op("x").contains('x'), "{op:?}");
+        assert_eq!(op.apply(4.0f32), expected, "{op:?}");
+    }
+}
+
+#[test]
+fn memory_report_linear_chain() {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<8>, f32, Cpu>::ones(&mut graph);
+    let _y = x.exp().exp().exp();
+    let report = graph.memory_report();
+    // Each node only needs its input, so two buffers are ever alive at once.
+    assert_eq!(
+        report,
+        MemoryReport {
+            buffers: 4,
+            total_bytes: 4 * 32,
+            peak_live_buffers: 2,
+            peak_bytes: 2 * 32,
+            nodes: (0..4)
+                .map(|i| NodeMemory {
+                    buffer: i,
+                    bytes: 32,
+                    live: i..(i + 2).min(4),
+                })
+                .collect(),
+        }
+    );
+}
+
+#[test]
+fn memory_report_inplace() {
+    let mut graph = Graph::empty();
+    let a = GraphTensor::<R1<8>, f32, Cpu>::rand(&mut graph);
+    let b = GraphTensor::<R1<8>, f32, Cpu>::rand(&mut graph);
+    let _c = (a + b).exp();
+    graph.optimize();
+    let report = graph.memory_report();
+    // The sum is written into the buffer of `a`.
+    assert_eq!(report.buffers, 3);
+    assert_eq!(report.nodes[2].buffer, report.nodes[0].buffer);
+    assert_eq!(report.peak_live_buffers, 2);
+}
+
+#[test]
+fn dedup_fills() {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R2<2, 3>, f32, Cpu>::rand(&mut graph);
+    let mut out = x.clone();
+    for _ in 0..5 {
+        out = out + GraphTensor::<R2<2, 3>, f32, Cpu>::zeros(&mut graph);
+    }
+    // A fill of another value is kept apart.
+    let out = out + GraphTensor::<R2<2, 3>, f32, Cpu>::fill(&mut graph, -0.0);
+    let unoptimized = graph.deep_clone();
+    graph.optimize();
+    let fills = |graph: &Graph<f32>| {
+        graph
+            .get_ops()
+            .iter()
+            .filter(|node| matches!(node.op, Op::Fill { .. }))
+            .count()
+    };
+    assert_eq!(fills(&unoptimized), 6);
+    assert_eq!(fills(&graph), 2);
+    let outputs = graph
+        .compile_multi::<Cpu>(&[x.id(), out.id()])
+        .unwrap()
+        .run()
+        .unwrap();
+    let x = outputs.get::<R2<2, 3>>(0).unwrap();
+    let out = outputs.get::<R2<2, 3>>(1).unwrap();
+    assert_eq!(out.data().unwrap(), x.data().unwrap());
+
+    // Distinct i64 fills that round to the same f64 are kept apart.
+    const BIG: i64 = 1 << 53;
+    let mut graph = Graph::empty();
+    // The identity is not constant folded, so the fills below stay separate nodes.
+    let a = GraphTensor::<R2<2, 2>, i64, Cpu>::eye(&mut graph)
+        + GraphTensor::<R2<2, 2>, i64, Cpu>::fill(&mut graph, BIG);
+    let b = GraphTensor::<R2<2, 2>, i64, Cpu>::eye(&mut graph)
+        + GraphTensor::<R2<2, 2>, i64, Cpu>::fill(&mut graph, BIG + 1);
+    let _out = b - a;
+    graph.optimize();
+    let compiled: CompiledGraph<R2<2, 2>, i64, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![1, 1]; 2]);
+}
+
+#[test]
+fn constant_folding() {
+    let mut graph = Graph::empty();
+    let two = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 2.0);
+    let three = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 3.0);
+    let one = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 1.0);
+    let _out = two * three + one;
+    graph.optimize();
+    assert_eq!(graph.get_ops().len(), 1);
+    assert!(matches!(graph.get_ops()[0].op, Op::Fill { v: 7.0 }));
+
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 0.0, 4.0);
+    let two = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 2.0);
+    let _out = (x * two).exp2().sqrt();
+    let expected = graph
+        .clone()
+        .compile::<R1<4>, Cpu>()
+        .unwrap()
+        .run()
+        .unwrap();
+    graph.optimize();
+    assert_eq!(graph.get_ops().len(), 1);
+    assert!(matches!(graph.get_ops()[0].op, Op::Const { .. }));
+    let compiled: CompiledGraph<R1<4>, f32, Cpu> = graph.compile().unwrap();
+    assert_eq!(
+        compiled.run().unwrap().to_flat_vec().unwrap(),
+        expected.to_flat_vec().unwrap()
+    );
+}
+
+#[test]
+fn inplace_only_for_last_use() {
+    let mut graph = Graph::empty();
+    // Random, so that constant folding leaves the ops in place.
+    let a = GraphTensor::<R1<4>, f32, Cpu>::rand(&mut graph);
+    let b = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 4.0);
+    let c = a.clone() + b;
+    let d = c * a.clone();
+    graph.optimize();
+    let flags = |graph: &Graph<f32>| {
+        graph
+            .get_ops()
+            .iter()
+            .map(|node| match &node.op {
+                Op::BinaryOp { l_id, r_id, .. } => vec![l_id.is_inplace(), r_id.is_inplace()],
+                _ => vec![],
+            })
+            .collect::<Vec<_>>()
+    };
+    // `a` is read again by the mul, so only `b` may be overwritten by the add. The sum is then
+    // last used by the mul.
+    let expected = vec![vec![], vec![], vec![false, true], vec![true, false]];
+    assert_eq!(flags(&graph), expected);
+    // The pass recomputes every flag rather than accumulating them.
+    graph.optimize();
+    assert_eq!(flags(&graph), expected);
+
+    // `a` is never overwritten, so requesting it keeps every other flag.
+    let outputs = graph
+        .compile_multi::<Cpu>(&[a.id(), d.id()])
+        .unwrap()
+        .run()
+        .unwrap();
+    let a = outputs.get::<R1<4>>(0).unwrap().to_flat_vec().unwrap();
+    let d = outputs.get::<R1<4>>(1).unwrap().to_flat_vec().unwrap();
+    assert_eq!(d, a.iter().map(|a| (a + 4.0) * a).collect::<Vec<_>>());
+}
+
+#[test]
+fn dyn_tensor_add() {
+    let mut graph = Graph::empty();
+    let (rows, cols) = (3, 4);
+    let x = DynGraphTensor::<f32, Cpu>::fill(&mut graph, &[rows, cols], 2.0);
+    let y = DynGraphTensor::<f32, Cpu>::ones(&mut graph, &[rows, cols]);
+    let z = (x.clone() + y).unwrap();
+    assert_eq!(z.dims(), &[3, 4]);
+    assert!(GraphTensor::<R2<4, 3>, f32, Cpu>::try_from(z.clone()).is_err());
+    let _z = GraphTensor::<R2<3, 4>, f32, Cpu>::try_from(z).unwrap();
+    let compiled: CompiledGraph<R2<3, 4>, f32, Cpu> = graph.clone().compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![3.0; 4]; 3]);
+
+    let w = DynGraphTensor::<f32, Cpu>::ones(&mut graph, &[rows]);
+    match x + w {
+        Err(Error::ShapeMismatch { expected, got }) => {
+            assert_eq!(expected, vec![3, 4]);
+            assert_eq!(got, vec![3]);
+        }
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("added tensors of different shapes"),
+    }
+}
+
+#[test]
+fn dyn_tensor_matmul() {
+    let mut graph = Graph::empty();
+    let (m, k, n) = (2, 3, 4);
+    let l = DynGraphTensor::<f32, Cpu>::fill(&mut graph, &[1, m, k], 2.0);
+    let r = DynGraphTensor::<f32, Cpu>::ones(&mut graph, &[1, k, n]);
+    let out = DynGraphTensor::<f32, Cpu>::ones(&mut graph, &[1, m, n]);
+    let prod = l
+        .clone()
+        .matmul_axpby(r.clone(), out.clone(), 1.0, 1.0)
+        .unwrap();
+    assert_eq!(prod.dims(), &[1, 2, 4]);
+    let _prod = GraphTensor::<R3<1, 2, 4>, f32, Cpu>::try_from(prod).unwrap();
+    let compiled: CompiledGraph<R3<1, 2, 4>, f32, Cpu> = graph.clone().compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![vec![7.0; 4]; 2]]);
+
+    // The inner dimensions differ.
+    let bad = DynGraphTensor::<f32, Cpu>::ones(&mut graph, &[1, k + 1, n]);
+    match l.clone().matmul(bad) {
+        Err(Error::ShapeMismatch { expected, got }) => {
+            assert_eq!(expected, vec![1, 3, 4]);
+            assert_eq!(got, vec![1, 4, 4]);
+        }
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("multiplied misaligned matrices"),
+    }
+    // So do the batch dimensions.
+    let bad = DynGraphTensor::<f32, Cpu>::ones(&mut graph, &[2, k, n]);
+    assert!(matches!(
+        l.clone().matmul(bad),
+        Err(Error::ShapeMismatch { .. })
+    ));
+    // The accumulator does not have the product's shape.
+    let bad = DynGraphTensor::<f32, Cpu>::ones(&mut graph, &[1, n, m]);
+    assert!(matches!(
+        l.clone().matmul_axpby(r, bad, 1.0, 1.0),
+        Err(Error::ShapeMismatch { .. })
+    ));
+    let flat = DynGraphTensor::<f32, Cpu>::ones(&mut graph, &[k, n]);
+    assert!(matches!(l.matmul(flat), Err(Error::InvalidArgument(_))));
+}
+
+#[test]
+fn validation_errors_are_typed() {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<3>, f32, Cpu>::ones(&mut graph);
+    match GraphTensor::stack::<2, R2<3, 2>>([x.clone(), x.clone()]) {
+        Err(Error::ShapeMismatch { expected, got }) => {
+            assert_eq!(expected, vec![2, 3]);
+            assert_eq!(got, vec![3, 2]);
+        }
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("stacked into the wrong shape"),
+    }
+    assert!(matches!(
+        x.sum_axis::<R1<1>>(1),
+        Err(Error::InvalidArgument(msg)) if msg.contains("axis 1")
+    ));
+}
+
+#[test]
+fn empty_graph_errors() {
+    let graph = Graph::<f32>::empty();
+    assert!(matches!(
+        graph.compile::<R1<1>, Cpu>(),
+        Err(Error::EmptyGraph)
+    ));
+}
+
+#[cfg(feature = "cuda")]
+#[test]
+fn empty_graph_errors_cuda() {
+    let graph = Graph::<f32>::empty();
+    assert!(matches!(
+        graph.compile::<R1<1>, Cuda<0>>(),
+        Err(Error::EmptyGraph)
+    ));
+}
+
+#[test]
+fn backward_sum_of_squares() {
+    let mut graph = Graph::empty();
+    let a = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 0.0, 4.0);
+    let loss = (a.clone() * a.clone()).sum_axis::<R1<1>>(0).unwrap();
+    let grads = graph.backward(&loss.id()).unwrap();
+    let da = grads.get(&a).unwrap();
+    let compiled = graph.compile_multi::<Cpu>(&[da.id()]).unwrap();
+    let outputs = compiled.run().unwrap();
+    let da = outputs.get::<R1<4>>(0).unwrap();
+    assert_eq!(da.data().unwrap().to_vec(), vec![0.0, 2.0, 4.0, 6.0]);
+}
+
+#[test]
+fn backward_roll() {
+    let mut graph = Graph::empty();
+    let a = GraphTensor::<R1<4>, f32, Cpu>::ones(&mut graph);
+    let w = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 0.0, 4.0);
+    let loss = (a.clone().roll(1, 0).unwrap() * w)
+        .sum_axis::<R1<1>>(0)
+        .unwrap();
+    let grads = graph.backward(&loss.id()).unwrap();
+    let da = grads.get(&a).unwrap();
+    let compiled = graph.compile_multi::<Cpu>(&[da.id()]).unwrap();
+    let outputs = compiled.run().unwrap();
+    // Entry `i` of `a` is multiplied by `w[i + 1]`.
+    let da = outputs.get::<R1<4>>(0).unwrap();
+    assert_eq!(da.data().unwrap().to_vec(), vec![1.0, 2.0, 3.0, 0.0]);
+}
+
+#[test]
+fn backward_clamp() {
+    let mut graph = Graph::empty();
+    // [-2, -1, 0, 1, 2]
+    let a = GraphTensor::<R1<5>, f32, Cpu>::arange(&mut graph, -2.0, 3.0);
+    let loss = a.clone().clamp(-1.0, 1.0).sum_all();
+    let grads = graph.backward(&loss.id()).unwrap();
+    let da = grads.get(&a).unwrap();
+    let compiled = graph.compile_multi::<Cpu>(&[da.id()]).unwrap();
+    let outputs = compiled.run().unwrap();
+    // Zero where the value was clamped, one within the bounds, including on them.
+    let da = outputs.get::<R1<5>>(0).unwrap();
+    assert_eq!(da.data().unwrap().to_vec(), vec![0.0, 1.0, 1.0, 1.0, 0.0]);
+}
+
+#[test]
+fn backward_broadcast() {
+    let mut graph = Graph::empty();
+    let a = GraphTensor::<R2<1, 2>, f32, Cpu>::ones(&mut graph);
+    let w = GraphTensor::<R2<3, 2>, f32, Cpu>::fill(&mut graph, 2.0);
+    let loss = (a.clone().broadcast_to::<R2<3, 2>>().unwrap() * w).sum_all();
+    let grads = graph.backward(&loss.id()).unwrap();
+    let da = grads.get(&a).unwrap();
+    let compiled = graph.compile_multi::<Cpu>(&[da.id()]).unwrap();
+    let outputs = compiled.run().unwrap();
+    // Each entry of `a` is repeated into three rows.
+    let da = outputs.get::<R2<1, 2>>(0).unwrap();
+    assert_eq!(da.data().unwrap().to_vec(), vec![vec![6.0, 6.0]]);
+}
+
+#[test]
+fn backward_matmul() {
+    let mut graph = Graph::empty();
+    let l = GraphTensor::<R2<2, 3>, f32, Cpu>::rand(&mut graph);
+    let r = GraphTensor::<R2<2, 3>, f32, Cpu>::fill(&mut graph, 3.0);
+    let loss = l
+        .clone()
+        .matmul(r.t())
+        .sum_axis::<R2<2, 1>>(1)
+        .unwrap()
+        .sum_axis::<R2<1, 1>>(0)
+        .unwrap();
+    let grads = graph.backward(&loss.id()).unwrap();
+    let (dl, dr) = (grads.get(&l).unwrap(), grads.get(&r).unwrap());
+    let compiled = graph
+        .compile_multi::<Cpu>(&[l.id(), dl.id(), dr.id()])
+        .unwrap();
+    let outputs = compiled.run().unwrap();
+    let l = outputs.get::<R2<2, 3>>(0).unwrap().to_flat_vec().unwrap();
+    // d sum(l @ rᵀ) / dl = ones @ r, the column sums of rᵀ.
+    let dl = outputs.get::<R2<2, 3>>(1).unwrap();
+    assert_eq!(dl.to_flat_vec().unwrap(), vec![6.0; 6]);
+    // d / drᵀ = lᵀ @ ones, so each entry of r receives the sum of its column of l.
+    let dr = outputs.get::<R2<2, 3>>(2).unwrap().to_flat_vec().unwrap();
+    let col_sums = (0..3).map(|j| l[j] + l[3 + j]).collect::<Vec<_>>();
+    for (i, d) in dr.iter().enumerate() {
+        assert!((d - col_sums[i % 3]).abs() < 1e-6, "{dr:?} vs {col_sums:?}");
+    }
+
+    let mut graph = Graph::empty();
+    let a = GraphTensor::<R1<4>, f32, Cpu>::ones(&mut graph);
+    let out = a.exp();
+    assert!(graph.backward(&out.id()).is_err());
+}
+
+#[test]
+fn run_tiled_matches_run() {
+    const N: usize = 100_003;
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<N>, f32, Cpu>::arange(&mut graph, -1.0, 1.0);
+    let y = GraphTensor::<R1<N>, f32, Cpu>::fill(&mut graph, 0.5);
+    let z = (x.clone() * y + x.clone()).clamp(-1.0, 1.0);
+    let _out = (z.clone() * z).sqrt() - x;
+    let compiled: CompiledGraph<R1<N>, f32, Cpu> = graph.clone().compile().unwrap();
+    let full = compiled.run().unwrap().to_flat_vec().unwrap();
+    for tile in [1 << 12, N, 2 * N] {
+        let tiled = compiled.run_tiled(tile).unwrap();
+        assert_eq!(tiled.to_flat_vec().unwrap(), full);
+    }
+    assert!(compiled.run_tiled(0).is_err());
+
+    // Constant folding leaves a single constant, which is tiled by slicing.
+    graph.optimize();
+    let compiled: CompiledGraph<R1<N>, f32, Cpu> = graph.compile().unwrap();
+    let tiled = compiled.run_tiled(1000).unwrap();
+    assert_eq!(tiled.to_flat_vec().unwrap(), full);
+
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R2<4, 4>, f32, Cpu>::ones(&mut graph);
+    let _out = x.clone().matmul(x);
+    let compiled: CompiledGraph<R2<4, 4>, f32, Cpu> = graph.compile().unwrap();
+    assert!(compiled.run_tiled(4).is_err());
+}
+
+#[test]
+fn map_cpu_custom_unary() {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, -1.0, 3.0);
+    let _y = x.map_cpu("square_plus_one", |x| x * x + 1.0);
+    graph.optimize();
+    let compiled: CompiledGraph<R1<4>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert_eq!(tensor.data().unwrap().to_vec(), vec![2.0, 1.0, 2.0, 5.0]);
+}
+
+#[test]
+fn map_cpu_hashes_by_name() {
+    let build = |name| {
+        let mut graph = Graph::empty();
+        let x = GraphTensor::<R1<4>, f32, Cpu>::ones(&mut graph);
+        let _y = x.map_cpu(name, |x| x * x + 1.0);
+        graph
+    };
+    assert_eq!(
+        build("square_plus_one").structural_hash(),
+        build("square_plus_one").structural_hash()
+    );
+    assert_ne!(
+        build("square_plus_one").structural_hash(),
+        build("other").structural_hash()
+    );
+}
+
+#[cfg(feature = "cuda")]
+#[test]
+fn map_cpu_rejected_on_cuda() {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<4>, f32, Cuda<0>>::ones(&mut graph);
+    let _y = x.map_cpu("square_plus_one", |x| x * x + 1.0);
+    assert!(matches!(
+        graph.compile::<R1<4>, Cuda<0>>(),
+        Err(Error::UnsupportedOp { .. })
+    ));
+}
+
+/// Run one compiled graph from several threads at once.
+macro_rules! run_concurrently {
+    ($dev:ty) => {{
+        fn assert_send_sync<X: Send + Sync>() {}
+        assert_send_sync::<CompiledGraph<R1<1024>, f32, $dev>>();
+
+        let mut graph = Graph::empty();
+        let x = GraphTensor::<R1<1024>, f32, $dev>::arange(&mut graph, 0.0, 1024.0);
+        let two = GraphTensor::<R1<1024>, f32, $dev>::fill(&mut graph, 2.0);
+        let one = GraphTensor::<R1<1024>, f32, $dev>::ones(&mut graph);
+        let _y = (x * two + one).sqrt();
+        let compiled = Arc::new(graph.compile::<R1<1024>, $dev>().unwrap());
+        let expected = (0..1024)
+            .map(|i| (i as f32 * 2.0 + 1.0).sqrt())
+            .collect::<Vec<_>>();
+        let workers = (0..4)
+            .map(|_| {
+                let compiled = compiled.clone();
+                std::thread::spawn(move || {
+                    (0..16)
+                        .map(|_| compiled.run().unwrap().to_flat_vec().unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            for out in worker.join().unwrap() {
+                assert_eq!(out, expected);
+            }
+        }
+    }};
+}
+
+#[test]
+fn run_from_threads() {
+    run_concurrently!(Cpu);
+}
+
+#[cfg(feature = "cuda")]
+#[test]
+fn run_from_threads_cuda() {
+    run_concurrently!(Cuda<0>);
+}
+
+#[test]
+fn append_graph() {
+    fn product(graph: &mut Graph<f32>) -> GraphTensor<R1<4>, f32, Cpu> {
+        let x = GraphTensor::<R1<4>, f32, Cpu>::arange(graph, 0.0, 4.0);
+        let y = GraphTensor::<R1<4>, f32, Cpu>::fill(graph, 3.0);
+        x * y
+    }
+    fn shifted(graph: &mut Graph<f32>) -> GraphTensor<R1<4>, f32, Cpu> {
+        let x = GraphTensor::<R1<4>, f32, Cpu>::arange(graph, 1.0, 5.0);
+        x.clone() * x + GraphTensor::<R1<4>, f32, Cpu>::ones(graph)
+    }
+
+    let mut graph = Graph::empty();
+    let a = product(&mut graph);
+    let mut other = Graph::empty();
+    let b = shifted(&mut other);
+
+    let compiled: CompiledGraph<R1<4>, f32, Cpu> = graph.deep_clone().compile().unwrap();
+    let expected_a = compiled.run().unwrap().to_flat_vec().unwrap();
+    let compiled: CompiledGraph<R1<4>, f32, Cpu> = other.deep_clone().compile().unwrap();
+    let expected_b = compiled.run().unwrap().to_flat_vec().unwrap();
+
+    let offset = graph.append(&other);
+    assert_eq!(offset, 3);
+    assert_eq!(graph.get_ops().len(), 3 + other.get_ops().len());
+    let compiled = graph
+        .compile_multi::<Cpu>(&[a.id(), b.id().shifted(offset)])
+        .unwrap();
+    let outputs = compiled.run().unwrap();
+    let a = outputs.get::<R1<4>>(0).unwrap().to_flat_vec().unwrap();
+    let b = outputs.get::<R1<4>>(1).unwrap().to_flat_vec().unwrap();
+    assert_eq!(a, expected_a);
+    assert_eq!(b, expected_b);
+    assert_eq!(b, vec![2.0, 5.0, 10.0, 17.0]);
+}
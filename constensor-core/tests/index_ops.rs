@@ -0,0 +1,103 @@
+use constensor_core::{CompiledGraph, Cpu, Graph, GraphTensor, IndexMode, R1, R2};
+
+mod common;
+
+macro_rules! test_for_device_index {
+    ($dev:ty, $name:ident) => {
+        mod $name {
+            use super::*;
+
+            /// A column of indices.
+            fn column(
+                graph: &mut Graph<f32>,
+                values: [f32; 2],
+            ) -> GraphTensor<R2<2, 1>, f32, $dev> {
+                let rows = values.map(|v| GraphTensor::<R1<1>, f32, $dev>::fill(graph, v));
+                GraphTensor::stack(rows).unwrap()
+            }
+
+            #[test]
+            fn gather() {
+                let mut graph = Graph::empty();
+                let x = common::matrix_2x3::<$dev>(&mut graph);
+                let idx = column(&mut graph, [2.0, 0.0]);
+                let _g = x.gather(1, idx, IndexMode::Error).unwrap();
+                let compiled: CompiledGraph<R2<2, 1>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![2.0], vec![3.0]]);
+            }
+
+            #[test]
+            fn gather_out_of_range_errors() {
+                let mut graph = Graph::empty();
+                let x = common::matrix_2x3::<$dev>(&mut graph);
+                let idx = column(&mut graph, [5.0, 0.0]);
+                let _g = x.gather(1, idx, IndexMode::Error).unwrap();
+                let compiled: CompiledGraph<R2<2, 1>, f32, $dev> = graph.compile().unwrap();
+                assert!(compiled.run().is_err());
+            }
+
+            #[test]
+            fn gather_out_of_range_clamps() {
+                let mut graph = Graph::empty();
+                let x = common::matrix_2x3::<$dev>(&mut graph);
+                let idx = column(&mut graph, [5.0, -1.0]);
+                let _g = x.gather(1, idx, IndexMode::Clamp).unwrap();
+                let compiled: CompiledGraph<R2<2, 1>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![2.0], vec![3.0]]);
+            }
+
+            #[test]
+            fn gather_bad_index_shape() {
+                let mut graph = Graph::empty();
+                let x = common::matrix_2x3::<$dev>(&mut graph);
+                let idx = GraphTensor::<R2<3, 1>, f32, $dev>::fill(&mut graph, 0.0);
+                assert!(x.gather(1, idx, IndexMode::Error).is_err());
+            }
+
+            #[test]
+            fn scatter() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<2, 3>, f32, $dev>::zeros(&mut graph);
+                let idx = column(&mut graph, [2.0, 0.0]);
+                let src = column(&mut graph, [7.0, 8.0]);
+                let _s = x.scatter(1, idx, src, IndexMode::Error).unwrap();
+                let compiled: CompiledGraph<R2<2, 3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![vec![0.0, 0.0, 7.0], vec![8.0, 0.0, 0.0]],
+                );
+            }
+
+            #[test]
+            fn scatter_out_of_range_errors() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<2, 3>, f32, $dev>::zeros(&mut graph);
+                let idx = column(&mut graph, [3.0, 0.0]);
+                let src = column(&mut graph, [7.0, 8.0]);
+                let _s = x.scatter(1, idx, src, IndexMode::Error).unwrap();
+                let compiled: CompiledGraph<R2<2, 3>, f32, $dev> = graph.compile().unwrap();
+                assert!(compiled.run().is_err());
+            }
+
+            #[test]
+            fn scatter_out_of_range_clamps() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<2, 3>, f32, $dev>::zeros(&mut graph);
+                let idx = column(&mut graph, [3.0, 0.0]);
+                let src = column(&mut graph, [7.0, 8.0]);
+                let _s = x.scatter(1, idx, src, IndexMode::Clamp).unwrap();
+                let compiled: CompiledGraph<R2<2, 3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![vec![0.0, 0.0, 7.0], vec![8.0, 0.0, 0.0]],
+                );
+            }
+        }
+    };
+}
+
+test_for_device_index!(Cpu, cpu_tests_index);
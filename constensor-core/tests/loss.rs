@@ -0,0 +1,76 @@
+use constensor_core::{CompiledGraph, Cpu, Graph, GraphTensor, R1, R2};
+
+mod common;
+
+fn reference_cross_entropy(logits: &[[f32; 3]], targets: &[usize]) -> f32 {
+    let total: f32 = logits
+        .iter()
+        .zip(targets)
+        .map(|(row, &t)| row.iter().map(|x| x.exp()).sum::<f32>().ln() - row[t])
+        .sum();
+    total / logits.len() as f32
+}
+
+#[test]
+fn cross_entropy_matches_reference() {
+    let mut graph = Graph::empty();
+    let logits = common::matrix_2x3_mixed::<Cpu>(&mut graph);
+    // [2, 0]
+    let targets = GraphTensor::<R1<2>, f32, Cpu>::arange_step(&mut graph, 2.0, -2.0);
+    let _loss = logits.cross_entropy(targets);
+    let compiled: CompiledGraph<R1<1>, f32, Cpu> = graph.compile().unwrap();
+    let loss = compiled.run().unwrap().data().unwrap()[0];
+
+    let expected = reference_cross_entropy(&[[0.0, 1.0, 2.0], [5.0, 3.0, 1.0]], &[2, 0]);
+    assert!((loss - expected).abs() < 1e-6, "{loss} != {expected}");
+}
+
+#[test]
+fn log_softmax_rows_normalize() {
+    let mut graph = Graph::empty();
+    // Large logits would overflow `exp` without subtracting the row maximum.
+    let r0 = GraphTensor::<R1<3>, f32, Cpu>::arange(&mut graph, 100.0, 103.0);
+    let r1 = GraphTensor::<R1<3>, f32, Cpu>::arange(&mut graph, -1.0, 2.0);
+    let x: GraphTensor<R2<2, 3>, f32, Cpu> = GraphTensor::stack([r0, r1]).unwrap();
+    let _y = x.log_softmax();
+    let compiled: CompiledGraph<R2<2, 3>, f32, Cpu> = graph.compile().unwrap();
+    for row in compiled.run().unwrap().data().unwrap().iter() {
+        let total: f32 = row.iter().map(|x| x.exp()).sum();
+        assert!((total - 1.0).abs() < 1e-6, "{row:?}");
+    }
+}
+
+#[test]
+fn normalize_rows() {
+    let mut graph = Graph::empty();
+    // [[3, 4], [0, 0]]
+    let r0 = GraphTensor::<R1<2>, f32, Cpu>::arange(&mut graph, 3.0, 5.0);
+    let r1 = GraphTensor::<R1<2>, f32, Cpu>::zeros(&mut graph);
+    let x: GraphTensor<R2<2, 2>, f32, Cpu> = GraphTensor::stack([r0, r1]).unwrap();
+    let _y = x.normalize();
+    let compiled: CompiledGraph<R2<2, 2>, f32, Cpu> = graph.compile().unwrap();
+    // The zero row is divided by epsilon instead of its zero norm.
+    assert_eq!(
+        compiled.run().unwrap().data().unwrap().to_vec(),
+        vec![vec![0.6, 0.8], vec![0.0, 0.0]]
+    );
+}
+
+#[test]
+fn layer_norm_rows() {
+    let mut graph = Graph::empty();
+    // [[0, 1, 2], [5, 5, 5]]
+    let r0 = GraphTensor::<R1<3>, f32, Cpu>::arange(&mut graph, 0.0, 3.0);
+    let r1 = GraphTensor::<R1<3>, f32, Cpu>::fill(&mut graph, 5.0);
+    let x: GraphTensor<R2<2, 3>, f32, Cpu> = GraphTensor::stack([r0, r1]).unwrap();
+    let _y = x.layer_norm();
+    let compiled: CompiledGraph<R2<2, 3>, f32, Cpu> = graph.compile().unwrap();
+    let rows = compiled.run().unwrap().data().unwrap().to_vec();
+    // The variance of [0, 1, 2] is 2/3.
+    let scale = (2.0f32 / 3.0 + f32::EPSILON).sqrt();
+    for (y, expected) in rows[0].iter().zip([-1.0 / scale, 0.0, 1.0 / scale]) {
+        assert!((y - expected).abs() < 1e-6, "{y} != {expected}");
+    }
+    // A constant row has zero variance and stays finite.
+    assert_eq!(rows[1], vec![0.0; 3]);
+}
@@ -0,0 +1,55 @@
+use std::{fs::File, io::Read};
+
+use constensor_core::{CompiledGraph, Cpu, Graph, Tensor, R2};
+
+mod common;
+
+fn matrix(start: f32) -> Tensor<R2<2, 3>, f32, Cpu> {
+    let mut graph = Graph::empty();
+    let _x = common::matrix_2x3_from::<Cpu>(&mut graph, start);
+    let compiled: CompiledGraph<R2<2, 3>, f32, Cpu> = graph.compile().unwrap();
+    compiled.run().unwrap()
+}
+
+#[test]
+fn save_npy_layout() {
+    let path = std::env::temp_dir().join("constensor_save_npy_layout.npy");
+    matrix(0.0).save_npy(&path).unwrap();
+    let mut bytes = Vec::new();
+    File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+    let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+    assert_eq!((10 + header_len) % 64, 0);
+    let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+    assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }"));
+    let data = bytes[10 + header_len..]
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(data, vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+}
+
+#[test]
+fn save_npz_members() {
+    let path = std::env::temp_dir().join("constensor_save_npz_members.npz");
+    let a = matrix(0.0);
+    let b = matrix(6.0);
+    Tensor::save_npz(&[("a", &a), ("b", &b)], &path).unwrap();
+    let mut archive = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+    let mut names = archive.file_names().map(str::to_string).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, ["a.npy", "b.npy"]);
+
+    let mut member = Vec::new();
+    archive
+        .by_name("b.npy")
+        .unwrap()
+        .read_to_end(&mut member)
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let header_len = u16::from_le_bytes([member[8], member[9]]) as usize;
+    let first = f32::from_le_bytes(member[10 + header_len..14 + header_len].try_into().unwrap());
+    assert_eq!(first, 6.0);
+}
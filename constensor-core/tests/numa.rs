@@ -0,0 +1,18 @@
+#![cfg(feature = "numa")]
+
+use constensor_core::{CompiledGraph, Cpu, Graph, GraphTensor, R1};
+
+#[test]
+fn pinned_threads_and_first_touch() {
+    // Large enough that the buffers are first-touched in parallel.
+    const N: usize = 1 << 20;
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<N>, f32, Cpu>::arange(&mut graph, 0.0, N as f32);
+    let two = GraphTensor::<R1<N>, f32, Cpu>::fill(&mut graph, 2.0);
+    let _y = x * two;
+    let compiled: CompiledGraph<R1<N>, f32, Cpu> = graph.compile().unwrap();
+    let compiled = compiled.with_threads(2).unwrap();
+    let tensor = compiled.run().unwrap();
+    let data = tensor.data().unwrap();
+    assert!(data.iter().enumerate().all(|(i, y)| *y == i as f32 * 2.0));
+}
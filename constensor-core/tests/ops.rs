@@ -2,7 +2,7 @@ use std::f32::consts::PI;
 
 #[cfg(feature = "cuda")]
 use constensor_core::Cuda;
-use constensor_core::{CompiledGraph, Cpu, Graph, GraphTensor, R1, R2, R3};
+use constensor_core::{CompiledGraph, Cpu, Graph, GraphTensor, Mask, Op, R1, R2, R3};
 #[cfg(feature = "bfloat")]
 use half::bf16;
 #[cfg(feature = "half")]
@@ -28,6 +28,19 @@ macro_rules! test_for_device_float {
                 );
             }
 
+            #[test]
+            fn arange_spans_blocks() {
+                // Longer than one block of threads, and fused with another elementwise op.
+                const N: usize = 3000;
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<N>, f32, $dev>::arange(&mut graph, 0.0, N as f32);
+                let y = GraphTensor::<R1<N>, f32, $dev>::ones(&mut graph);
+                let _res = x + y;
+                let compiled: CompiledGraph<R1<N>, f32, $dev> = graph.compile().unwrap();
+                let data = compiled.run().unwrap().to_flat_vec().unwrap();
+                assert_eq!(data, (1..=N).map(|i| i as f32).collect::<Vec<_>>());
+            }
+
             #[test]
             fn add_div() {
                 let mut graph = Graph::empty();
@@ -38,14 +51,26 @@ macro_rules! test_for_device_float {
                 let _res = z / c;
                 let compiled: CompiledGraph<R2<3, 4>, f32, $dev> = graph.compile().unwrap();
                 let tensor = compiled.run().unwrap();
-                assert_eq!(
-                    tensor.data().unwrap().to_vec(),
-                    vec![
-                        [1.3333334, 1.3333334, 1.3333334, 1.3333334,],
-                        [1.3333334, 1.3333334, 1.3333334, 1.3333334,],
-                        [1.3333334, 1.3333334, 1.3333334, 1.3333334,],
-                    ],
-                );
+
+                let mut graph = Graph::empty();
+                let _expected = GraphTensor::<R2<3, 4>, f32, Cpu>::fill(&mut graph, 4.0 / 3.0);
+                let compiled: CompiledGraph<R2<3, 4>, f32, Cpu> = graph.compile().unwrap();
+                let expected = compiled.run().unwrap();
+                assert!(tensor.allclose_default(&expected).unwrap());
+            }
+
+            #[test]
+            fn eye() {
+                let mut graph = Graph::empty();
+                let _x = GraphTensor::<R2<4, 4>, f32, $dev>::eye(&mut graph);
+                let compiled: CompiledGraph<R2<4, 4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                let data = tensor.data().unwrap();
+                for (i, row) in data.iter().enumerate() {
+                    for (j, x) in row.iter().enumerate() {
+                        assert_eq!(*x, if i == j { 1.0 } else { 0.0 });
+                    }
+                }
             }
 
             #[test]
@@ -59,6 +84,43 @@ macro_rules! test_for_device_float {
                 assert_eq!(tensor.data().unwrap().to_vec(), vec![1.0, 1.25, 1.5, 1.75]);
             }
 
+            #[test]
+            fn maximum_minimum() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<2, 2>, f32, $dev>::fill(&mut graph, 1.0);
+                let y = GraphTensor::<R2<2, 2>, f32, $dev>::fill(&mut graph, 2.0);
+                let _res = x.clone().maximum(y.clone()) * x.minimum(y);
+                let compiled: CompiledGraph<R2<2, 2>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![2.0; 2]; 2]);
+            }
+
+            #[test]
+            fn maximum_mixed_sign() {
+                let mut graph = Graph::empty();
+                // Long enough to cover both the SIMD blocks and the scalar tail on the CPU.
+                let x = GraphTensor::<R1<19>, f32, $dev>::arange(&mut graph, -9.0, 10.0);
+                let y = GraphTensor::<R1<19>, f32, $dev>::zeros(&mut graph);
+                let _res = x.maximum(y);
+                let compiled: CompiledGraph<R1<19>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                let expected = (-9..10).map(|x| x.max(0) as f32).collect::<Vec<_>>();
+                assert_eq!(tensor.data().unwrap().to_vec(), expected);
+            }
+
+            #[test]
+            fn rem() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<3>, f32, $dev>::arange(&mut graph, 5.5, 8.5);
+                let y = GraphTensor::<R1<3>, f32, $dev>::fill(&mut graph, 2.0);
+                let z = GraphTensor::<R1<3>, f32, $dev>::fill(&mut graph, -1.0);
+                let _res = (z * x) % y;
+                let compiled: CompiledGraph<R1<3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                // The sign follows the dividend: -5.5 % 2 == -1.5.
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![-1.5, -0.5, -1.5]);
+            }
+
             #[test]
             fn matmul() {
                 let mut graph = Graph::empty();
@@ -71,6 +133,17 @@ macro_rules! test_for_device_float {
                 assert_eq!(tensor.data().unwrap().to_vec(), expected);
             }
 
+            #[test]
+            fn matmul_2d() {
+                let mut graph = Graph::empty();
+                let a = GraphTensor::<R2<2, 3>, f32, $dev>::fill(&mut graph, 2.0);
+                let b = GraphTensor::<R2<3, 2>, f32, $dev>::fill(&mut graph, 3.0);
+                let _c = a.matmul(b);
+                let compiled: CompiledGraph<R2<2, 2>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![[18.0, 18.0]; 2]);
+            }
+
             #[test]
             fn matmul_axpby() {
                 let mut graph = Graph::empty();
@@ -83,6 +156,92 @@ macro_rules! test_for_device_float {
                 let expected: [Vec<[f32; 2]>; 1] = [vec![[4.0, 4.0], [4.0, 4.0]]];
                 assert_eq!(tensor.data().unwrap().to_vec(), expected);
             }
+
+            #[test]
+            fn matmul_axpby_2d() {
+                let mut graph = Graph::empty();
+                let a = GraphTensor::<R2<2, 3>, f32, $dev>::ones(&mut graph);
+                let b = GraphTensor::<R2<3, 2>, f32, $dev>::ones(&mut graph);
+                let o = GraphTensor::<R2<2, 2>, f32, $dev>::ones(&mut graph);
+                let _c = a.matmul_axpby(b, o, 1., 1.);
+                let compiled: CompiledGraph<R2<2, 2>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![[4.0, 4.0]; 2]);
+            }
+
+            #[test]
+            fn matmul_axpby_into() {
+                let mut graph = Graph::empty();
+                let a = GraphTensor::<R2<2, 3>, f32, $dev>::ones(&mut graph);
+                let b = GraphTensor::<R2<3, 2>, f32, $dev>::fill(&mut graph, 2.0);
+                let o = GraphTensor::<R2<4, 4>, f32, $dev>::zeros(&mut graph);
+                let _c = a.matmul_axpby_into(b, o, 0., 1.);
+                let compiled: CompiledGraph<R2<4, 4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![
+                        vec![6.0, 6.0, 0.0, 0.0],
+                        vec![6.0, 6.0, 0.0, 0.0],
+                        vec![0.0; 4],
+                        vec![0.0; 4],
+                    ]
+                );
+            }
+
+            #[test]
+            fn independent_chains() {
+                let mut graph = Graph::empty();
+                // Two unrelated chains of the same shape, the first also read twice.
+                let x = GraphTensor::<R1<4>, f32, $dev>::arange(&mut graph, 0.0, 4.0);
+                let one = GraphTensor::<R1<4>, f32, $dev>::fill(&mut graph, 1.0);
+                let chain1 = x + one;
+                let three = GraphTensor::<R1<4>, f32, $dev>::fill(&mut graph, 3.0);
+                let two = GraphTensor::<R1<4>, f32, $dev>::fill(&mut graph, 2.0);
+                let chain2 = three * two;
+                let _out = chain1.clone() * chain2 + chain1;
+                let compiled: CompiledGraph<R1<4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![7.0, 14.0, 21.0, 28.0]);
+            }
+
+            #[test]
+            fn matmul_then_add() {
+                let mut graph = Graph::empty();
+                let a = GraphTensor::<R3<1, 2, 3>, f32, $dev>::ones(&mut graph);
+                let b = GraphTensor::<R3<1, 3, 2>, f32, $dev>::ones(&mut graph);
+                let o = GraphTensor::<R3<1, 2, 2>, f32, $dev>::ones(&mut graph);
+                let _c = a.matmul(b) + o;
+                let compiled: CompiledGraph<R3<1, 2, 2>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                let expected: [Vec<[f32; 2]>; 1] = [vec![[4.0, 4.0], [4.0, 4.0]]];
+                assert_eq!(tensor.data().unwrap().to_vec(), expected);
+            }
+
+            #[test]
+            fn dot() {
+                let mut graph = Graph::empty();
+                let a = GraphTensor::<R1<3>, f32, $dev>::arange(&mut graph, 1.0, 4.0);
+                let b = GraphTensor::<R1<3>, f32, $dev>::arange(&mut graph, 4.0, 7.0);
+                let _c = a.dot(b);
+                let compiled: CompiledGraph<R1<1>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![32.0]);
+            }
+
+            #[test]
+            fn outer() {
+                let mut graph = Graph::empty();
+                let a = GraphTensor::<R1<3>, f32, $dev>::arange(&mut graph, 1.0, 4.0);
+                let b = GraphTensor::<R1<2>, f32, $dev>::arange(&mut graph, 4.0, 6.0);
+                let _c = a.outer(b);
+                let compiled: CompiledGraph<R2<3, 2>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![vec![4.0, 5.0], vec![8.0, 10.0], vec![12.0, 15.0]]
+                );
+            }
         }
     };
 }
@@ -134,6 +293,28 @@ macro_rules! test_for_device_int {
                 assert_eq!(tensor.data().unwrap().to_vec(), vec![1, 2, 3, 4]);
             }
 
+            #[test]
+            fn minimum_mixed_sign() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<4>, i32, $dev>::arange(&mut graph, -2, 2);
+                let y = GraphTensor::<R1<4>, i32, $dev>::zeros(&mut graph);
+                let _res = x.minimum(y);
+                let compiled: CompiledGraph<R1<4>, i32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![-2, -1, 0, 0]);
+            }
+
+            #[test]
+            fn rem() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<3>, i32, $dev>::arange(&mut graph, 7, 10);
+                let y = GraphTensor::<R1<3>, i32, $dev>::fill(&mut graph, 3);
+                let _res = x % y;
+                let compiled: CompiledGraph<R1<3>, i32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![1, 2, 0]);
+            }
+
             #[cfg(not(feature = "cuda"))]
             #[test]
             fn matmul() {
@@ -160,6 +341,54 @@ macro_rules! test_for_device_int {
                 let expected: [Vec<[i32; 2]>; 1] = [vec![[4, 4], [4, 4]]];
                 assert_eq!(tensor.data().unwrap().to_vec(), expected);
             }
+
+            #[cfg(not(feature = "cuda"))]
+            #[test]
+            fn matmul_axpby_into() {
+                let mut graph = Graph::empty();
+                let a = GraphTensor::<R3<2, 2, 3>, i32, $dev>::ones(&mut graph);
+                let b = GraphTensor::<R3<2, 3, 2>, i32, $dev>::ones(&mut graph);
+                let o = GraphTensor::<R3<2, 4, 4>, i32, $dev>::ones(&mut graph);
+                let _c = a.matmul_axpby_into(b, o, 1, 1);
+                let compiled: CompiledGraph<R3<2, 4, 4>, i32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                let batch = vec![[4, 4, 1, 1], [4, 4, 1, 1], [1; 4], [1; 4]];
+                assert_eq!(tensor.data().unwrap().to_vec(), [batch.clone(), batch]);
+            }
+
+            #[cfg(not(feature = "cuda"))]
+            #[test]
+            fn dot() {
+                let mut graph = Graph::empty();
+                let a = GraphTensor::<R1<3>, i32, $dev>::arange(&mut graph, 1, 4);
+                let b = GraphTensor::<R1<3>, i32, $dev>::arange(&mut graph, 4, 7);
+                let _c = a.dot(b);
+                let compiled: CompiledGraph<R1<1>, i32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![32]);
+            }
+
+            #[test]
+            fn fma_large_i64() {
+                const BIG: i64 = 1 << 31;
+                let mut graph = Graph::empty();
+                // The identity is not constant folded, so the mul-add below fuses into an FMA.
+                let eye = GraphTensor::<R2<2, 2>, i64, $dev>::eye(&mut graph);
+                let a = eye + GraphTensor::<R2<2, 2>, i64, $dev>::fill(&mut graph, BIG);
+                let b = GraphTensor::<R2<2, 2>, i64, $dev>::fill(&mut graph, BIG + 1);
+                let c = GraphTensor::<R2<2, 2>, i64, $dev>::fill(&mut graph, 1);
+                let _out = a * b + c;
+                graph.optimize();
+                assert!(graph
+                    .get_ops()
+                    .iter()
+                    .any(|node| matches!(node.op, Op::FusedMulAdd { .. })));
+                let compiled: CompiledGraph<R2<2, 2>, i64, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                // Beyond 2^53, so an FMA in double would round away the low bits.
+                let (on, off) = ((BIG + 1) * (BIG + 1) + 1, BIG * (BIG + 1) + 1);
+                assert_eq!(tensor.to_flat_vec().unwrap(), vec![on, off, off, on]);
+            }
         }
     };
 }
@@ -186,6 +415,24 @@ macro_rules! test_for_device_half {
                 );
             }
 
+            #[test]
+            fn arange_long() {
+                // 0.001 rounded to f16 is 0.0010004, which is a whole f16 ulp off by the end.
+                const N: usize = 1000;
+                let mut graph = Graph::empty();
+                let _gt = GraphTensor::<R1<N>, f16, $dev>::arange(
+                    &mut graph,
+                    f16::from_f64_const(0.0),
+                    f16::from_f64_const(1.0),
+                );
+                let compiled: CompiledGraph<R1<N>, f16, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                let expected = (0..N)
+                    .map(|i| f16::from_f64(i as f64 / N as f64))
+                    .collect::<Vec<_>>();
+                assert_eq!(tensor.data().unwrap().to_vec(), expected);
+            }
+
             #[test]
             fn add_div() {
                 let mut graph = Graph::empty();
@@ -304,6 +551,29 @@ macro_rules! test_for_device_bfloat {
                     ]
                 );
             }
+
+            #[test]
+            fn matmul_large_k() {
+                // Each partial sum past 4 is less than half a bf16 ulp away from the next, so a
+                // bf16 accumulator would stall there.
+                const K: usize = 4096;
+                let mut graph = Graph::empty();
+                let a = GraphTensor::<R3<1, 2, K>, bf16, $dev>::fill(
+                    &mut graph,
+                    bf16::from_f64_const(0.01),
+                );
+                let b = GraphTensor::<R3<1, K, 2>, bf16, $dev>::ones(&mut graph);
+                let _c = a.matmul(b);
+                let compiled: CompiledGraph<R3<1, 2, 2>, bf16, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                let expected = bf16::from_f64_const(0.01).to_f32() * K as f32;
+                for x in tensor.to_flat_vec().unwrap() {
+                    assert!(
+                        (x.to_f32() - expected).abs() / expected < 1e-2,
+                        "{x} vs {expected}"
+                    );
+                }
+            }
         }
     };
 }
@@ -394,6 +664,16 @@ macro_rules! test_for_device_exp {
                 let tensor = compiled.run().unwrap();
                 assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![4.0; 4]; 3],);
             }
+
+            #[test]
+            fn exp2_int() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<3, 4>, i32, $dev>::fill(&mut graph, 3);
+                let _res = x.exp2();
+                let compiled: CompiledGraph<R2<3, 4>, i32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![8; 4]; 3],);
+            }
         }
     };
 }
@@ -434,11 +714,11 @@ macro_rules! test_for_device_log {
                 let _res = x.log();
                 let compiled: CompiledGraph<R2<3, 4>, f32, $dev> = graph.compile().unwrap();
                 let tensor = compiled.run().unwrap();
-                for row in tensor.data().unwrap().iter() {
-                    for &val in row.iter() {
-                        assert!((val - 1.0).abs() < 1e-6);
-                    }
-                }
+
+                let mut graph = Graph::empty();
+                let _expected = GraphTensor::<R2<3, 4>, f32, Cpu>::ones(&mut graph);
+                let compiled: CompiledGraph<R2<3, 4>, f32, Cpu> = graph.compile().unwrap();
+                assert!(tensor.allclose_default(&compiled.run().unwrap()).unwrap());
             }
         }
     };
@@ -448,6 +728,46 @@ test_for_device_log!(Cpu, cpu_tests_log);
 #[cfg(feature = "cuda")]
 test_for_device_log!(Cuda<0>, cuda_tests_log);
 
+macro_rules! test_for_device_nan_to_num {
+    ($dev:ty, $name:ident) => {
+        mod $name {
+            use super::*;
+
+            #[test]
+            fn nan_to_num_posinf() {
+                let mut graph = Graph::empty();
+                // x / (x * x - x) = [-0.5, NaN, inf, 1]
+                let x = GraphTensor::<R1<4>, f32, $dev>::arange(&mut graph, -1.0, 3.0);
+                let d = x.clone() * x.clone() - x.clone();
+                let _res = (x / d).nan_to_num(0.0, 100.0, -100.0);
+                let compiled: CompiledGraph<R1<4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![-0.5, 0.0, 100.0, 1.0]);
+            }
+
+            #[test]
+            fn nan_to_num_neginf() {
+                let mut graph = Graph::empty();
+                // (x - 1) / (x * x - x) = [-1, -inf, NaN, 0.5]
+                let x = GraphTensor::<R1<4>, f32, $dev>::arange(&mut graph, -1.0, 3.0);
+                let one = GraphTensor::<R1<4>, f32, $dev>::ones(&mut graph);
+                let d = x.clone() * x.clone() - x.clone();
+                let _res = ((x - one) / d).nan_to_num(0.0, 100.0, -100.0);
+                let compiled: CompiledGraph<R1<4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![-1.0, -100.0, 0.0, 0.5]
+                );
+            }
+        }
+    };
+}
+
+test_for_device_nan_to_num!(Cpu, cpu_tests_nan_to_num);
+#[cfg(feature = "cuda")]
+test_for_device_nan_to_num!(Cuda<0>, cuda_tests_nan_to_num);
+
 macro_rules! test_for_device_rand {
     ($dev:ty, $name:ident) => {
         mod $name {
@@ -481,3 +801,229 @@ macro_rules! test_for_device_rand {
 test_for_device_rand!(Cpu, cpu_tests_rand);
 #[cfg(feature = "cuda")]
 test_for_device_rand!(Cuda<0>, cuda_tests_rand);
+
+macro_rules! test_for_device_clamp {
+    ($dev:ty, $name:ident) => {
+        mod $name {
+            use super::*;
+
+            #[test]
+            fn clamp() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<4>, f32, $dev>::arange(&mut graph, -2.0, 2.0);
+                let _res = x.clamp(-1.0, 0.5);
+                let compiled: CompiledGraph<R1<4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![-1.0, -1.0, 0.0, 0.5]);
+            }
+
+            #[test]
+            fn clamp_min_max() {
+                let mut graph = Graph::empty();
+                // [-2, -1, 0, 1, 2, 3]
+                let x = GraphTensor::<R1<6>, f32, $dev>::arange(&mut graph, -2.0, 4.0);
+                let _relu = x.clamp_min(0.0);
+                let compiled: CompiledGraph<R1<6>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![0.0, 0.0, 0.0, 1.0, 2.0, 3.0]
+                );
+
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<6>, i32, $dev>::arange(&mut graph, -2, 4);
+                let _res = x.clamp_max(1);
+                let compiled: CompiledGraph<R1<6>, i32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![-2, -1, 0, 1, 1, 1]);
+
+                // Bounds past 2^53 that collapse to the value when compared in f64.
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<2>, i64, $dev>::fill(&mut graph, 1 << 53);
+                let _res = x.clamp_min((1 << 53) + 1);
+                let compiled: CompiledGraph<R1<2>, i64, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![(1 << 53) + 1; 2]);
+            }
+
+            #[test]
+            fn masked_fill() {
+                let mut graph = Graph::empty();
+                // [[3, 1], [1, 3]]
+                let x = GraphTensor::<R2<2, 2>, f32, $dev>::eye(&mut graph)
+                    * GraphTensor::<R2<2, 2>, f32, $dev>::fill(&mut graph, 2.0)
+                    + GraphTensor::<R2<2, 2>, f32, $dev>::fill(&mut graph, 1.0);
+                // The anti-diagonal.
+                let mask = GraphTensor::<R2<2, 2>, f32, $dev>::eye(&mut graph).lt(GraphTensor::<
+                    R2<2, 2>,
+                    f32,
+                    $dev,
+                >::ones(
+                    &mut graph
+                ));
+                let _out = x.masked_fill(mask, f32::MIN);
+                let compiled: CompiledGraph<R2<2, 2>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![vec![3.0, f32::MIN], vec![f32::MIN, 3.0]]
+                );
+            }
+
+            #[test]
+            fn comparisons() {
+                type Compare = fn(
+                    GraphTensor<R1<4>, f32, $dev>,
+                    GraphTensor<R1<4>, f32, $dev>,
+                ) -> Mask<R1<4>, f32, $dev>;
+                let cases: [(Compare, [f32; 4], f32); 6] = [
+                    (GraphTensor::eq, [0.0, 1.0, 0.0, 0.0], 0.0),
+                    (GraphTensor::ne, [1.0, 0.0, 1.0, 1.0], 1.0),
+                    (GraphTensor::lt, [1.0, 0.0, 0.0, 0.0], 0.0),
+                    (GraphTensor::le, [1.0, 1.0, 0.0, 0.0], 0.0),
+                    (GraphTensor::gt, [0.0, 0.0, 1.0, 1.0], 0.0),
+                    (GraphTensor::ge, [0.0, 1.0, 1.0, 1.0], 0.0),
+                ];
+                for (compare, expected, with_nan) in cases {
+                    let mut graph = Graph::empty();
+                    let x = GraphTensor::<R1<4>, f32, $dev>::arange(&mut graph, 0.0, 4.0);
+                    let _m = compare(x, GraphTensor::ones(&mut graph)).into_tensor();
+                    let compiled: CompiledGraph<R1<4>, f32, $dev> = graph.compile().unwrap();
+                    let tensor = compiled.run().unwrap();
+                    assert_eq!(tensor.data().unwrap().to_vec(), expected.to_vec());
+
+                    let mut graph = Graph::empty();
+                    let x = GraphTensor::<R1<4>, f32, $dev>::fill(&mut graph, f32::NAN);
+                    let _m = compare(x, GraphTensor::ones(&mut graph)).into_tensor();
+                    let compiled: CompiledGraph<R1<4>, f32, $dev> = graph.compile().unwrap();
+                    let tensor = compiled.run().unwrap();
+                    assert_eq!(tensor.data().unwrap().to_vec(), vec![with_nan; 4]);
+                }
+            }
+
+            #[test]
+            fn clamp_prob() {
+                let mut graph = Graph::empty();
+                // [0.0, 0.5, 1.0]
+                let x = GraphTensor::<R1<3>, f32, $dev>::arange(&mut graph, 0.0, 1.5);
+                let _res = x.clamp_prob_eps(1e-6);
+                let compiled: CompiledGraph<R1<3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![1e-6, 0.5, 1.0 - 1e-6]);
+
+                // The default margin is the dtype's epsilon.
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<3>, f32, $dev>::arange(&mut graph, 0.0, 1.5);
+                let _res = x.clamp_prob();
+                let compiled: CompiledGraph<R1<3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![f32::EPSILON, 0.5, 1.0 - f32::EPSILON]
+                );
+            }
+        }
+    };
+}
+
+test_for_device_clamp!(Cpu, cpu_tests_clamp);
+#[cfg(feature = "cuda")]
+test_for_device_clamp!(Cuda<0>, cuda_tests_clamp);
+
+macro_rules! test_for_device_arange {
+    ($dev:ty, $name:ident) => {
+        mod $name {
+            use super::*;
+
+            #[test]
+            fn arange_single() {
+                let mut graph = Graph::empty();
+                let _x = GraphTensor::<R1<1>, f32, $dev>::arange(&mut graph, 2.0, 3.0);
+                let compiled: CompiledGraph<R1<1>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![2.0]);
+            }
+
+            #[test]
+            fn arange_inexact_step() {
+                let mut graph = Graph::empty();
+                // Accumulating a step of 0.1 ten times stays below 1.0.
+                let _x = GraphTensor::<R1<10>, f32, $dev>::arange(&mut graph, 0.0, 1.0);
+                let compiled: CompiledGraph<R1<10>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                let data = tensor.data().unwrap().to_vec();
+                assert_eq!(data.len(), 10);
+                for (i, x) in data.into_iter().enumerate() {
+                    assert!((x - i as f32 * 0.1).abs() < 1e-6, "{i}: {x}");
+                }
+            }
+
+            #[test]
+            fn arange_step_single() {
+                let mut graph = Graph::empty();
+                let _x = GraphTensor::<R1<1>, f32, $dev>::arange_step(&mut graph, -1.0, 0.5);
+                let compiled: CompiledGraph<R1<1>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![-1.0]);
+            }
+
+            #[test]
+            fn arange_step() {
+                let mut graph = Graph::empty();
+                let _x = GraphTensor::<R1<5>, f32, $dev>::arange_step(&mut graph, 1.0, -0.5);
+                let compiled: CompiledGraph<R1<5>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![1.0, 0.5, 0.0, -0.5, -1.0]
+                );
+            }
+        }
+    };
+}
+
+test_for_device_arange!(Cpu, cpu_tests_arange);
+#[cfg(feature = "cuda")]
+test_for_device_arange!(Cuda<0>, cuda_tests_arange);
+
+macro_rules! test_for_device_triangular {
+    ($dev:ty, $name:ident) => {
+        mod $name {
+            use super::*;
+
+            #[test]
+            fn triu() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<3, 3>, f32, $dev>::ones(&mut graph);
+                let _res = x.triu(0);
+                let compiled: CompiledGraph<R2<3, 3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![
+                        vec![1.0, 1.0, 1.0],
+                        vec![0.0, 1.0, 1.0],
+                        vec![0.0, 0.0, 1.0],
+                    ],
+                );
+            }
+
+            #[test]
+            fn tril_offset_batched() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R3<2, 2, 3>, i32, $dev>::fill(&mut graph, 5);
+                let _res = x.tril(-1);
+                let compiled: CompiledGraph<R3<2, 2, 3>, i32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![vec![vec![0, 0, 0], vec![5, 0, 0]]; 2],
+                );
+            }
+        }
+    };
+}
+
+test_for_device_triangular!(Cpu, cpu_tests_triangular);
+#[cfg(feature = "cuda")]
+test_for_device_triangular!(Cuda<0>, cuda_tests_triangular);
@@ -0,0 +1,44 @@
+//! The CPU buffer pools are controlled per process, so they are tested in a binary of their own.
+
+use constensor_core::{CompiledGraph, Cpu, Graph, GraphTensor, R1};
+
+const N: usize = 1 << 20;
+
+fn compile() -> CompiledGraph<R1<N>, f32, Cpu> {
+    let mut graph = Graph::empty();
+    let a = GraphTensor::<R1<N>, f32, Cpu>::arange(&mut graph, 0.0, 1.0);
+    let b = GraphTensor::<R1<N>, f32, Cpu>::arange(&mut graph, 1.0, 2.0);
+    let c = GraphTensor::<R1<N>, f32, Cpu>::arange(&mut graph, 2.0, 3.0);
+    let _out = a * b + c;
+    graph.compile().unwrap()
+}
+
+#[test]
+fn pool_capacity_clear_and_cap() {
+    let compiled = compile();
+    assert_eq!(Cpu::pool_capacity_bytes(), 0);
+
+    compiled.run().unwrap();
+    // Everything but the returned output is cached: the three inputs and the product.
+    assert_eq!(Cpu::pool_capacity_bytes(), 4 * N * size_of::<f32>());
+
+    let (_, stats) = compiled.run_tracked().unwrap();
+    assert_eq!(stats.reused, 4);
+    assert_eq!(stats.allocated, 1);
+
+    Cpu::clear_pool();
+    assert_eq!(Cpu::pool_capacity_bytes(), 0);
+
+    // A dropped graph's pool no longer counts.
+    compiled.run().unwrap();
+    drop(compiled);
+    assert_eq!(Cpu::pool_capacity_bytes(), 0);
+
+    // Graphs compiled after setting a cap keep at most that many bytes.
+    let _ = Cpu.with_pool_max_bytes(2 * N * size_of::<f32>());
+    let capped = compile();
+    capped.run().unwrap();
+    assert!(Cpu::pool_capacity_bytes() <= 2 * N * size_of::<f32>());
+    let (_, stats) = capped.run_tracked().unwrap();
+    assert!(stats.allocated > 1, "{stats:?}");
+}
@@ -0,0 +1,43 @@
+#![cfg(feature = "cuda")]
+
+use constensor_core::{CompiledGraph, Cuda, Graph, GraphTensor, R1};
+use std::{env, fs, path::PathBuf};
+
+fn run_graph() -> Vec<f32> {
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<4>, f32, Cuda<0>>::arange(&mut graph, 0.0, 4.0);
+    let y = GraphTensor::<R1<4>, f32, Cuda<0>>::fill(&mut graph, 3.0);
+    let _z = (x * y).sqrt();
+    let compiled: CompiledGraph<R1<4>, f32, Cuda<0>> = graph.compile().unwrap();
+    compiled.run().unwrap().data().unwrap().to_vec()
+}
+
+/// Every PTX file in the cache at `root`, across all compute capabilities.
+fn cached_ptx(root: &std::path::Path) -> Vec<PathBuf> {
+    fs::read_dir(root)
+        .unwrap()
+        .flat_map(|arch| fs::read_dir(arch.unwrap().path()).unwrap())
+        .map(|file| file.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ptx"))
+        .collect()
+}
+
+#[test]
+fn corrupt_ptx_cache_recovers() {
+    // A fresh cache holds only the kernels of this graph, and leaves the user's cache alone.
+    let root = tempfile::tempdir().unwrap();
+    env::set_var("CONSTENSOR_PTX_CACHE", root.path());
+
+    let expected = run_graph();
+    let files = cached_ptx(root.path());
+    assert!(!files.is_empty());
+    for file in &files {
+        fs::write(file, "not ptx").unwrap();
+    }
+
+    assert_eq!(run_graph(), expected);
+    // The kernels of this graph were recompiled and their cache files rewritten.
+    assert!(files
+        .iter()
+        .any(|file| fs::read_to_string(file).is_ok_and(|src| src != "not ptx")));
+}
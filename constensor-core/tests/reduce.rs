@@ -0,0 +1,371 @@
+use constensor_core::{CompiledGraph, Cpu, Error, Graph, GraphTensor, ReduceMode, R1, R2};
+use std::{env, process::Command};
+
+mod common;
+
+macro_rules! test_for_device_reduce {
+    ($dev:ty, $name:ident) => {
+        mod $name {
+            use super::*;
+
+            #[test]
+            fn sum_axis() {
+                let mut graph = Graph::empty();
+                let _s = common::matrix_2x3_mixed::<$dev>(&mut graph)
+                    .sum_axis::<R2<2, 1>>(1)
+                    .unwrap();
+                let compiled: CompiledGraph<R2<2, 1>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![3.0], vec![9.0]]);
+            }
+
+            #[test]
+            fn negative_axis() {
+                // -1 is the last axis and -rank the first.
+                let mut graph = Graph::empty();
+                let _s = common::matrix_2x3_mixed::<$dev>(&mut graph)
+                    .sum_axis::<R2<2, 1>>(-1)
+                    .unwrap();
+                let compiled: CompiledGraph<R2<2, 1>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![3.0], vec![9.0]]);
+
+                let mut graph = Graph::empty();
+                let _m = common::matrix_2x3_mixed::<$dev>(&mut graph)
+                    .max_axis::<R2<1, 3>>(-2)
+                    .unwrap();
+                let compiled: CompiledGraph<R2<1, 3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![5.0, 3.0, 2.0]]);
+
+                let mut graph = Graph::empty();
+                for axis in [2, -3] {
+                    assert!(matches!(
+                        common::matrix_2x3_mixed::<$dev>(&mut graph).sum_axis::<R2<2, 1>>(axis),
+                        Err(Error::InvalidArgument(_))
+                    ));
+                    assert!(common::matrix_2x3_mixed::<$dev>(&mut graph)
+                        .softmax::<R2<2, 1>>(axis)
+                        .is_err());
+                }
+            }
+
+            #[test]
+            fn sum_all() {
+                let mut graph = Graph::empty();
+                let _s = GraphTensor::<R2<3, 4>, f32, $dev>::fill(&mut graph, 2.0).sum_all();
+                let compiled: CompiledGraph<R1<1>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![24.0]);
+            }
+
+            #[test]
+            fn count_nonzero() {
+                let mut graph = Graph::empty();
+                let c = common::matrix_2x3_mixed::<$dev>(&mut graph)
+                    .t()
+                    .count_nonzero();
+                let compiled: CompiledGraph<R1<1>, u32, $dev> =
+                    c.graph().clone().compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![5]);
+
+                let mut graph = Graph::empty();
+                let c = GraphTensor::<R2<3, 4>, f32, $dev>::zeros(&mut graph).count_nonzero();
+                assert_eq!(c.eval().unwrap().data().unwrap().to_vec(), vec![0]);
+            }
+
+            #[test]
+            fn max_axis() {
+                let mut graph = Graph::empty();
+                let _m = common::matrix_2x3_mixed::<$dev>(&mut graph)
+                    .max_axis::<R2<1, 3>>(0)
+                    .unwrap();
+                let compiled: CompiledGraph<R2<1, 3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![5.0, 3.0, 2.0]]);
+            }
+
+            #[test]
+            fn empty_axis() {
+                // Sums and counts of nothing are zero, but nothing has no maximum.
+                let mut graph = Graph::empty();
+                let _s = GraphTensor::<R2<3, 0>, f32, $dev>::ones(&mut graph)
+                    .sum_axis::<R2<3, 1>>(1)
+                    .unwrap();
+                let compiled: CompiledGraph<R2<3, 1>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![0.0]; 3]);
+
+                let mut graph = Graph::empty();
+                let c = GraphTensor::<R2<3, 0>, f32, $dev>::ones(&mut graph).count_nonzero();
+                assert_eq!(c.eval().unwrap().data().unwrap().to_vec(), vec![0]);
+
+                let mut graph = Graph::empty();
+                let _m = GraphTensor::<R2<3, 0>, f32, $dev>::ones(&mut graph)
+                    .max_axis::<R2<3, 1>>(1)
+                    .unwrap();
+                let compiled: CompiledGraph<R2<3, 1>, f32, $dev> = graph.compile().unwrap();
+                assert!(matches!(compiled.run(), Err(Error::InvalidArgument(_))));
+
+                let mut graph = Graph::empty();
+                let a = GraphTensor::<R2<3, 0>, f32, $dev>::ones(&mut graph)
+                    .argmin_axis::<R2<3, 1>>(1)
+                    .unwrap();
+                let compiled: CompiledGraph<R2<3, 1>, u32, $dev> =
+                    a.graph().clone().compile().unwrap();
+                assert!(matches!(compiled.run(), Err(Error::InvalidArgument(_))));
+            }
+
+            #[test]
+            fn argmax_argmin_axis() {
+                let mut graph = Graph::empty();
+                let a = common::matrix_2x3_mixed::<$dev>(&mut graph)
+                    .argmax_axis::<R2<2, 1>>(1)
+                    .unwrap();
+                let compiled: CompiledGraph<R2<2, 1>, u32, $dev> =
+                    a.graph().clone().compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![2], vec![0]]);
+
+                let mut graph = Graph::empty();
+                let a = common::matrix_2x3_mixed::<$dev>(&mut graph)
+                    .t()
+                    .argmin_axis::<R2<3, 1>>(1)
+                    .unwrap();
+                let tensor = a.eval().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![vec![0], vec![0], vec![1]]
+                );
+
+                // Ties go to the lowest index.
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<4>, f32, $dev>::fill(&mut graph, 1.0);
+                let a = x.argmax_axis::<R1<1>>(0).unwrap();
+                assert_eq!(a.eval().unwrap().data().unwrap().to_vec(), vec![0]);
+            }
+
+            #[test]
+            fn argmax_beyond_source_dtype() {
+                // Index 299 does not fit in the u8 source.
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<299>, u8, $dev>::zeros(&mut graph)
+                    .pad::<1, R1<300>>([0], [1], 1)
+                    .unwrap();
+                let a = x.argmax_axis::<R1<1>>(0).unwrap();
+                assert_eq!(a.eval().unwrap().data().unwrap().to_vec(), vec![299]);
+            }
+
+            #[test]
+            fn softmax() {
+                let mut graph = Graph::empty();
+                let _s = common::matrix_2x3_mixed::<$dev>(&mut graph)
+                    .softmax::<R2<2, 1>>(1)
+                    .unwrap();
+                let compiled: CompiledGraph<R2<2, 3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                for (row, input) in tensor
+                    .data()
+                    .unwrap()
+                    .iter()
+                    .zip([[0.0f32, 1.0, 2.0], [5.0, 3.0, 1.0]])
+                {
+                    let total: f32 = input.iter().map(|x| x.exp()).sum();
+                    for (y, x) in row.iter().zip(input) {
+                        let expected = x.exp() / total;
+                        assert!((y - expected).abs() < 1e-6, "{y} != {expected}");
+                    }
+                    assert!((row.iter().sum::<f32>() - 1.0).abs() < 1e-6, "{row:?}");
+                }
+            }
+
+            #[test]
+            fn softmax_axis0_large() {
+                let mut graph = Graph::empty();
+                // exp(1000) overflows f32 without subtracting the column maximum.
+                let x = GraphTensor::<R2<2, 3>, f32, $dev>::fill(&mut graph, 1000.0);
+                let _s = x.softmax::<R2<1, 3>>(0).unwrap();
+                let compiled: CompiledGraph<R2<2, 3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), vec![vec![0.5; 3]; 2]);
+            }
+
+            #[test]
+            fn reduce_shape_mismatch() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<2, 3>, f32, $dev>::ones(&mut graph);
+                assert!(x.clone().sum_axis::<R2<2, 3>>(1).is_err());
+                assert!(x.max_axis::<R2<2, 1>>(2).is_err());
+            }
+        }
+    };
+}
+
+test_for_device_reduce!(Cpu, cpu_tests_reduce);
+
+#[test]
+fn sum_all_of_view() {
+    // Views that are not row-major are copied before the reduction.
+    let mut graph = Graph::empty();
+    let x = GraphTensor::<R1<6>, f32, Cpu>::arange(&mut graph, 0.0, 6.0);
+    let _s = x.clone().outer(x).t().sum_all();
+    let compiled: CompiledGraph<R1<1>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert_eq!(tensor.data().unwrap().to_vec(), vec![225.0]);
+}
+
+#[test]
+fn deterministic_sum_is_reproducible() {
+    const N: usize = 1 << 15;
+    let run = |threads: usize| {
+        let mut graph = Graph::empty();
+        // A permutation of 0..N, scaled so the partial sums round.
+        let i = GraphTensor::<R1<N>, f32, Cpu>::arange(&mut graph, 0.0, N as f32);
+        let a = GraphTensor::<R1<N>, f32, Cpu>::fill(&mut graph, 97.0);
+        let n = GraphTensor::<R1<N>, f32, Cpu>::fill(&mut graph, N as f32);
+        let scale = GraphTensor::<R1<N>, f32, Cpu>::fill(&mut graph, 0.01);
+        let _s = (((i * a) % n) * scale).sum_axis::<R1<1>>(0).unwrap();
+        let compiled: CompiledGraph<R1<1>, f32, Cpu> = graph.compile().unwrap();
+        let compiled = compiled
+            .with_threads(threads)
+            .unwrap()
+            .with_reduce_mode(ReduceMode::Deterministic);
+        compiled.run().unwrap().data().unwrap()[0]
+    };
+    let expected = (0..N)
+        .map(|i| ((i * 97) % N) as f32 * 0.01)
+        .fold(0.0f32, |acc, x| acc + x);
+    for threads in [1, 4, 4] {
+        assert_eq!(
+            run(threads).to_bits(),
+            expected.to_bits(),
+            "{threads} threads"
+        );
+    }
+}
+
+/// A long sum in the default reduce mode, which rounds differently if split per thread.
+fn default_mode_sum_bits() -> u32 {
+    const N: usize = 1 << 17;
+    let mut graph = Graph::empty();
+    let i = GraphTensor::<R1<N>, f32, Cpu>::arange(&mut graph, 0.0, N as f32);
+    let scale = GraphTensor::<R1<N>, f32, Cpu>::fill(&mut graph, 0.01);
+    let _s = (i * scale).sum_axis::<R1<1>>(0).unwrap();
+    let compiled: CompiledGraph<R1<1>, f32, Cpu> = graph.compile().unwrap();
+    compiled.run().unwrap().data().unwrap()[0].to_bits()
+}
+
+#[test]
+#[ignore = "run by default_mode_ignores_rayon_num_threads in a child process"]
+fn print_default_mode_sum_bits() {
+    println!("bits={}", default_mode_sum_bits());
+}
+
+#[test]
+fn default_mode_ignores_rayon_num_threads() {
+    // The global pool is sized once per process, so each thread count needs its own process.
+    let run = |threads: &str| {
+        let out = Command::new(env::current_exe().unwrap())
+            .args(["--ignored", "--exact", "print_default_mode_sum_bits"])
+            .args(["--nocapture", "--test-threads=1"])
+            .env("RAYON_NUM_THREADS", threads)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{out:?}");
+        String::from_utf8(out.stdout)
+            .unwrap()
+            .lines()
+            .find_map(|line| line.split_once("bits=")?.1.parse::<u32>().ok())
+            .expect("the child prints the bits of its sum")
+    };
+    let expected = default_mode_sum_bits();
+    for threads in ["1", "2", "7"] {
+        assert_eq!(run(threads), expected, "RAYON_NUM_THREADS={threads}");
+    }
+}
+
+#[test]
+fn deterministic_graph_is_reproducible() {
+    const N: usize = 1 << 15;
+    let run = |threads: usize| {
+        let mut graph = Graph::empty();
+        let i = GraphTensor::<R1<N>, f32, Cpu>::arange(&mut graph, 0.0, N as f32);
+        let a = GraphTensor::<R1<N>, f32, Cpu>::fill(&mut graph, 97.0);
+        let n = GraphTensor::<R1<N>, f32, Cpu>::fill(&mut graph, N as f32);
+        let scale = GraphTensor::<R1<N>, f32, Cpu>::fill(&mut graph, 1e-4);
+        let x = ((i * a) % n) * scale;
+        // Reductions, a matmul and elementwise ops in one graph.
+        let weights = x.clone().softmax::<R1<1>>(0).unwrap();
+        let _out = (weights * x.clone()).sum_axis::<R1<1>>(0).unwrap() + x.clone().dot(x);
+        let compiled: CompiledGraph<R1<1>, f32, Cpu> = graph.compile().unwrap();
+        let compiled = compiled
+            .with_threads(threads)
+            .unwrap()
+            .with_reduce_mode(ReduceMode::Deterministic);
+        (0..3)
+            .map(|_| compiled.run().unwrap().as_bytes().unwrap())
+            .collect::<Vec<_>>()
+    };
+    let expected = run(1)[0].clone();
+    for threads in [1, 4] {
+        for bytes in run(threads) {
+            assert_eq!(bytes, expected, "{threads} threads");
+        }
+    }
+}
+
+#[test]
+fn compensated_sum_is_more_accurate() {
+    const N: usize = 1 << 20;
+    let run = |mode: ReduceMode| {
+        let mut graph = Graph::empty();
+        let x = GraphTensor::<R1<N>, f32, Cpu>::fill(&mut graph, 0.1);
+        let _s = x.sum_axis::<R1<1>>(0).unwrap();
+        let compiled: CompiledGraph<R1<1>, f32, Cpu> = graph.compile().unwrap();
+        let sum = compiled
+            .with_reduce_mode(mode)
+            .run()
+            .unwrap()
+            .data()
+            .unwrap()[0];
+        (sum as f64 - 0.1f32 as f64 * N as f64).abs()
+    };
+    let naive = run(ReduceMode::Deterministic);
+    let compensated = run(ReduceMode::Compensated);
+    assert!(compensated < naive, "{compensated} vs {naive}");
+    // Within an ulp of the exact sum, near 1e5.
+    assert!(compensated < 1e-2, "{compensated}");
+}
+
+#[cfg(feature = "half")]
+#[test]
+fn count_nonzero_half_beyond_2048() {
+    // f16 cannot represent odd integers above 2048, but the count is a u32.
+    let mut graph = Graph::empty();
+    let c = GraphTensor::<R1<3001>, half::f16, Cpu>::ones(&mut graph).count_nonzero();
+    assert_eq!(c.eval().unwrap().data().unwrap().to_vec(), vec![3001]);
+}
+
+#[test]
+fn compensated_dot_is_more_accurate() {
+    const N: usize = 1 << 16;
+    let run = |mode: ReduceMode| {
+        let mut graph = Graph::empty();
+        let x = GraphTensor::<R1<N>, f32, Cpu>::fill(&mut graph, 0.1);
+        let y = GraphTensor::<R1<N>, f32, Cpu>::fill(&mut graph, 3.0);
+        let _d = x.dot(y);
+        let compiled: CompiledGraph<R1<1>, f32, Cpu> = graph.compile().unwrap();
+        let dot = compiled
+            .with_reduce_mode(mode)
+            .run()
+            .unwrap()
+            .data()
+            .unwrap()[0];
+        (dot as f64 - (0.1f32 * 3.0) as f64 * N as f64).abs()
+    };
+    let naive = run(ReduceMode::Deterministic);
+    let compensated = run(ReduceMode::Compensated);
+    assert!(compensated < naive, "{compensated} vs {naive}");
+    assert!(compensated < 1e-2, "{compensated}");
+}
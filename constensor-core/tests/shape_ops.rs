@@ -0,0 +1,410 @@
+use constensor_core::{CompiledGraph, Cpu, Graph, GraphTensor, R1, R2, R3};
+
+mod common;
+
+macro_rules! test_for_device_stack {
+    ($dev:ty, $name:ident) => {
+        mod $name {
+            use super::*;
+
+            #[test]
+            fn stack_rows() {
+                let mut graph = Graph::empty();
+                let a = GraphTensor::<R1<4>, f32, $dev>::fill(&mut graph, 1.0);
+                let b = GraphTensor::<R1<4>, f32, $dev>::arange(&mut graph, 0.0, 4.0);
+                let c = GraphTensor::<R1<4>, f32, $dev>::fill(&mut graph, 3.0);
+                let _s: GraphTensor<R2<3, 4>, f32, $dev> = GraphTensor::stack([a, b, c]).unwrap();
+                graph.optimize();
+                let compiled: CompiledGraph<R2<3, 4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![
+                        vec![1.0, 1.0, 1.0, 1.0],
+                        vec![0.0, 1.0, 2.0, 3.0],
+                        vec![3.0, 3.0, 3.0, 3.0],
+                    ],
+                );
+            }
+
+            #[test]
+            fn stack_transposed() {
+                let mut graph = Graph::empty();
+                let a = common::matrix_2x3::<$dev>(&mut graph);
+                let b = GraphTensor::<R2<2, 3>, f32, $dev>::fill(&mut graph, 7.0);
+                let _s: GraphTensor<R3<2, 3, 2>, f32, $dev> =
+                    GraphTensor::stack([a.t(), b.t()]).unwrap();
+                let compiled: CompiledGraph<R3<2, 3, 2>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![
+                        vec![vec![0.0, 3.0], vec![1.0, 4.0], vec![2.0, 5.0]],
+                        vec![vec![7.0; 2]; 3],
+                    ],
+                );
+            }
+
+            #[test]
+            fn cat0() {
+                let mut graph = Graph::empty();
+                let a = common::matrix_2x3::<$dev>(&mut graph);
+                let b = GraphTensor::<R2<1, 3>, f32, $dev>::fill(&mut graph, 9.0);
+                // `a.cat0::<1, 4>(b)` would fail to compile: the output must have 2 + 1 rows.
+                let _c: GraphTensor<R2<3, 3>, f32, $dev> = a.cat0(b);
+                let compiled: CompiledGraph<R2<3, 3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![
+                        vec![0.0, 1.0, 2.0],
+                        vec![3.0, 4.0, 5.0],
+                        vec![9.0, 9.0, 9.0],
+                    ],
+                );
+            }
+
+            #[test]
+            fn cat0_transposed() {
+                let mut graph = Graph::empty();
+                let rows = [0.0, 2.0].map(|start| {
+                    GraphTensor::<R1<2>, f32, $dev>::arange(&mut graph, start, start + 2.0)
+                });
+                let a: GraphTensor<R2<2, 2>, f32, $dev> = GraphTensor::stack(rows).unwrap();
+                let b = GraphTensor::<R2<1, 2>, f32, $dev>::fill(&mut graph, 9.0);
+                let _c: GraphTensor<R2<3, 2>, f32, $dev> = a.t().cat0(b);
+                let compiled: CompiledGraph<R2<3, 2>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![vec![0.0, 2.0], vec![1.0, 3.0], vec![9.0, 9.0]],
+                );
+            }
+
+            #[test]
+            fn slice_rows() {
+                let mut graph = Graph::empty();
+                let rows = [0.0, 5.0, 10.0, 15.0].map(|start| {
+                    GraphTensor::<R1<5>, f32, $dev>::arange(&mut graph, start, start + 5.0)
+                });
+                let x: GraphTensor<R2<4, 5>, f32, $dev> = GraphTensor::stack(rows).unwrap();
+                let _s = x.slice_rows::<1, 2>().unwrap();
+                let compiled: CompiledGraph<R2<2, 5>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![
+                        vec![5.0, 6.0, 7.0, 8.0, 9.0],
+                        vec![10.0, 11.0, 12.0, 13.0, 14.0],
+                    ],
+                );
+            }
+
+            #[test]
+            fn slice_rows_transposed() {
+                let mut graph = Graph::empty();
+                let x = common::matrix_2x3::<$dev>(&mut graph);
+                let _s = x.t().slice_rows::<1, 2>().unwrap();
+                let compiled: CompiledGraph<R2<2, 2>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![vec![1.0, 4.0], vec![2.0, 5.0]],
+                );
+            }
+
+            #[test]
+            fn slice_rows_out_of_range() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<4, 5>, f32, $dev>::fill(&mut graph, 1.0);
+                assert!(x.slice_rows::<3, 2>().is_err());
+            }
+
+            #[test]
+            fn pad() {
+                let mut graph = Graph::empty();
+                let rows = [1.0, 3.0].map(|start| {
+                    GraphTensor::<R1<2>, f32, $dev>::arange(&mut graph, start, start + 2.0)
+                });
+                let x: GraphTensor<R2<2, 2>, f32, $dev> = GraphTensor::stack(rows).unwrap();
+                let _p: GraphTensor<R2<4, 4>, f32, $dev> = x.pad([1, 1], [1, 1], 0.0).unwrap();
+                let compiled: CompiledGraph<R2<4, 4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![
+                        vec![0.0, 0.0, 0.0, 0.0],
+                        vec![0.0, 1.0, 2.0, 0.0],
+                        vec![0.0, 3.0, 4.0, 0.0],
+                        vec![0.0, 0.0, 0.0, 0.0],
+                    ],
+                );
+            }
+
+            #[test]
+            fn pad_uneven_transposed() {
+                let mut graph = Graph::empty();
+                let rows = [1.0, 3.0].map(|start| {
+                    GraphTensor::<R1<2>, f32, $dev>::arange(&mut graph, start, start + 2.0)
+                });
+                let x: GraphTensor<R2<2, 2>, f32, $dev> = GraphTensor::stack(rows).unwrap();
+                let _p: GraphTensor<R2<3, 3>, f32, $dev> = x.t().pad([0, 1], [1, 0], -1.0).unwrap();
+                let compiled: CompiledGraph<R2<3, 3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![
+                        vec![-1.0, 1.0, 3.0],
+                        vec![-1.0, 2.0, 4.0],
+                        vec![-1.0, -1.0, -1.0],
+                    ],
+                );
+            }
+
+            #[test]
+            fn pad_wrong_output_shape() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<2, 2>, f32, $dev>::fill(&mut graph, 1.0);
+                assert!(x.pad::<2, R2<4, 3>>([1, 1], [1, 1], 0.0).is_err());
+            }
+
+            #[test]
+            fn flatten() {
+                let mut graph = Graph::empty();
+                let mats = [0.0, 12.0].map(|base| {
+                    let rows = [0.0, 4.0, 8.0].map(|start| {
+                        GraphTensor::<R1<4>, f32, $dev>::arange(
+                            &mut graph,
+                            base + start,
+                            base + start + 4.0,
+                        )
+                    });
+                    GraphTensor::<R1<4>, f32, $dev>::stack::<3, R2<3, 4>>(rows).unwrap()
+                });
+                let x: GraphTensor<R3<2, 3, 4>, f32, $dev> = GraphTensor::stack(mats).unwrap();
+                let _f = x.flatten_to::<24>().unwrap();
+                let compiled: CompiledGraph<R1<24>, f32, $dev> = graph.compile().unwrap();
+                let data = compiled.run().unwrap().data().unwrap().to_vec();
+                assert_eq!(data[0], 0.0);
+                assert_eq!(data[23], 23.0);
+                assert_eq!(data, (0..24).map(|x| x as f32).collect::<Vec<_>>());
+            }
+
+            #[test]
+            fn flatten_errors() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<2, 3>, f32, $dev>::fill(&mut graph, 1.0);
+                assert!(x.flatten_to::<5>().is_err());
+                assert!(x.t().flatten_to::<6>().is_err());
+            }
+
+            #[test]
+            fn split() {
+                let mut graph = Graph::empty();
+                let rows = [0.0, 6.0, 12.0, 18.0].map(|start| {
+                    GraphTensor::<R1<6>, f32, $dev>::arange(&mut graph, start, start + 6.0)
+                });
+                let x = GraphTensor::<R1<6>, f32, $dev>::stack::<4, R2<4, 6>>(rows).unwrap();
+                let [top, bottom] = x.split::<2, R2<2, 6>>(0).unwrap();
+                let _prod = top * bottom;
+                let compiled: CompiledGraph<R2<2, 6>, f32, $dev> = graph.compile().unwrap();
+                let data = compiled.run().unwrap().to_flat_vec().unwrap();
+                let expected = (0..12).map(|i| (i * (i + 12)) as f32).collect::<Vec<_>>();
+                assert_eq!(data, expected);
+            }
+
+            #[test]
+            fn split_errors() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<4, 6>, f32, $dev>::fill(&mut graph, 1.0);
+                assert!(x.clone().split::<4, R2<4, 6>>(2).is_err());
+                assert!(x.clone().split::<4, R2<4, 1>>(1).is_err());
+                assert!(x.split::<3, R2<4, 3>>(1).is_err());
+            }
+
+            #[test]
+            fn unsqueeze_squeeze() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<4>, f32, $dev>::arange(&mut graph, 0.0, 4.0);
+                let row = x.unsqueeze::<R2<1, 4>>(0).unwrap();
+                let _back = row.squeeze::<R1<4>>(0).unwrap();
+                let compiled: CompiledGraph<R1<4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), [0.0, 1.0, 2.0, 3.0]);
+
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<4>, f32, $dev>::arange(&mut graph, 0.0, 4.0);
+                let _row = x.unsqueeze::<R2<1, 4>>(0).unwrap();
+                let compiled: CompiledGraph<R2<1, 4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), [vec![0.0, 1.0, 2.0, 3.0]]);
+
+                // A column from a negative axis, then broadcast along it.
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<2>, f32, $dev>::arange(&mut graph, 0.0, 2.0);
+                let col = x.unsqueeze::<R2<2, 1>>(-1).unwrap();
+                let _b = col.broadcast_to::<R2<2, 3>>().unwrap();
+                let compiled: CompiledGraph<R2<2, 3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![vec![0.0; 3], vec![1.0; 3]]
+                );
+
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<2, 4>, f32, $dev>::fill(&mut graph, 1.0);
+                assert!(x.clone().squeeze::<R1<4>>(0).is_err());
+                assert!(x.clone().unsqueeze::<R3<2, 4, 1>>(1).is_err());
+                assert!(x.unsqueeze::<R3<2, 4, 1>>(3).is_err());
+            }
+
+            #[test]
+            fn flip() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<4>, f32, $dev>::arange(&mut graph, 1.0, 5.0);
+                let _f = x.flip(0).unwrap();
+                let compiled: CompiledGraph<R1<4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), [4.0, 3.0, 2.0, 1.0]);
+
+                for (axis, expected) in [
+                    (1, vec![vec![2.0, 1.0, 0.0], vec![5.0, 4.0, 3.0]]),
+                    (-2, vec![vec![3.0, 4.0, 5.0], vec![0.0, 1.0, 2.0]]),
+                ] {
+                    let mut graph = Graph::empty();
+                    let _f = common::matrix_2x3::<$dev>(&mut graph).flip(axis).unwrap();
+                    let compiled: CompiledGraph<R2<2, 3>, f32, $dev> = graph.compile().unwrap();
+                    let tensor = compiled.run().unwrap();
+                    assert_eq!(tensor.data().unwrap().to_vec(), expected, "axis {axis}");
+                }
+
+                let mut graph = Graph::empty();
+                assert!(common::matrix_2x3::<$dev>(&mut graph).flip(2).is_err());
+            }
+
+            #[test]
+            fn roll() {
+                for (shift, expected) in [
+                    (1, [4.0, 1.0, 2.0, 3.0]),
+                    (-1, [2.0, 3.0, 4.0, 1.0]),
+                    (5, [4.0, 1.0, 2.0, 3.0]),
+                    (0, [1.0, 2.0, 3.0, 4.0]),
+                ] {
+                    let mut graph = Graph::empty();
+                    let x = GraphTensor::<R1<4>, f32, $dev>::arange(&mut graph, 1.0, 5.0);
+                    let _r = x.roll(shift, 0).unwrap();
+                    let compiled: CompiledGraph<R1<4>, f32, $dev> = graph.compile().unwrap();
+                    let tensor = compiled.run().unwrap();
+                    assert_eq!(tensor.data().unwrap().to_vec(), expected, "shift {shift}");
+                }
+
+                // Rolling a transposed view reads it through its strides.
+                let mut graph = Graph::empty();
+                let x = common::matrix_2x3::<$dev>(&mut graph);
+                let _r = x.t().roll(-1, 0).unwrap();
+                let compiled: CompiledGraph<R2<3, 2>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![vec![1.0, 4.0], vec![2.0, 5.0], vec![0.0, 3.0]]
+                );
+
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<4>, f32, $dev>::arange(&mut graph, 1.0, 5.0);
+                let _r = x.roll(1, -1).unwrap();
+                let compiled: CompiledGraph<R1<4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), [4.0, 1.0, 2.0, 3.0]);
+
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R1<4>, f32, $dev>::fill(&mut graph, 1.0);
+                assert!(x.clone().roll(1, 1).is_err());
+                assert!(x.roll(1, -2).is_err());
+            }
+
+            #[test]
+            fn broadcast_to() {
+                let mut graph = Graph::empty();
+                let row = GraphTensor::<R1<4>, f32, $dev>::arange(&mut graph, 1.0, 5.0);
+                let row = GraphTensor::<R1<4>, f32, $dev>::stack::<1, R2<1, 4>>([row]).unwrap();
+                let _b = row.broadcast_to::<R2<3, 4>>().unwrap();
+                let compiled: CompiledGraph<R2<3, 4>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![vec![1.0, 2.0, 3.0, 4.0]; 3]
+                );
+
+                // A column of a transposed view repeats along the other axis.
+                let mut graph = Graph::empty();
+                let col = GraphTensor::<R1<3>, f32, $dev>::arange(&mut graph, 0.0, 3.0);
+                let col = GraphTensor::<R1<3>, f32, $dev>::stack::<1, R2<1, 3>>([col]).unwrap();
+                let _b = col.t().broadcast_to::<R2<3, 2>>().unwrap();
+                let compiled: CompiledGraph<R2<3, 2>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(
+                    tensor.data().unwrap().to_vec(),
+                    vec![vec![0.0, 0.0], vec![1.0, 1.0], vec![2.0, 2.0]]
+                );
+
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<2, 4>, f32, $dev>::fill(&mut graph, 1.0);
+                assert!(x.clone().broadcast_to::<R2<3, 4>>().is_err());
+                assert!(x.broadcast_to::<R3<2, 2, 4>>().is_err());
+            }
+
+            #[test]
+            fn diagonal() {
+                // [[0, 1, 2], [3, 4, 5], [6, 7, 8]]
+                let square = |graph: &mut Graph<f32>| {
+                    let rows = [0.0, 3.0, 6.0].map(|start| {
+                        GraphTensor::<R1<3>, f32, $dev>::arange(graph, start, start + 3.0)
+                    });
+                    GraphTensor::<R1<3>, f32, $dev>::stack::<3, R2<3, 3>>(rows).unwrap()
+                };
+                let mut graph = Graph::empty();
+                let _d = square(&mut graph).diagonal();
+                let compiled: CompiledGraph<R1<3>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), [0.0, 4.0, 8.0]);
+
+                for (offset, expected) in [(1, [1.0, 5.0]), (-1, [3.0, 7.0])] {
+                    let mut graph = Graph::empty();
+                    let _d = square(&mut graph).diagonal_offset::<R1<2>>(offset).unwrap();
+                    let compiled: CompiledGraph<R1<2>, f32, $dev> = graph.compile().unwrap();
+                    let tensor = compiled.run().unwrap();
+                    assert_eq!(tensor.data().unwrap().to_vec(), expected, "offset {offset}");
+                }
+
+                // A transposed view is read through its strides.
+                let mut graph = Graph::empty();
+                let _d = square(&mut graph).t().diagonal_offset::<R1<1>>(2).unwrap();
+                let compiled: CompiledGraph<R1<1>, f32, $dev> = graph.compile().unwrap();
+                let tensor = compiled.run().unwrap();
+                assert_eq!(tensor.data().unwrap().to_vec(), [6.0]);
+
+                let mut graph = Graph::empty();
+                assert!(square(&mut graph).diagonal_offset::<R1<3>>(1).is_err());
+                assert!(square(&mut graph).diagonal_offset::<R1<1>>(3).is_err());
+            }
+
+            #[test]
+            fn shape_dims() {
+                let mut graph = Graph::empty();
+                let x = GraphTensor::<R2<3, 4>, f32, $dev>::fill(&mut graph, 1.0);
+                assert_eq!(x.shape_dims(), vec![3, 4]);
+                assert_eq!(x.t().shape_dims(), vec![4, 3]);
+            }
+
+            #[test]
+            fn stack_wrong_output_shape() {
+                let mut graph = Graph::empty();
+                let a = GraphTensor::<R1<4>, f32, $dev>::fill(&mut graph, 1.0);
+                let b = GraphTensor::<R1<4>, f32, $dev>::fill(&mut graph, 2.0);
+                assert!(GraphTensor::<R1<4>, f32, $dev>::stack::<2, R2<4, 2>>([a, b]).is_err());
+            }
+        }
+    };
+}
+
+test_for_device_stack!(Cpu, cpu_tests_stack);
@@ -0,0 +1,329 @@
+use std::borrow::Cow;
+
+use constensor_core::{CompiledGraph, Cpu, Error, Graph, GraphTensor, Shape, Tensor, R1, R2, R3};
+
+mod common;
+
+#[test]
+fn rows() {
+    let mut graph = Graph::empty();
+    let r0 = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 0.0, 4.0);
+    let r1 = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 4.0, 8.0);
+    let r2 = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 8.0, 12.0);
+    let _x: GraphTensor<R2<3, 4>, f32, Cpu> = GraphTensor::stack([r0, r1, r2]).unwrap();
+    let compiled: CompiledGraph<R2<3, 4>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    let rows = tensor.rows().unwrap().collect::<Vec<_>>();
+    assert_eq!(
+        rows,
+        vec![
+            &[0.0, 1.0, 2.0, 3.0][..],
+            &[4.0, 5.0, 6.0, 7.0][..],
+            &[8.0, 9.0, 10.0, 11.0][..],
+        ],
+    );
+}
+
+#[test]
+fn rows_transposed() {
+    let mut graph = Graph::empty();
+    let _x = GraphTensor::<R2<3, 4>, f32, Cpu>::fill(&mut graph, 1.0);
+    let compiled: CompiledGraph<R2<3, 4>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert!(matches!(
+        tensor.t().rows().map(Iterator::count),
+        Err(Error::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn cpu_vector_data_is_borrowed() {
+    let mut graph = Graph::empty();
+    let _x = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 0.0, 4.0);
+    let compiled: CompiledGraph<R1<4>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    let (first, second) = (tensor.data().unwrap(), tensor.data().unwrap());
+    assert!(matches!(first, Cow::Borrowed(_)));
+    // Both reads point at the tensor's own buffer.
+    assert_eq!(first.as_ptr(), second.as_ptr());
+    assert_eq!(*first, vec![0.0, 1.0, 2.0, 3.0]);
+}
+
+#[test]
+fn bytes_round_trip() {
+    let mut graph = Graph::empty();
+    let _x = common::matrix_2x3::<Cpu>(&mut graph);
+    let compiled: CompiledGraph<R2<2, 3>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+
+    let bytes = tensor.as_bytes().unwrap();
+    assert_eq!(bytes.len(), 6 * 4);
+    assert_eq!(bytes[4..8], 1.0f32.to_le_bytes());
+    let back = Tensor::<R2<2, 3>, f32, Cpu>::from_bytes(&bytes).unwrap();
+    assert_eq!(back.data().unwrap(), tensor.data().unwrap());
+
+    // Views are written row-major.
+    let t = Tensor::<R2<3, 2>, f32, Cpu>::from_bytes(&tensor.t().as_bytes().unwrap()).unwrap();
+    assert_eq!(t.data().unwrap(), tensor.t().data().unwrap());
+
+    assert!(matches!(
+        Tensor::<R2<2, 3>, f32, Cpu>::from_bytes(&bytes[1..]),
+        Err(Error::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn to_device_cpu() {
+    let mut graph = Graph::empty();
+    let _x = common::matrix_2x3::<Cpu>(&mut graph);
+    let compiled: CompiledGraph<R2<2, 3>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    // A view comes out as a row-major copy.
+    let moved = tensor.t().to_device::<Cpu>().unwrap();
+    assert!(moved.is_contiguous());
+    assert_eq!(moved.data().unwrap(), tensor.t().data().unwrap());
+}
+
+#[test]
+fn flat_vec() {
+    let mut graph = Graph::empty();
+    let x = common::matrix_2x3::<Cpu>(&mut graph);
+    let _x: GraphTensor<R3<1, 2, 3>, f32, Cpu> = GraphTensor::stack([x]).unwrap();
+    let compiled: CompiledGraph<R3<1, 2, 3>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    let dims = tensor.dims();
+    let data = tensor.to_flat_vec().unwrap();
+    assert_eq!(dims, vec![1, 2, 3]);
+    // Element [0, 1, 2] in row-major order.
+    let (i, j, k) = (0, 1, 2);
+    assert_eq!(data[(i * dims[1] + j) * dims[2] + k], 5.0);
+}
+
+#[cfg(feature = "ndarray")]
+#[test]
+fn to_ndarray() {
+    let mut graph = Graph::empty();
+    let x = common::matrix_2x3::<Cpu>(&mut graph);
+    let _x: GraphTensor<R3<1, 2, 3>, f32, Cpu> = GraphTensor::stack([x]).unwrap();
+    let compiled: CompiledGraph<R3<1, 2, 3>, f32, Cpu> = graph.compile().unwrap();
+    let array = compiled.run().unwrap().to_ndarray().unwrap();
+    assert_eq!(array.shape(), [1, 2, 3]);
+    assert_eq!(array[[0, 1, 2]], 5.0);
+
+    // A transposed view is copied out in its own row-major order.
+    let mut graph = Graph::empty();
+    let _x = common::matrix_2x3::<Cpu>(&mut graph);
+    let compiled: CompiledGraph<R2<2, 3>, f32, Cpu> = graph.compile().unwrap();
+    let array = compiled.run().unwrap().t().to_ndarray().unwrap();
+    assert_eq!(array.shape(), [3, 2]);
+    assert_eq!(array[[2, 1]], 5.0);
+}
+
+#[test]
+fn flat_vec_matches_data() {
+    let mut graph = Graph::empty();
+    let _x = GraphTensor::<R2<3, 4>, f32, Cpu>::rand(&mut graph);
+    let compiled: CompiledGraph<R2<3, 4>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    let flat = tensor.to_flat_vec().unwrap();
+    assert_eq!(flat.len(), tensor.dims().iter().product::<usize>());
+    assert_eq!(flat[0], tensor.data().unwrap()[0][0]);
+    assert_eq!(flat, tensor.data().unwrap().concat());
+}
+
+#[test]
+fn flat_vec_transposed() {
+    let mut graph = Graph::empty();
+    let _x = common::matrix_2x3::<Cpu>(&mut graph);
+    let compiled: CompiledGraph<R2<2, 3>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap().t();
+    assert_eq!(tensor.dims(), vec![3, 2]);
+    assert_eq!(
+        tensor.to_flat_vec().unwrap(),
+        vec![0.0, 3.0, 1.0, 4.0, 2.0, 5.0]
+    );
+}
+
+#[test]
+fn contiguity() {
+    let mut graph = Graph::empty();
+    let _x = GraphTensor::<R2<2, 3>, f32, Cpu>::fill(&mut graph, 1.0);
+    let compiled: CompiledGraph<R2<2, 3>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert!(tensor.is_contiguous());
+    assert!(!tensor.t().is_contiguous());
+    assert!(tensor.t().t().is_contiguous());
+
+    // Transposing a dimension of size 1 does not change the layout.
+    let mut graph = Graph::empty();
+    let _x = GraphTensor::<R2<1, 3>, f32, Cpu>::fill(&mut graph, 1.0);
+    let compiled: CompiledGraph<R2<1, 3>, f32, Cpu> = graph.compile().unwrap();
+    assert!(compiled.run().unwrap().t().is_contiguous());
+}
+
+#[test]
+fn transposed_view_in_graph() {
+    let mut graph = Graph::empty();
+    let x = common::matrix_2x3::<Cpu>(&mut graph);
+    let _t = x.t();
+    let compiled: CompiledGraph<R2<3, 2>, f32, Cpu> = graph.clone().compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert!(!tensor.is_contiguous());
+    assert_eq!(
+        tensor.data().unwrap().to_vec(),
+        vec![vec![0.0, 3.0], vec![1.0, 4.0], vec![2.0, 5.0]]
+    );
+
+    // Elementwise ops read the view through its strides and produce a row-major result.
+    let ones = GraphTensor::<R2<3, 2>, f32, Cpu>::ones(&mut graph);
+    let _y = x.t().exp2() + ones;
+    let compiled: CompiledGraph<R2<3, 2>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert!(tensor.is_contiguous());
+    assert_eq!(
+        tensor.to_flat_vec().unwrap(),
+        vec![2.0, 9.0, 3.0, 17.0, 5.0, 33.0]
+    );
+}
+
+#[test]
+fn matmul_2d_transposed_view() {
+    let mut graph = Graph::empty();
+    // [[0, 1, 2], [3, 4, 5]] times its transpose
+    let x = common::matrix_2x3::<Cpu>(&mut graph);
+    let _c = x.clone().matmul(x.t());
+    let compiled: CompiledGraph<R2<2, 2>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert_eq!(
+        tensor.data().unwrap().to_vec(),
+        vec![vec![5.0, 14.0], vec![14.0, 50.0]]
+    );
+}
+
+#[test]
+fn contiguous_materializes_view() {
+    let mut graph = Graph::empty();
+    let x = common::matrix_2x3::<Cpu>(&mut graph);
+    assert!(x.t().flatten_to::<6>().is_err());
+    let _flat = x.t().contiguous().flatten_to::<6>().unwrap();
+    let compiled: CompiledGraph<R1<6>, f32, Cpu> = graph.clone().compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert_eq!(
+        tensor.to_flat_vec().unwrap(),
+        vec![0.0, 3.0, 1.0, 4.0, 2.0, 5.0]
+    );
+
+    let _c = x.t().contiguous();
+    let compiled: CompiledGraph<R2<3, 2>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert!(tensor.is_contiguous());
+    assert_eq!(
+        tensor.data().unwrap().to_vec(),
+        vec![vec![0.0, 3.0], vec![1.0, 4.0], vec![2.0, 5.0]]
+    );
+}
+
+fn filled(v: f32) -> Tensor<R1<3>, f32, Cpu> {
+    let mut graph = Graph::empty();
+    let _x = GraphTensor::<R1<3>, f32, Cpu>::fill(&mut graph, v);
+    let compiled: CompiledGraph<R1<3>, f32, Cpu> = graph.compile().unwrap();
+    compiled.run().unwrap()
+}
+
+#[test]
+fn allclose() {
+    let (a, b) = (filled(1.0), filled(1.0 + 1e-7));
+    assert!(a.allclose_default(&b).unwrap());
+    assert!(!a.allclose(&b, 0.0, 0.0, false).unwrap());
+    assert!(!a.allclose_default(&filled(1.1)).unwrap());
+
+    let nan = filled(f32::NAN);
+    assert!(!nan.allclose_default(&nan).unwrap());
+    assert!(nan.allclose(&nan, 1e-5, 1e-8, true).unwrap());
+    assert!(!nan.allclose(&a, 1e-5, 1e-8, true).unwrap());
+    let inf = filled(f32::INFINITY);
+    assert!(inf.allclose_default(&inf).unwrap());
+    assert!(!inf.allclose_default(&filled(f32::NEG_INFINITY)).unwrap());
+    assert!(!a.allclose_default(&inf).unwrap());
+}
+
+#[test]
+fn reshape() {
+    let mut graph = Graph::empty();
+    let r0 = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 0.0, 4.0);
+    let r1 = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 4.0, 8.0);
+    let r2 = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 8.0, 12.0);
+    let _x: GraphTensor<R2<3, 4>, f32, Cpu> = GraphTensor::stack([r0, r1, r2]).unwrap();
+    let compiled: CompiledGraph<R2<3, 4>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+
+    let flat = tensor.reshape::<R1<12>>().unwrap();
+    assert_eq!(
+        flat.data().unwrap().to_vec(),
+        (0..12).map(|x| x as f32).collect::<Vec<_>>()
+    );
+    let back = flat.reshape::<R2<3, 4>>().unwrap();
+    assert_eq!(back.data().unwrap(), tensor.data().unwrap());
+
+    match tensor.reshape::<R1<10>>() {
+        Err(Error::ShapeMismatch { expected, got }) => {
+            assert_eq!(expected, vec![10]);
+            assert_eq!(got, vec![3, 4]);
+        }
+        Err(e) => panic!("unexpected error {e}"),
+        Ok(_) => panic!("reshaped to a different element count"),
+    }
+    assert!(tensor.t().reshape::<R1<12>>().is_err());
+}
+
+#[test]
+fn item() {
+    let mut graph = Graph::empty();
+    let a = GraphTensor::<R1<4>, f32, Cpu>::arange(&mut graph, 0.0, 4.0);
+    let b = GraphTensor::<R1<4>, f32, Cpu>::fill(&mut graph, 2.0);
+    let _d = a.dot(b);
+    let compiled: CompiledGraph<R1<1>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert_eq!(tensor.item().unwrap(), 12.0);
+    assert_eq!(tensor.reshape::<R2<1, 1>>().unwrap().item().unwrap(), 12.0);
+
+    let mut graph = Graph::empty();
+    let _x = GraphTensor::<R1<2>, f32, Cpu>::ones(&mut graph);
+    let compiled: CompiledGraph<R1<2>, f32, Cpu> = graph.compile().unwrap();
+    assert!(compiled.run().unwrap().item().is_err());
+}
+
+#[test]
+fn display() {
+    let mut graph = Graph::empty();
+    let rows = [0.0, 2.0]
+        .map(|start| GraphTensor::<R1<2>, f32, Cpu>::arange(&mut graph, start, start + 2.0));
+    let _x: GraphTensor<R2<2, 2>, f32, Cpu> = GraphTensor::stack(rows).unwrap();
+    let compiled: CompiledGraph<R2<2, 2>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert_eq!(
+        tensor.to_string(),
+        "Tensor[2, 2] f32\n[[0.0, 1.0],\n [2.0, 3.0]]"
+    );
+    assert_eq!(format!("{tensor:?}"), tensor.to_string());
+}
+
+#[test]
+fn display_summarizes_large_tensors() {
+    let mut graph = Graph::empty();
+    let _x = GraphTensor::<R1<2000>, f32, Cpu>::arange(&mut graph, 0.0, 2000.0);
+    let compiled: CompiledGraph<R1<2000>, f32, Cpu> = graph.compile().unwrap();
+    let tensor = compiled.run().unwrap();
+    assert_eq!(
+        tensor.to_string(),
+        "Tensor[2000] f32\n[0.0, 1.0, 2.0, ..., 1997.0, 1998.0, 1999.0]"
+    );
+}
+
+#[test]
+fn shape_rank_and_element_count() {
+    const { assert!(R3::<1, 2, 3>::RANK == 3) };
+    const { assert!(R1::<5>::RANK == 1) };
+    assert_eq!(R3::<1, 2, 3>::shape(), vec![1, 2, 3]);
+    assert_eq!(R3::<1, 2, 3>::element_count(), 6);
+}